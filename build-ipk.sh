@@ -226,6 +226,7 @@ start_service() {
     procd_set_param respawn       # Tự động restart khi crash
     procd_set_param stdout 1      # Log stdout vào syslog
     procd_set_param stderr 1      # Log stderr vào syslog
+    procd_set_param term_timeout 5  # SIGTERM → chờ ugate tắt êm (xả dữ liệu, DISCONNECT) trước khi SIGKILL
     procd_close_instance
 }
 
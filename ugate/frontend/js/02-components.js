@@ -35,7 +35,7 @@ const ChBadge = {
   computed: {
     cls() {
       if (this.enabled === false) return 'ch-badge disabled';
-      if (this.state === 'connected') return 'ch-badge connected';
+      if (this.state === 'connected' || this.state === 'listening') return 'ch-badge connected';
       if (this.state === 'disabled') return 'ch-badge disabled';
       return 'ch-badge disconnected';
     },
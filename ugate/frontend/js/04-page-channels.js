@@ -41,9 +41,8 @@ const ConfigPage = {
             <span style="color:#e2e8f0;font-size:.85rem">{{ c.mqtt.tls ? 'Bật' : 'Tắt' }}</span>
           </label>
           <span class="lbl">Client ID</span>
-          <input type="text" readonly class="inp" style="opacity:.6;cursor:default"
-                 :value="mqttClientId" placeholder="Chưa kết nối"
-                 title="Tự sinh ngẫu nhiên mỗi lần kết nối">
+          <input type="text" v-model="c.mqtt.client_id" :placeholder="mqttClientId || 'Tự sinh theo tên + MAC'"
+                 title="Bỏ trống = <tên thiết bị>-<MAC>">
           <span class="lbl">Username</span>
           <input type="text" v-model="c.mqtt.username">
           <span class="lbl">Mật khẩu</span>
@@ -56,6 +55,28 @@ const ConfigPage = {
           <input type="text" v-model="c.mqtt.topic">
           <span class="lbl">Sub Topic</span>
           <input type="text" v-model="c.mqtt.sub_topic">
+          <span class="lbl">Status Topic</span>
+          <input type="text" v-model="c.mqtt.status_topic" placeholder="Bỏ trống = tắt">
+          <span class="lbl">Clean session</span>
+          <label class="chk">
+            <input type="checkbox" v-model="c.mqtt.clean_session">
+            <span class="chk-box"></span>
+            <span style="color:#e2e8f0;font-size:.85rem">{{ c.mqtt.clean_session ? 'Bật' : 'Tắt' }}</span>
+          </label>
+          <span class="lbl">Keep-alive (s)</span>
+          <input type="number" v-model.number="c.mqtt.keep_alive_secs">
+          <span class="lbl">Inflight</span>
+          <input type="number" v-model.number="c.mqtt.inflight">
+          <span class="lbl">Giao thức</span>
+          <select v-model="c.mqtt.protocol_version">
+            <option value="3.1.1">MQTT 3.1.1</option><option value="5">MQTT 5</option>
+          </select>
+          <template v-if="c.mqtt.protocol_version === '5'">
+            <span class="lbl">Message expiry (s)</span>
+            <input type="number" v-model.number="c.mqtt.message_expiry_secs" title="0 = không hết hạn">
+            <span class="lbl">User properties</span>
+            <input type="text" v-model="c.mqtt.user_properties" placeholder="key=value,key2=value2">
+          </template>
         </div>
       </div>
 
@@ -65,9 +86,81 @@ const ConfigPage = {
           <span class="lbl">Phương thức</span>
           <select v-model="c.http.method">
             <option value="post">POST</option><option value="get">GET</option>
+            <option value="put">PUT</option><option value="patch">PATCH</option>
           </select>
           <span class="lbl">URL</span>
           <input type="text" v-model="c.http.url">
+          <span class="lbl">Xác thực</span>
+          <select v-model="c.http.auth">
+            <option value="none">Không</option><option value="basic">Basic</option>
+            <option value="bearer">Bearer</option><option value="api_key">API key</option>
+          </select>
+          <template v-if="c.http.auth === 'basic'">
+            <span class="lbl">Username</span>
+            <input type="text" v-model="c.http.username">
+            <span class="lbl">Mật khẩu</span>
+            <input type="password" v-model="c.http.password">
+          </template>
+          <template v-if="c.http.auth === 'bearer' || c.http.auth === 'api_key'">
+            <span class="lbl">Token</span>
+            <input type="password" v-model="c.http.token">
+          </template>
+          <template v-if="c.http.auth === 'api_key'">
+            <span class="lbl">Header API key</span>
+            <input type="text" v-model="c.http.api_key_header">
+          </template>
+          <span class="lbl">Headers</span>
+          <input type="text" v-model="c.http.headers" placeholder="Tên=giá trị,Tên2=giá trị2">
+          <span class="lbl">Body template</span>
+          <input type="text" v-model="c.http.body_template" placeholder='Bỏ trống = {"data":..,"len":..}'>
+          <span class="lbl">Giữ thứ tự</span>
+          <label class="chk">
+            <input type="checkbox" v-model="c.http.ordered">
+            <span class="chk-box"></span>
+            <span style="color:#e2e8f0;font-size:.85rem">{{ c.http.ordered ? 'Tuần tự' : 'Song song' }}</span>
+          </label>
+          <span class="lbl">Song song tối đa</span>
+          <input type="number" v-model.number="c.http.max_concurrent" :disabled="c.http.ordered">
+          <span class="lbl">Batch (message)</span>
+          <input type="number" v-model.number="c.http.batch_max_items" title="1 = không gộp">
+          <span class="lbl">Batch định dạng</span>
+          <select v-model="c.http.batch_format">
+            <option value="json">JSON array</option><option value="ndjson">NDJSON</option>
+          </select>
+          <span class="lbl">Batch tối đa (bytes)</span>
+          <input type="number" v-model.number="c.http.batch_max_bytes">
+          <span class="lbl">Batch gom (ms)</span>
+          <input type="number" v-model.number="c.http.batch_max_ms">
+          <span class="lbl">Gzip</span>
+          <label class="chk">
+            <input type="checkbox" v-model="c.http.gzip">
+            <span class="chk-box"></span>
+            <span style="color:#e2e8f0;font-size:.85rem">{{ c.http.gzip ? 'Bật' : 'Tắt' }}</span>
+          </label>
+          <span class="lbl">Telemetry</span>
+          <label class="chk">
+            <input type="checkbox" v-model="c.http.telemetry">
+            <span class="chk-box"></span>
+            <span style="color:#e2e8f0;font-size:.85rem">{{ c.http.telemetry ? 'Bật' : 'Tắt' }}</span>
+          </label>
+        </div>
+        <div class="cf" style="grid-template-columns:auto 1fr auto 2fr;margin-top:8px">
+          <span class="lbl">Sign secret</span>
+          <input type="password" v-model="c.http.sign_secret" placeholder="Bỏ trống = không ký">
+          <span class="lbl">Chỉ nhận lệnh có chữ ký</span>
+          <label class="chk">
+            <input type="checkbox" v-model="c.http.verify_response">
+            <span class="chk-box"></span>
+            <span style="color:#e2e8f0;font-size:.85rem">{{ c.http.verify_response ? 'Bật' : 'Tắt' }}</span>
+          </label>
+          <span class="lbl">Poll URL</span>
+          <input type="text" v-model="c.http.poll_url" placeholder="Bỏ trống = tắt poll lệnh">
+          <span class="lbl">Poll ack URL</span>
+          <input type="text" v-model="c.http.poll_ack_url" placeholder="Bỏ trống = Poll URL">
+          <span class="lbl">Poll chu kỳ (s)</span>
+          <input type="number" v-model.number="c.http.poll_interval_secs" title="0 = long-poll liên tục">
+          <span class="lbl">Poll timeout (s)</span>
+          <input type="number" v-model.number="c.http.poll_timeout_secs">
         </div>
       </div>
 
@@ -0,0 +1,118 @@
+//! Nút reset vật lý trên vỏ thiết bị (GPIO input)
+//! Nhấn ngắn: restart service | giữ ≥5s: bật WiFi AP | giữ ≥10s: factory reset
+//! Hành động thực hiện khi thả nút, heartbeat LED báo mức giữ hiện tại
+
+use crate::config::{AppState, ButtonConfig};
+use crate::gpio::GpioLine;
+use crate::web_api::status::SharedStats;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Thời gian nhấn tối thiểu để tính là 1 lần nhấn (lọc nhiễu/dội phím)
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Mức giữ nút — quyết định hành động khi thả
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum HoldStage {
+    Short = 0,
+    WifiAp = 1,
+    FactoryReset = 2,
+}
+
+impl HoldStage {
+    /// Đọc từ SharedStats::button_stage (0 = không nhấn, 1..=3 = stage + 1)
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(Self::Short),
+            2 => Some(Self::WifiAp),
+            3 => Some(Self::FactoryReset),
+            _ => None,
+        }
+    }
+
+    fn for_duration(held: Duration, config: &ButtonConfig) -> Self {
+        if held >= Duration::from_secs(config.reset_hold_secs as u64) {
+            Self::FactoryReset
+        } else if held >= Duration::from_secs(config.ap_hold_secs as u64) {
+            Self::WifiAp
+        } else {
+            Self::Short
+        }
+    }
+}
+
+/// Task theo dõi nút: poll mỗi 50ms, cập nhật stage cho LED, thực hiện hành động khi thả
+pub async fn run(config: ButtonConfig, state: Arc<AppState>, stats: Arc<SharedStats>) {
+    let pin = match config.pin {
+        Some(p) => p,
+        None => return,
+    };
+    let line = match GpioLine::request_input("gpiochip0", pin as u32, config.active_low) {
+        Ok(l) => {
+            log::info!("[Button] Pin {} sẵn sàng (active_low={})", pin, config.active_low);
+            l
+        }
+        Err(e) => {
+            log::warn!("[Button] Không thể mở pin {}: {} (bỏ qua)", pin, e);
+            return;
+        }
+    };
+
+    let mut pressed_since: Option<Instant> = None;
+    let mut poll = tokio::time::interval(Duration::from_millis(50));
+
+    loop {
+        poll.tick().await;
+        let pressed = line.get_value().unwrap_or(false);
+
+        match (pressed, pressed_since) {
+            (true, None) => pressed_since = Some(Instant::now()),
+            (true, Some(since)) => {
+                let held = since.elapsed();
+                if held >= DEBOUNCE {
+                    let stage = HoldStage::for_duration(held, &config);
+                    stats.button_stage.store(stage as u8 + 1, Ordering::Relaxed);
+                }
+            }
+            (false, Some(since)) => {
+                pressed_since = None;
+                stats.button_stage.store(0, Ordering::Relaxed);
+                let held = since.elapsed();
+                if held >= DEBOUNCE {
+                    let stage = HoldStage::for_duration(held, &config);
+                    log::info!("[Button] Thả sau {:.1}s → {:?}", held.as_secs_f32(), stage);
+                    // Restart/factory reset chạy uci + ghi file: không chạy trên runtime current_thread
+                    let state = state.clone();
+                    let _ = tokio::task::spawn_blocking(move || perform(stage, &state)).await;
+                }
+            }
+            (false, None) => {}
+        }
+    }
+}
+
+fn perform(stage: HoldStage, state: &Arc<AppState>) {
+    match stage {
+        HoldStage::Short => crate::web_api::maintenance::restart_service(),
+        HoldStage::WifiAp => crate::web_api::wifi::enable_ap_fallback(),
+        HoldStage::FactoryReset => crate::web_api::maintenance::factory_reset(state),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hold_stage_thresholds() {
+        let cfg = ButtonConfig { pin: Some(38), active_low: true, ap_hold_secs: 5, reset_hold_secs: 10 };
+        assert_eq!(HoldStage::for_duration(Duration::from_millis(300), &cfg), HoldStage::Short);
+        assert_eq!(HoldStage::for_duration(Duration::from_secs(5), &cfg), HoldStage::WifiAp);
+        assert_eq!(HoldStage::for_duration(Duration::from_millis(9999), &cfg), HoldStage::WifiAp);
+        assert_eq!(HoldStage::for_duration(Duration::from_secs(12), &cfg), HoldStage::FactoryReset);
+        assert_eq!(HoldStage::from_u8(HoldStage::WifiAp as u8 + 1), Some(HoldStage::WifiAp));
+        assert_eq!(HoldStage::from_u8(0), None);
+    }
+}
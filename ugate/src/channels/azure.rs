@@ -0,0 +1,157 @@
+//! Azure IoT Hub (mqtt.preset = azure): kết nối MQTT 3.1.1 theo device connection string
+//! `HostName=<hub>.azure-devices.net;DeviceId=<id>;SharedAccessKey=<base64>`
+//! - Broker = HostName:8883 (TLS), client ID = DeviceId, username `<host>/<id>/?api-version=..`
+//! - Password = SAS token HMAC-SHA256 tự sinh, hết hạn sau mqtt.azure_sas_ttl_secs → kết nối
+//!   lại với token mới trước khi hết hạn (hub ngắt kết nối khi token hết hạn)
+//! - Dữ liệu UART + telemetry → `devices/<id>/messages/events/` (hub không nhận topic khác)
+//! - Cloud-to-device `devices/<id>/messages/devicebound/#` → lệnh như sub_topic
+//! - Direct method `$iothub/methods/POST/<method>/?$rid=<rid>` → Command, phản hồi
+//!   `$iothub/methods/res/<status>/?$rid=<rid>`
+
+use crate::commands::{parse_json_command, Command};
+use crate::config::{MqttConfig, MqttVersion};
+use crate::web_api::url_encode;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::hmac;
+
+const API_VERSION: &str = "2021-04-12";
+const METHOD_PREFIX: &str = "$iothub/methods/POST/";
+
+pub struct Hub {
+    host: String,
+    device_id: String,
+    key: Vec<u8>,
+    ttl_secs: u64,
+}
+
+impl Hub {
+    pub fn parse(connection_string: &str, ttl_secs: u32) -> Result<Self, String> {
+        let field = |name: &str| {
+            connection_string
+                .split(';')
+                .filter_map(|kv| kv.trim().split_once('='))
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.to_string())
+                .filter(|v| !v.is_empty())
+                .ok_or_else(|| format!("Connection string thiếu {}", name))
+        };
+        let key = BASE64.decode(field("SharedAccessKey")?)
+            .map_err(|e| format!("SharedAccessKey không phải base64: {}", e))?;
+        Ok(Self {
+            host: field("HostName")?,
+            device_id: field("DeviceId")?,
+            key,
+            ttl_secs: ttl_secs.max(60) as u64,
+        })
+    }
+
+    /// SAS token hết hạn lúc `now + ttl`: (token, thời điểm hết hạn unix)
+    pub fn sas_token(&self, now: u64) -> (String, u64) {
+        let expiry = now + self.ttl_secs;
+        let resource = url_encode(&format!("{}/devices/{}", self.host, self.device_id));
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &self.key);
+        let signature = hmac::sign(&key, format!("{}\n{}", resource, expiry).as_bytes());
+        let token = format!(
+            "SharedAccessSignature sr={}&sig={}&se={}",
+            resource, url_encode(&BASE64.encode(signature.as_ref())), expiry
+        );
+        (token, expiry)
+    }
+
+    /// Làm mới token khi đã dùng 80% thời hạn
+    pub fn refresh_after(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.ttl_secs * 4 / 5)
+    }
+
+    /// Ghi đè cấu hình kết nối theo hub. Hub chỉ nhận publish lên topic events/$iothub
+    /// nên tắt presence, Home Assistant, shadow, Sparkplug (publish topic khác bị ngắt kết nối)
+    pub fn apply(&self, mqtt: &mut MqttConfig, token: String) {
+        mqtt.broker = self.host.clone();
+        mqtt.port = 8883;
+        mqtt.tls = true;
+        mqtt.version = MqttVersion::V311;
+        mqtt.client_id = self.device_id.clone();
+        mqtt.username = format!("{}/{}/?api-version={}", self.host, self.device_id, API_VERSION);
+        mqtt.password = token;
+        mqtt.topic = self.events_topic();
+        mqtt.sub_topic.clear();
+        mqtt.status_topic.clear();
+        mqtt.ha_discovery = false;
+        mqtt.shadow_topic.clear();
+        mqtt.sparkplug = false;
+        mqtt.qos = mqtt.qos.min(1);
+    }
+
+    pub fn events_topic(&self) -> String {
+        format!("devices/{}/messages/events/", self.device_id)
+    }
+
+    pub fn subscriptions(&self) -> Vec<String> {
+        vec![format!("devices/{}/messages/devicebound/#", self.device_id), format!("{}#", METHOD_PREFIX)]
+    }
+}
+
+/// `$iothub/methods/POST/<method>/?$rid=<rid>` → (method, rid)
+pub fn method_request(topic: &str) -> Option<(&str, &str)> {
+    let (method, query) = topic.strip_prefix(METHOD_PREFIX)?.split_once("/?")?;
+    let rid = query.split('&').find_map(|kv| kv.strip_prefix("$rid="))?;
+    Some((method, rid))
+}
+
+/// Direct method → Command. Tên method: gpio/setGpio, pwm, uart_tx/uartTx; payload là
+/// tham số của lệnh JSON tương ứng (`{"pin":1,"state":"on"}`), uart_tx nhận cả chuỗi JSON
+pub fn method_command(method: &str, payload: &str) -> Option<Command> {
+    let cmd = match method {
+        "gpio" | "setGpio" => "gpio",
+        "pwm" | "setPwm" => "pwm",
+        "uart_tx" | "uartTx" => "uart_tx",
+        _ => return None,
+    };
+    let payload = payload.trim();
+    let json = match payload.strip_prefix('{') {
+        Some(rest) => format!(r#"{{"cmd":"{}",{}"#, cmd, rest),
+        None => format!(r#"{{"cmd":"{}","data":{}}}"#, cmd, payload),
+    };
+    parse_json_command(&json)
+}
+
+pub fn method_response_topic(status: u16, rid: &str) -> String {
+    format!("$iothub/methods/res/{}/?$rid={}", status, rid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::GpioState;
+
+    #[test]
+    fn test_sas_token() {
+        let cs = "HostName=hub1.azure-devices.net;DeviceId=dev1;SharedAccessKey=MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+        let hub = Hub::parse(cs, 3600).unwrap();
+        let (token, expiry) = hub.sas_token(1_700_000_000);
+        assert_eq!(expiry, 1_700_003_600);
+        assert_eq!(
+            token,
+            "SharedAccessSignature sr=hub1.azure-devices.net%2Fdevices%2Fdev1&sig=pUg8EnrUuaPvYfz2XRFp80acidQjyasHgXMeugGQx3I%3D&se=1700003600"
+        );
+        let mut mqtt = MqttConfig::default();
+        hub.apply(&mut mqtt, token);
+        assert_eq!(mqtt.username, "hub1.azure-devices.net/dev1/?api-version=2021-04-12");
+        assert_eq!(mqtt.topic, "devices/dev1/messages/events/");
+        assert!(Hub::parse("HostName=h;DeviceId=d", 3600).is_err());
+    }
+
+    #[test]
+    fn test_direct_methods() {
+        assert_eq!(method_request("$iothub/methods/POST/setGpio/?$rid=1f"), Some(("setGpio", "1f")));
+        assert_eq!(method_request("devices/dev1/messages/devicebound/x"), None);
+        assert!(matches!(
+            method_command("setGpio", r#"{"pin":2,"state":"on"}"#),
+            Some(Command::Gpio { pin: 2, state: GpioState::On })
+        ));
+        assert!(matches!(method_command("uartTx", r#""hello""#), Some(Command::UartTx { ref data }) if data == "hello"));
+        assert!(method_command("reboot", "{}").is_none());
+        assert_eq!(method_response_topic(200, "1f"), "$iothub/methods/res/200/?$rid=1f");
+    }
+}
@@ -1,165 +1,505 @@
-//! Bộ đệm offline: lưu dữ liệu khi mất kết nối
-//! RAM queue → tràn thì ghi ra disk (/tmp/ugate_buffer/)
-//! Khi kết nối lại, đọc từ disk trước rồi mới tới RAM
-#![allow(dead_code, unused_imports)]
+//! Bộ đệm offline: lưu dữ liệu khi mất kết nối (FIFO, giữ đúng thứ tự)
+//! RAM queue → đầy thì ghi tiếp ra disk dạng log append-only nhiều segment:
+//!   seg-<seq>.log: record = [len u32][crc32 u32][ts u64][payload], CRC tính trên ts + payload
+//!   cursor: "<seq> <offset>" — vị trí đọc, lưu định kỳ (crash → gửi lại vài message, không mất)
+//! Segment đọc hết thì xoá; vượt dung lượng thì xoá segment cũ nhất (bỏ dữ liệu cũ nhất)
+//! Ghi gom theo lô (4KB hoặc 2s) để giảm mòn flash khi đặt trên USB/overlay
+//! Record hỏng (mất điện giữa chừng) → cắt bỏ từ record hỏng tới cuối segment
 
 use std::collections::VecDeque;
-use std::io::{BufRead, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// 1 message kèm thời điểm nhận (unix giây)
+pub type Stamped = (u64, Vec<u8>);
+
+/// Kích thước tối đa 1 segment (nhỏ hơn nếu giới hạn dung lượng nhỏ)
+const SEGMENT_BYTES: u64 = 64 * 1024;
+/// Gom ghi tới khi đủ lô hoặc quá thời gian
+const WRITE_BATCH: usize = 4096;
+const WRITE_FLUSH: Duration = Duration::from_secs(2);
+/// Lưu cursor sau mỗi N record đọc ra
+const CURSOR_EVERY: u32 = 32;
+/// len(4) + crc(4) + ts(8)
+const HEADER: usize = 16;
+/// Record lớn hơn thì coi như header hỏng
+const MAX_RECORD: u32 = 1 << 20;
+
+struct Segment {
+    seq: u64,
+    bytes: u64,
+    records: usize,
+}
 
 pub struct OfflineBuffer {
-    ram_queue: VecDeque<Vec<u8>>,
+    ram_queue: VecDeque<Stamped>,
     ram_limit: usize,
     disk_path: PathBuf,
+    /// Segment trên disk, cũ nhất ở đầu (đang đọc), mới nhất ở cuối (đang ghi)
+    segments: VecDeque<Segment>,
+    /// Vị trí đọc + số record đã đọc trong segment đầu
+    read_offset: u64,
+    read_records: usize,
+    unsaved_reads: u32,
+    /// Record chờ ghi xuống segment cuối (đã tính vào segment.bytes/records)
+    pending: Vec<u8>,
+    pending_since: Option<Instant>,
+    /// Giới hạn tổng dung lượng disk (0 = không giới hạn), vượt thì bỏ segment cũ nhất
+    max_disk_bytes: u64,
+    /// Message cũ hơn N giây bị bỏ khi lấy ra (0 = không giới hạn)
+    max_age_secs: u64,
+    dropped: u32,
 }
 
 impl OfflineBuffer {
     pub fn new(ram_limit: usize, disk_path: PathBuf) -> Self {
         // Tạo thư mục buffer nếu chưa có
         let _ = std::fs::create_dir_all(&disk_path);
-        Self {
+        let mut buf = Self {
             ram_queue: VecDeque::new(),
             ram_limit,
             disk_path,
-        }
+            segments: VecDeque::new(),
+            read_offset: 0,
+            read_records: 0,
+            unsaved_reads: 0,
+            pending: Vec::new(),
+            pending_since: None,
+            max_disk_bytes: 0,
+            max_age_secs: 0,
+            dropped: 0,
+        };
+        // Dữ liệu còn lại từ lần chạy trước
+        buf.open_segments();
+        buf
+    }
+
+    pub fn with_limits(mut self, max_disk_bytes: u64, max_age_secs: u64) -> Self {
+        self.max_disk_bytes = max_disk_bytes;
+        self.max_age_secs = max_age_secs;
+        self.enforce_cap();
+        self
     }
 
-    /// Thêm message vào buffer. Nếu RAM đầy → ghi ra disk
+    /// Thêm message vào buffer. RAM đầy (hoặc disk đang còn dữ liệu) → ghi ra disk
     pub fn push(&mut self, msg: Vec<u8>) {
-        if self.ram_queue.len() < self.ram_limit {
-            self.ram_queue.push_back(msg);
+        self.push_stamped((now_secs(), msg));
+    }
+
+    fn push_stamped(&mut self, entry: Stamped) {
+        if self.disk_count() == 0 && self.ram_queue.len() < self.ram_limit {
+            self.ram_queue.push_back(entry);
         } else {
-            self.write_to_disk(&msg);
+            self.write_to_disk(&entry);
         }
     }
 
-    /// Lấy message ra: ưu tiên disk trước (FIFO), sau đó RAM
+    /// Trả message chưa gửi được về đầu hàng đợi (giữ thứ tự + thời điểm nhận gốc)
+    pub fn requeue(&mut self, entries: Vec<Stamped>) {
+        for entry in entries.into_iter().rev() {
+            self.ram_queue.push_front(entry);
+        }
+    }
+
+    /// Lấy message ra theo thứ tự FIFO
+    #[allow(dead_code)]
     pub fn pop(&mut self) -> Option<Vec<u8>> {
-        // Đọc từ disk trước (dữ liệu cũ hơn)
-        if let Some(data) = self.read_one_from_disk() {
-            return Some(data);
+        self.pop_stamped().map(|(_, data)| data)
+    }
+
+    /// Như `pop` nhưng kèm thời điểm nhận, bỏ qua message quá hạn
+    pub fn pop_stamped(&mut self) -> Option<Stamped> {
+        loop {
+            let entry = match self.ram_queue.pop_front() {
+                Some(e) => e,
+                None => self.read_one_from_disk()?,
+            };
+            if self.max_age_secs > 0 && now_secs().saturating_sub(entry.0) > self.max_age_secs {
+                self.dropped += 1;
+                continue;
+            }
+            return Some(entry);
         }
-        self.ram_queue.pop_front()
     }
 
+    /// Số message trong RAM
+    #[allow(dead_code)]
     pub fn len(&self) -> usize {
         self.ram_queue.len()
     }
 
+    /// Tổng số message (RAM + disk)
+    pub fn total(&self) -> usize {
+        self.ram_queue.len() + self.disk_count()
+    }
+
+    /// Số message đã bỏ do quá hạn hoặc vượt dung lượng
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.ram_queue.is_empty() && !self.disk_file().exists()
+        self.ram_queue.is_empty() && self.disk_count() == 0
+    }
+
+    fn disk_count(&self) -> usize {
+        self.segments.iter().map(|s| s.records).sum::<usize>() - self.read_records
     }
 
-    /// Ghi tất cả RAM queue ra disk (khi shutdown)
+    /// Ghi tất cả RAM queue ra disk (khi shutdown). RAM cũ hơn disk nên phải nằm trước:
+    /// viết lại segment đầu = RAM + phần chưa đọc của segment đó
     pub fn flush_to_disk(&mut self) {
-        while let Some(msg) = self.ram_queue.pop_front() {
-            self.write_to_disk(&msg);
+        self.flush_pending();
+        if self.ram_queue.is_empty() {
+            self.save_cursor();
+            return;
+        }
+        let mut data = Vec::new();
+        let mut records = 0;
+        for entry in self.ram_queue.drain(..) {
+            data.extend_from_slice(&encode(&entry));
+            records += 1;
         }
+        match self.segments.front_mut() {
+            Some(front) => {
+                let path = self.disk_path.join(segment_name(front.seq));
+                if let Ok(old) = std::fs::read(&path) {
+                    data.extend_from_slice(old.get(self.read_offset as usize..).unwrap_or(&[]));
+                }
+                records += front.records - self.read_records;
+                let tmp = path.with_extension("tmp");
+                if std::fs::write(&tmp, &data).is_ok() {
+                    let _ = std::fs::rename(&tmp, &path);
+                }
+                front.bytes = data.len() as u64;
+                front.records = records;
+            }
+            None => {
+                if std::fs::write(self.disk_path.join(segment_name(1)), &data).is_ok() {
+                    self.segments.push_back(Segment { seq: 1, bytes: data.len() as u64, records });
+                }
+            }
+        }
+        self.read_offset = 0;
+        self.read_records = 0;
+        self.save_cursor();
     }
 
     /// Nạp dữ liệu từ disk vào RAM khi khởi động
+    #[allow(dead_code)]
     pub fn load_from_disk(&mut self) -> usize {
-        let path = self.disk_file();
-        if !path.exists() {
-            return 0;
-        }
-        let content = match std::fs::read_to_string(&path) {
-            Ok(c) => c,
-            Err(_) => return 0,
-        };
         let mut count = 0;
-        let mut remaining = Vec::new();
-        for line in content.lines() {
-            if line.is_empty() { continue; }
-            if let Ok(data) = hex_decode(line) {
-                if self.ram_queue.len() < self.ram_limit {
-                    self.ram_queue.push_back(data);
+        while self.ram_queue.len() < self.ram_limit {
+            match self.read_one_from_disk() {
+                Some(entry) => {
+                    self.ram_queue.push_back(entry);
                     count += 1;
-                } else {
-                    remaining.push(line.to_string());
                 }
+                None => break,
             }
         }
-        // Xoá file hoặc ghi lại phần chưa đọc
-        if remaining.is_empty() {
-            let _ = std::fs::remove_file(&path);
+        self.save_cursor();
+        count
+    }
+
+    /// Có record đang gom lô chưa ghi xuống disk
+    pub fn has_pending_writes(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Ghi lô đang chờ nếu đã quá thời gian gom (gọi định kỳ khi không có dữ liệu mới)
+    pub fn flush_due(&mut self) {
+        if self.pending_since.is_some_and(|t| t.elapsed() >= WRITE_FLUSH) {
+            self.sync();
+        }
+    }
+
+    /// Ghi lô đang chờ + lưu cursor (gọi định kỳ hoặc trước khi thoát)
+    pub fn sync(&mut self) {
+        self.flush_pending();
+        self.save_cursor();
+    }
+
+    // --- Segment log ---
+
+    /// Quét thư mục: đọc cursor, xoá segment đã đọc xong, kiểm tra + sửa record hỏng
+    fn open_segments(&mut self) {
+        let mut seqs: Vec<u64> = std::fs::read_dir(&self.disk_path)
+            .map(|rd| rd.filter_map(|e| parse_segment_name(&e.ok()?.file_name().to_string_lossy())).collect())
+            .unwrap_or_default();
+        seqs.sort_unstable();
+
+        let (cursor_seq, cursor_offset) = std::fs::read_to_string(self.cursor_file())
+            .ok()
+            .and_then(|c| {
+                let (seq, off) = c.trim().split_once(' ')?;
+                Some((seq.parse().ok()?, off.parse().ok()?))
+            })
+            .unwrap_or((0u64, 0u64));
+
+        for seq in seqs {
+            let path = self.disk_path.join(segment_name(seq));
+            if seq < cursor_seq {
+                let _ = std::fs::remove_file(&path);
+                continue;
+            }
+            let data = std::fs::read(&path).unwrap_or_default();
+            let ends = scan_records(&data);
+            let valid = ends.last().copied().unwrap_or(0);
+            if (valid as usize) < data.len() {
+                log::warn!("[Buffer] {} hỏng từ byte {}, cắt bỏ {} bytes", path.display(), valid, data.len() - valid as usize);
+                if let Ok(f) = std::fs::OpenOptions::new().write(true).open(&path) {
+                    let _ = f.set_len(valid);
+                }
+            }
+            if ends.is_empty() {
+                let _ = std::fs::remove_file(&path);
+                continue;
+            }
+            if self.segments.is_empty() && seq == cursor_seq {
+                // Cursor chỉ tin được nếu rơi đúng ranh giới record
+                self.read_records = ends.iter().take_while(|&&e| e <= cursor_offset).count();
+                self.read_offset = if self.read_records > 0 { ends[self.read_records - 1] } else { 0 };
+            }
+            self.segments.push_back(Segment { seq, bytes: valid, records: ends.len() });
+        }
+    }
+
+    fn cursor_file(&self) -> PathBuf {
+        self.disk_path.join("cursor")
+    }
+
+    fn segment_limit(&self) -> u64 {
+        if self.max_disk_bytes > 0 {
+            // Luôn có vài segment để xoá segment cũ nhất không mất quá nhiều dữ liệu
+            SEGMENT_BYTES.min((self.max_disk_bytes / 4).max(1024))
         } else {
-            let _ = std::fs::write(&path, remaining.join("\n") + "\n");
+            SEGMENT_BYTES
         }
-        count
     }
 
-    fn disk_file(&self) -> PathBuf {
-        self.disk_path.join("buffer.hex")
+    /// Thêm 1 record vào segment cuối (gom lô), xoay segment khi đầy
+    fn write_to_disk(&mut self, entry: &Stamped) {
+        let rec = encode(entry);
+        let limit = self.segment_limit();
+        let rotate = match self.segments.back() {
+            Some(last) => last.bytes > 0 && last.bytes + rec.len() as u64 > limit,
+            None => true,
+        };
+        if rotate {
+            self.flush_pending();
+            let seq = self.segments.back().map(|s| s.seq + 1).unwrap_or(1);
+            self.segments.push_back(Segment { seq, bytes: 0, records: 0 });
+        }
+        if let Some(last) = self.segments.back_mut() {
+            last.bytes += rec.len() as u64;
+            last.records += 1;
+        }
+        self.pending.extend_from_slice(&rec);
+        let since = *self.pending_since.get_or_insert_with(Instant::now);
+        self.enforce_cap();
+        if self.pending.len() >= WRITE_BATCH || since.elapsed() >= WRITE_FLUSH {
+            self.flush_pending();
+        }
     }
 
-    /// Ghi 1 message ra disk dạng hex (1 dòng = 1 message)
-    fn write_to_disk(&self, msg: &[u8]) {
-        let path = self.disk_file();
-        if let Ok(mut file) = std::fs::OpenOptions::new()
+    fn flush_pending(&mut self) {
+        self.pending_since = None;
+        if self.pending.is_empty() {
+            return;
+        }
+        let seq = match self.segments.back() {
+            Some(s) => s.seq,
+            None => return,
+        };
+        let path = self.disk_path.join(segment_name(seq));
+        let written = std::fs::OpenOptions::new()
             .create(true)
             .append(true)
             .open(&path)
-        {
-            let hex = hex_encode(msg);
-            let _ = writeln!(file, "{}", hex);
+            .and_then(|mut f| f.write_all(&self.pending));
+        if let Err(e) = written {
+            log::error!("[Buffer] Ghi {} lỗi: {}", path.display(), e);
         }
+        self.pending.clear();
     }
 
-    /// Đọc 1 dòng đầu tiên từ disk, ghi lại phần còn lại
-    fn read_one_from_disk(&mut self) -> Option<Vec<u8>> {
-        let path = self.disk_file();
-        if !path.exists() {
-            return None;
+    /// Vượt dung lượng → xoá segment cũ nhất (không xoá segment đang ghi)
+    fn enforce_cap(&mut self) {
+        if self.max_disk_bytes == 0 {
+            return;
+        }
+        while self.segments.len() > 1 && self.segments.iter().map(|s| s.bytes).sum::<u64>() > self.max_disk_bytes {
+            let front = self.segments.pop_front().unwrap();
+            let lost = front.records - self.read_records;
+            self.dropped += lost as u32;
+            log::warn!("[Buffer] Vượt {} bytes, bỏ {} message cũ nhất", self.max_disk_bytes, lost);
+            let _ = std::fs::remove_file(self.disk_path.join(segment_name(front.seq)));
+            self.read_offset = 0;
+            self.read_records = 0;
+            self.save_cursor();
         }
+    }
 
-        let content = std::fs::read_to_string(&path).ok()?;
-        let mut lines = content.lines();
-        let first = lines.next()?;
-        let data = hex_decode(first).ok()?;
+    /// Đọc 1 record tại cursor. Segment đọc hết thì xoá, record hỏng thì bỏ phần còn lại của segment
+    fn read_one_from_disk(&mut self) -> Option<Stamped> {
+        loop {
+            let front = self.segments.front()?;
+            if self.read_records >= front.records {
+                let seq = front.seq;
+                self.segments.pop_front();
+                let _ = std::fs::remove_file(self.disk_path.join(segment_name(seq)));
+                self.read_offset = 0;
+                self.read_records = 0;
+                self.save_cursor();
+                continue;
+            }
+            let (seq, records) = (front.seq, front.records);
+            if self.segments.len() == 1 {
+                // Đọc tới segment đang ghi → ghi lô đang chờ trước
+                self.flush_pending();
+            }
+            match read_record(&self.disk_path.join(segment_name(seq)), self.read_offset) {
+                Some((entry, len)) => {
+                    self.read_offset += len;
+                    self.read_records += 1;
+                    self.unsaved_reads += 1;
+                    if self.unsaved_reads >= CURSOR_EVERY {
+                        self.save_cursor();
+                    }
+                    return Some(entry);
+                }
+                None => {
+                    let lost = records - self.read_records;
+                    log::warn!("[Buffer] Segment {} hỏng tại byte {}, bỏ {} message", seq, self.read_offset, lost);
+                    self.dropped += lost as u32;
+                    self.read_records = records;
+                }
+            }
+        }
+    }
 
-        // Ghi lại phần còn lại
-        let rest: Vec<&str> = lines.collect();
-        if rest.is_empty() {
-            let _ = std::fs::remove_file(&path);
-        } else {
-            let _ = std::fs::write(&path, rest.join("\n") + "\n");
+    /// Lưu vị trí đọc (ghi file tạm rồi rename để không bao giờ đọc được cursor dở dang)
+    fn save_cursor(&mut self) {
+        self.unsaved_reads = 0;
+        let path = self.cursor_file();
+        match self.segments.front() {
+            Some(front) => {
+                let tmp = path.with_extension("tmp");
+                if std::fs::write(&tmp, format!("{} {}", front.seq, self.read_offset)).is_ok() {
+                    let _ = std::fs::rename(&tmp, &path);
+                }
+            }
+            None => {
+                let _ = std::fs::remove_file(&path);
+            }
         }
+    }
+}
+
+impl Drop for OfflineBuffer {
+    fn drop(&mut self) {
+        self.sync();
+    }
+}
+
+fn segment_name(seq: u64) -> String {
+    format!("seg-{:010}.log", seq)
+}
+
+fn parse_segment_name(name: &str) -> Option<u64> {
+    name.strip_prefix("seg-")?.strip_suffix(".log")?.parse().ok()
+}
+
+fn encode(entry: &Stamped) -> Vec<u8> {
+    let mut body = Vec::with_capacity(8 + entry.1.len());
+    body.extend_from_slice(&entry.0.to_le_bytes());
+    body.extend_from_slice(&entry.1);
+    let mut rec = Vec::with_capacity(8 + body.len());
+    rec.extend_from_slice(&(entry.1.len() as u32).to_le_bytes());
+    rec.extend_from_slice(&crc32(&body).to_le_bytes());
+    rec.extend_from_slice(&body);
+    rec
+}
+
+/// Giải mã 1 record ở đầu `data`. Trả (entry, số bytes) hoặc None nếu thiếu/hỏng
+fn decode(data: &[u8]) -> Option<(Stamped, u64)> {
+    if data.len() < HEADER {
+        return None;
+    }
+    let len = u32::from_le_bytes(data[0..4].try_into().ok()?);
+    if len > MAX_RECORD || data.len() < HEADER + len as usize {
+        return None;
+    }
+    let crc = u32::from_le_bytes(data[4..8].try_into().ok()?);
+    let body = &data[8..HEADER + len as usize];
+    if crc32(body) != crc {
+        return None;
+    }
+    let ts = u64::from_le_bytes(body[0..8].try_into().ok()?);
+    Some(((ts, body[8..].to_vec()), (HEADER + len as usize) as u64))
+}
 
-        Some(data)
+/// Offset kết thúc của từng record hợp lệ liên tiếp từ đầu segment
+fn scan_records(data: &[u8]) -> Vec<u64> {
+    let mut ends = Vec::new();
+    let mut pos = 0usize;
+    while let Some((_, len)) = decode(&data[pos..]) {
+        pos += len as usize;
+        ends.push(pos as u64);
     }
+    ends
 }
 
-/// Encode bytes thành hex string (không phụ thuộc crate hex)
-fn hex_encode(data: &[u8]) -> String {
-    data.iter().map(|b| format!("{:02x}", b)).collect()
+fn read_record(path: &std::path::Path, offset: u64) -> Option<(Stamped, u64)> {
+    let mut file = std::fs::File::open(path).ok()?;
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut header = [0u8; HEADER];
+    file.read_exact(&mut header).ok()?;
+    let len = u32::from_le_bytes(header[0..4].try_into().ok()?);
+    if len > MAX_RECORD {
+        return None;
+    }
+    let mut rec = header.to_vec();
+    rec.resize(HEADER + len as usize, 0);
+    file.read_exact(&mut rec[HEADER..]).ok()?;
+    decode(&rec)
 }
 
-/// Decode hex string thành bytes
-fn hex_decode(s: &str) -> Result<Vec<u8>, ()> {
-    if s.len() % 2 != 0 {
-        return Err(());
+/// CRC-32 (IEEE 802.3, poly 0xEDB88320) — tính từng bit, không cần bảng 1KB
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
     }
-    (0..s.len())
-        .step_by(2)
-        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| ()))
-        .collect()
+    !crc
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use std::path::Path;
 
     /// Tạo thư mục tạm riêng cho mỗi test (tránh xung đột khi chạy song song)
-    fn unique_dir(name: &str) -> PathBuf {
+    pub(crate) fn unique_dir(name: &str) -> PathBuf {
         let dir = std::env::temp_dir().join(format!("ugate_test_{}_{}", std::process::id(), name));
         let _ = std::fs::remove_dir_all(&dir);
         let _ = std::fs::create_dir_all(&dir);
         dir
     }
 
-    fn cleanup(dir: &Path) {
+    pub(crate) fn cleanup(dir: &Path) {
         let _ = std::fs::remove_dir_all(dir);
     }
 
@@ -186,10 +526,11 @@ mod tests {
         buf.push(vec![3]); // → disk
 
         assert_eq!(buf.len(), 2); // Chỉ đếm RAM
-        // pop đọc disk trước (dữ liệu cũ overflow), rồi RAM
-        assert_eq!(buf.pop(), Some(vec![3])); // từ disk
+        assert_eq!(buf.total(), 3);
+        // FIFO: RAM (cũ hơn) trước, rồi tới phần tràn ra disk
         assert_eq!(buf.pop(), Some(vec![1])); // từ RAM
         assert_eq!(buf.pop(), Some(vec![2])); // từ RAM
+        assert_eq!(buf.pop(), Some(vec![3])); // từ disk
         assert_eq!(buf.pop(), None);
         cleanup(&dir);
     }
@@ -213,10 +554,72 @@ mod tests {
     }
 
     #[test]
-    fn test_hex_roundtrip() {
-        let data = vec![0x01, 0xFF, 0x00, 0xAB];
-        let hex = hex_encode(&data);
-        assert_eq!(hex, "01ff00ab");
-        assert_eq!(hex_decode(&hex), Ok(data));
+    fn test_requeue_and_max_age() {
+        let dir = unique_dir("age");
+        let mut buf = OfflineBuffer::new(10, dir.clone()).with_limits(0, 60);
+        buf.push(vec![3]);
+        // Message chưa được ack trả về đầu hàng đợi, giữ thứ tự
+        buf.requeue(vec![(now_secs(), vec![1]), (now_secs() - 120, vec![2])]);
+        assert_eq!(buf.pop(), Some(vec![1]));
+        assert_eq!(buf.pop(), Some(vec![3])); // [2] quá 60s → bỏ
+        assert_eq!(buf.dropped(), 1);
+        assert!(buf.is_empty());
+        cleanup(&dir);
+    }
+
+    #[test]
+    fn test_cursor_persisted() {
+        let dir = unique_dir("cursor");
+        {
+            let mut buf = OfflineBuffer::new(0, dir.clone());
+            for i in 0..5u8 {
+                buf.push(vec![i]);
+            }
+            assert_eq!(buf.pop(), Some(vec![0]));
+            assert_eq!(buf.pop(), Some(vec![1]));
+        } // drop → ghi lô + lưu cursor
+        let mut buf = OfflineBuffer::new(0, dir.clone());
+        assert_eq!(buf.total(), 3);
+        assert_eq!(buf.pop(), Some(vec![2]));
+        cleanup(&dir);
+    }
+
+    #[test]
+    fn test_corruption_recovery() {
+        let dir = unique_dir("corrupt");
+        {
+            let mut buf = OfflineBuffer::new(0, dir.clone());
+            buf.push(vec![1, 1]);
+            buf.push(vec![2, 2]);
+        }
+        // Mất điện giữa lúc ghi: record cuối dở dang
+        let seg = dir.join(segment_name(1));
+        let mut data = std::fs::read(&seg).unwrap();
+        data.extend_from_slice(&encode(&(0, vec![3; 10]))[..12]);
+        std::fs::write(&seg, &data).unwrap();
+
+        let mut buf = OfflineBuffer::new(0, dir.clone());
+        assert_eq!(buf.total(), 2);
+        assert_eq!(buf.pop(), Some(vec![1, 1]));
+        assert_eq!(buf.pop(), Some(vec![2, 2]));
+        assert_eq!(buf.pop(), None);
+        cleanup(&dir);
+    }
+
+    #[test]
+    fn test_size_cap_drops_oldest() {
+        let dir = unique_dir("cap");
+        // Segment tối thiểu 1KB → mỗi record ~116 bytes, ~8 record/segment
+        let mut buf = OfflineBuffer::new(0, dir.clone()).with_limits(2048, 0);
+        for i in 0..40u8 {
+            buf.push(vec![i; 100]);
+        }
+        assert!(buf.dropped() > 0);
+        assert_eq!(buf.total() as u32 + buf.dropped(), 40);
+        // Còn lại là dữ liệu mới nhất, vẫn đúng thứ tự
+        let first = buf.pop().unwrap()[0];
+        assert_eq!(first as u32, buf.dropped());
+        assert_eq!(buf.pop().unwrap()[0], first + 1);
+        cleanup(&dir);
     }
 }
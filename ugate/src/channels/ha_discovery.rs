@@ -0,0 +1,241 @@
+//! Home Assistant MQTT discovery: publish retained config dưới `<ha_prefix>/<component>/<node>/<entity>/config`
+//! - GPIO output → switch, lệnh qua `ugate/<node>/gpio/<n>/set` (ON/OFF/TOGGLE) → Command::Gpio
+//! - GPIO input → binary_sensor
+//! - CPU, RAM, uptime, bộ đếm UART → sensor (diagnostic)
+//! - Trường JSON trong frame UART (mqtt.ha_fields) → sensor đọc từ topic dữ liệu
+//!
+//! Mọi entity đọc chung 1 state topic `ugate/<node>/state` qua value_template, availability theo
+//! status topic (LWT). Discovery publish lại mỗi lần kết nối (đổi cấu hình pin → MQTT kết nối lại)
+//! và khi Home Assistant khởi động lại (`<ha_prefix>/status` = "online")
+
+use crate::commands::{Command, GpioState};
+use crate::config::{Config, MqttConfig};
+use crate::web_api::json_escape;
+use std::time::Duration;
+
+/// Số entity GPIO tối đa (khớp SharedStats.gpio_states/gpio_inputs): pin bị bỏ khỏi cấu hình
+/// thì publish config rỗng để Home Assistant xoá entity
+const MAX_PINS: usize = 4;
+
+/// Chu kỳ publish state (CPU, RAM, bộ đếm). GPIO đổi mức thì publish ngay
+pub const STATE_EVERY: Duration = Duration::from_secs(30);
+
+/// Sensor hệ thống: (key trong state JSON, tên, đơn vị, device_class, state_class)
+const SENSORS: [(&str, &str, &str, &str, &str); 7] = [
+    ("cpu", "CPU", "%", "", "measurement"),
+    ("ram_used", "RAM used", "MB", "data_size", "measurement"),
+    ("uptime", "Uptime", "s", "duration", "total_increasing"),
+    ("uart_rx_frames", "UART RX frames", "", "", "total_increasing"),
+    ("uart_tx_frames", "UART TX frames", "", "", "total_increasing"),
+    ("uart_rx_bytes", "UART RX bytes", "B", "data_size", "total_increasing"),
+    ("uart_failed", "UART errors", "", "", "total_increasing"),
+];
+
+pub struct Discovery {
+    prefix: String,
+    node: String,
+    base: String,
+    availability: String,
+}
+
+/// Node ID ổn định: "ugate_<MAC>" (hoặc theo device_name khi không đọc được MAC)
+fn node_id(cfg: &Config) -> String {
+    let raw = match super::topic::device_mac() {
+        Some(mac) => format!("ugate_{}", mac),
+        None => format!("ugate_{}", cfg.general.device_name),
+    };
+    sanitize(&raw)
+}
+
+/// object_id của Home Assistant chỉ nhận [a-zA-Z0-9_-]
+fn sanitize(s: &str) -> String {
+    s.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect()
+}
+
+/// value_template đọc 1 key trong state JSON
+fn tpl(key: &str) -> String {
+    format!("{{{{ value_json.{} }}}}", key)
+}
+
+impl Discovery {
+    pub fn new(cfg: &Config, mqtt: &MqttConfig, status_topic: &str) -> Self {
+        let node = node_id(cfg);
+        Self {
+            prefix: mqtt.ha_prefix.trim_end_matches('/').to_string(),
+            base: format!("ugate/{}", node),
+            node,
+            availability: status_topic.to_string(),
+        }
+    }
+
+    /// Status topic (LWT) dùng khi bật discovery mà chưa cấu hình mqtt.status_topic
+    pub fn default_status_topic(cfg: &Config) -> String {
+        format!("ugate/{}/status", node_id(cfg))
+    }
+
+    pub fn state_topic(&self) -> String {
+        format!("{}/state", self.base)
+    }
+
+    /// Wildcard subscribe nhận lệnh của mọi switch
+    pub fn command_filter(&self) -> String {
+        format!("{}/gpio/+/set", self.base)
+    }
+
+    /// Home Assistant publish "online" lên đây khi khởi động → publish lại discovery
+    pub fn birth_topic(&self) -> String {
+        format!("{}/status", self.prefix)
+    }
+
+    /// Toàn bộ config discovery (topic, payload). Payload rỗng = xoá entity cũ
+    pub fn messages(&self, cfg: &Config, mqtt: &MqttConfig) -> Vec<(String, String)> {
+        let mut out = Vec::new();
+        for n in 1..=MAX_PINS {
+            let topic = self.config_topic("switch", &format!("gpio{}", n));
+            let payload = if n <= cfg.gpio.pins.len().min(MAX_PINS) {
+                self.entity(cfg, &format!("gpio{}", n), &format!("GPIO {}", n), &format!(
+                    r#""state_topic":"{}","value_template":"{}","command_topic":"{}/gpio/{}/set","payload_on":"ON","payload_off":"OFF""#,
+                    self.state_topic(), tpl(&format!("gpio{}", n)), self.base, n
+                ))
+            } else {
+                String::new()
+            };
+            out.push((topic, payload));
+        }
+        for n in 1..=MAX_PINS {
+            let topic = self.config_topic("binary_sensor", &format!("input{}", n));
+            let payload = if n <= cfg.gpio.inputs.len().min(MAX_PINS) {
+                self.entity(cfg, &format!("input{}", n), &format!("Input {}", n), &format!(
+                    r#""state_topic":"{}","value_template":"{}","payload_on":"ON","payload_off":"OFF""#,
+                    self.state_topic(), tpl(&format!("input{}", n))
+                ))
+            } else {
+                String::new()
+            };
+            out.push((topic, payload));
+        }
+        for (key, name, unit, class, state_class) in SENSORS {
+            let mut fields = format!(
+                r#""state_topic":"{}","value_template":"{}","state_class":"{}","entity_category":"diagnostic""#,
+                self.state_topic(), tpl(key), state_class
+            );
+            if !unit.is_empty() {
+                fields.push_str(&format!(r#","unit_of_measurement":"{}""#, unit));
+            }
+            if !class.is_empty() {
+                fields.push_str(&format!(r#","device_class":"{}""#, class));
+            }
+            out.push((self.config_topic("sensor", key), self.entity(cfg, key, name, &fields)));
+        }
+        // Giá trị giải mã từ UART: frame JSON publish lên topic dữ liệu mặc định
+        let data_topic = super::topic::render(&mqtt.topic, &super::topic::TopicContext::device(cfg));
+        for (field, unit) in &mqtt.ha_fields {
+            let id = format!("field_{}", sanitize(field));
+            let mut fields = format!(
+                r#""state_topic":"{}","value_template":"{}""#,
+                json_escape(&data_topic), json_escape(&tpl(field))
+            );
+            if !unit.is_empty() {
+                fields.push_str(&format!(r#","unit_of_measurement":"{}""#, json_escape(unit)));
+            }
+            out.push((self.config_topic("sensor", &id), self.entity(cfg, &id, field, &fields)));
+        }
+        out
+    }
+
+    /// `ugate/<node>/gpio/<n>/set` + "ON"/"OFF"/"TOGGLE" → Command::Gpio
+    pub fn parse_command(&self, topic: &str, payload: &str) -> Option<Command> {
+        let pin: u8 = topic
+            .strip_prefix(&self.base)?
+            .strip_prefix("/gpio/")?
+            .strip_suffix("/set")?
+            .parse()
+            .ok()?;
+        let state = match payload.trim().to_uppercase().as_str() {
+            "ON" | "1" => GpioState::On,
+            "OFF" | "0" => GpioState::Off,
+            "TOGGLE" => GpioState::Toggle,
+            _ => return None,
+        };
+        Some(Command::Gpio { pin, state })
+    }
+
+    fn config_topic(&self, component: &str, object: &str) -> String {
+        format!("{}/{}/{}/{}/config", self.prefix, component, self.node, object)
+    }
+
+    /// Payload config chung: tên, unique_id, availability (LWT), device + các trường riêng
+    fn entity(&self, cfg: &Config, object: &str, name: &str, fields: &str) -> String {
+        let availability = if self.availability.is_empty() {
+            String::new()
+        } else {
+            format!(
+                r#","availability_topic":"{}","availability_template":"{}","payload_available":"online","payload_not_available":"offline""#,
+                json_escape(&self.availability), tpl("state")
+            )
+        };
+        format!(
+            r#"{{"name":"{}","unique_id":"{node}_{obj}","object_id":"{node}_{obj}",{}{},"device":{{"identifiers":["{node}"],"name":"{}","manufacturer":"ugate","model":"MT7688 gateway","sw_version":"{}"}}}}"#,
+            json_escape(name),
+            fields,
+            availability,
+            json_escape(&cfg.general.device_name),
+            env!("CARGO_PKG_VERSION"),
+            node = self.node,
+            obj = sanitize(object),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> (Config, Discovery) {
+        let mut cfg = Config::default();
+        cfg.gpio.pins = vec![17, 18];
+        cfg.gpio.inputs = vec![2];
+        cfg.mqtt.ha_fields = vec![("temp".into(), "°C".into())];
+        let ha = Discovery {
+            prefix: "homeassistant".into(),
+            node: "ugate_test".into(),
+            base: "ugate/ugate_test".into(),
+            availability: "ugate/ugate_test/status".into(),
+        };
+        (cfg, ha)
+    }
+
+    #[test]
+    fn test_messages() {
+        let (cfg, ha) = setup();
+        let msgs = ha.messages(&cfg, &cfg.mqtt);
+        let get = |t: &str| msgs.iter().find(|(topic, _)| topic == t).map(|(_, p)| p.clone());
+        let sw = get("homeassistant/switch/ugate_test/gpio2/config").unwrap();
+        assert!(sw.contains(r#""command_topic":"ugate/ugate_test/gpio/2/set""#));
+        assert!(sw.contains(r#""availability_topic":"ugate/ugate_test/status""#));
+        assert!(sw.contains("{{ value_json.gpio2 }}"));
+        // Pin không còn cấu hình → payload rỗng để xoá entity
+        assert_eq!(get("homeassistant/switch/ugate_test/gpio3/config").unwrap(), "");
+        assert!(!get("homeassistant/binary_sensor/ugate_test/input1/config").unwrap().is_empty());
+        assert_eq!(get("homeassistant/binary_sensor/ugate_test/input2/config").unwrap(), "");
+        assert!(get("homeassistant/sensor/ugate_test/cpu/config").unwrap().contains(r#""unit_of_measurement":"%""#));
+        let field = get("homeassistant/sensor/ugate_test/field_temp/config").unwrap();
+        assert!(field.contains(r#""state_topic":"ugate/data""#));
+        assert!(field.contains("°C"));
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let (_, ha) = setup();
+        match ha.parse_command("ugate/ugate_test/gpio/2/set", "on") {
+            Some(Command::Gpio { pin, state }) => {
+                assert_eq!(pin, 2);
+                assert_eq!(state, GpioState::On);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(ha.parse_command("ugate/ugate_test/gpio/x/set", "ON").is_none());
+        assert!(ha.parse_command("ugate/other/gpio/1/set", "ON").is_none());
+        assert!(ha.parse_command("ugate/ugate_test/gpio/1/set", "maybe").is_none());
+    }
+}
@@ -0,0 +1,219 @@
+//! Kênh downlink HTTP poll: GET http.poll_url định kỳ (hoặc long-poll: server giữ request tới
+//! khi có lệnh, poll_interval_secs = 0), chạy độc lập với HTTP publisher — cloud gửi được lệnh
+//! khi MCU không có dữ liệu, mạng chặn kết nối vào (TCP server, MQTT)
+//! Response: 0..n lệnh JSON (object, array, NDJSON hoặc {"commands":[...]}) → Command,
+//! kết quả nhận lệnh ("accepted", chưa phải kết quả thực thi) POST về poll_ack_url (rỗng =
+//! poll_url). Lỗi mạng/HTTP/chữ ký → chờ theo Reconnector; 2 lần poll cách nhau ít nhất 1s
+//! Request chạy trên thread riêng (không phải spawn_blocking): long-poll đang treo không giữ
+//! runtime lại khi tắt
+//! Dùng chung auth, header, ký HMAC / kiểm tra chữ ký response của section http
+
+use crate::channels::http_pub::{authorize, read_response, sign_request};
+use crate::channels::reconnect::Reconnector;
+use crate::commands::{parse_json_command, Command};
+use crate::config::{AppState, Config, HttpConfig};
+use crate::web_api::{json_array_objects, json_escape, jval};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Kết quả 1 lệnh nhận được: (id trong lệnh, Ok(tên lệnh) | Err(lý do))
+type CommandResult = (Option<String>, Result<&'static str, &'static str>);
+
+/// Khoảng nghỉ tối thiểu giữa 2 lần poll (poll_interval_secs = 0, server trả ngay 204/body rỗng)
+const MIN_POLL_GAP: Duration = Duration::from_secs(1);
+
+pub async fn run(state: Arc<AppState>, cmd_tx: mpsc::Sender<Command>) {
+    loop {
+        if crate::shutdown::is_shutting_down() {
+            return;
+        }
+        if state.get().http.poll_url.is_empty() {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(5)) => {}
+                _ = crate::shutdown::wait() => {}
+            }
+            continue;
+        }
+        run_poll_loop(&state, &cmd_tx).await;
+    }
+}
+
+/// Vòng lặp poll với cấu hình hiện tại, trả về khi config thay đổi hoặc đang tắt
+async fn run_poll_loop(state: &AppState, cmd_tx: &mpsc::Sender<Command>) {
+    let config = state.get();
+    let mut config_watch = state.subscribe();
+    let http = Arc::new(config.http.clone());
+    // Long-poll: timeout phải dài hơn thời gian server giữ request
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(http.poll_timeout_secs as u64))
+        .build();
+    let interval = Duration::from_secs(http.poll_interval_secs as u64);
+    let mut backoff = Reconnector::new(Duration::from_secs(1), Duration::from_secs(300));
+    log::info!("[HTTP Poll] GET '{}' ({})", http.poll_url,
+        if interval.is_zero() { "long-poll".to_string() } else { format!("mỗi {}s", interval.as_secs()) });
+
+    loop {
+        let fetch = {
+            let (agent, http) = (agent.clone(), http.clone());
+            off_runtime(move || fetch(&agent, &http))
+        };
+        let fetched = tokio::select! {
+            _ = config_watch.changed() => {
+                log::info!("[HTTP Poll] Config thay đổi, reload...");
+                return;
+            }
+            _ = crate::shutdown::wait() => return,
+            fetched = fetch => fetched,
+        };
+
+        let delay = match fetched {
+            Ok(body) => {
+                if backoff.attempts() > 0 {
+                    log::info!("[HTTP Poll] Endpoint hoạt động trở lại");
+                }
+                backoff.reset();
+                let results = dispatch(&body, cmd_tx).await;
+                if results.is_empty() {
+                    interval
+                } else {
+                    let ack = ack_body(&config, &results);
+                    let (agent, http) = (agent.clone(), http.clone());
+                    let acked = off_runtime(move || post_ack(&agent, &http, &ack));
+                    let acked = tokio::select! {
+                        acked = acked => acked,
+                        _ = crate::shutdown::wait() => return,
+                    };
+                    if let Err(e) = acked {
+                        log::warn!("[HTTP Poll] Gửi kết quả lệnh thất bại: {}", e);
+                    }
+                    // Vừa có lệnh → poll tiếp sớm nhất có thể, có thể còn lệnh đang chờ
+                    Duration::ZERO
+                }
+            }
+            Err(reason) => {
+                let delay = backoff.next_delay();
+                log::warn!("[HTTP Poll] Poll thất bại: {}. Thử lại sau {}s", reason, delay.as_secs());
+                delay
+            }
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(delay.max(MIN_POLL_GAP)) => {}
+            _ = config_watch.changed() => {
+                log::info!("[HTTP Poll] Config thay đổi, reload...");
+                return;
+            }
+            _ = crate::shutdown::wait() => return,
+        }
+    }
+}
+
+/// Chạy request chặn trên thread riêng, không chờ thread khi tắt (khác spawn_blocking: runtime
+/// drop phải đợi task blocking xong, long-poll có thể treo tới poll_timeout_secs)
+async fn off_runtime<T: Send + 'static>(f: impl FnOnce() -> Result<T, String> + Send + 'static) -> Result<T, String> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    std::thread::Builder::new()
+        .name("http_poll".into())
+        .spawn(move || {
+            let _ = tx.send(f());
+        })
+        .map_err(|e| e.to_string())?;
+    rx.await.unwrap_or_else(|_| Err("poll thread dừng".into()))
+}
+
+/// GET poll_url (chạy ngoài runtime). Response sai chữ ký → lỗi, thử lại theo backoff
+fn fetch(agent: &ureq::Agent, http: &HttpConfig) -> Result<String, String> {
+    let (request, request_signature) = sign_request(authorize(agent.get(&http.poll_url), http), http, b"");
+    match request.call() {
+        Ok(resp) => read_response(resp, http, request_signature.as_deref())
+            .map_err(|reason| format!("bỏ lệnh trong response: {}", reason)),
+        Err(ureq::Error::Status(code, _)) => Err(format!("HTTP {}", code)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn post_ack(agent: &ureq::Agent, http: &HttpConfig, body: &str) -> Result<(), String> {
+    let url = if http.poll_ack_url.is_empty() { &http.poll_url } else { &http.poll_ack_url };
+    let request = agent.post(url).set("Content-Type", "application/json");
+    sign_request(authorize(request, http), http, body.as_bytes())
+        .0
+        .send_string(body)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Các lệnh JSON trong response: object, array, NDJSON hoặc {"commands":[...]}
+fn command_items(body: &str) -> Vec<&str> {
+    let body = body.trim();
+    if body.starts_with('[') {
+        return json_array_objects(body);
+    }
+    let wrapped = body.find(r#""commands":"#).map(|pos| body[pos + 11..].trim_start());
+    match wrapped {
+        Some(list) if list.starts_with('[') => json_array_objects(list),
+        _ => body.lines().map(str::trim).filter(|l| l.starts_with('{')).collect(),
+    }
+}
+
+/// Chuyển lệnh hợp lệ cho dispatcher, trả kết quả từng lệnh theo thứ tự nhận
+async fn dispatch(body: &str, cmd_tx: &mpsc::Sender<Command>) -> Vec<CommandResult> {
+    let mut results = Vec::new();
+    for item in command_items(body) {
+        let id = jval(item, "id");
+        let result = match parse_json_command(item) {
+            Some(cmd) => {
+                let name = cmd.name();
+                log::info!("[HTTP Poll] Lệnh {:?}", cmd);
+                match cmd_tx.send(cmd).await {
+                    Ok(()) => Ok(name),
+                    Err(_) => Err("dispatcher unavailable"),
+                }
+            }
+            None => Err("invalid command"),
+        };
+        results.push((id, result));
+    }
+    results
+}
+
+/// Lệnh đã chuyển cho dispatcher = "accepted" (chưa phải kết quả thực thi), cùng dạng phản hồi MQTT
+/// {"device_name":"..","results":[{"id":"7","accepted":true,"command":"gpio"},{"accepted":false,"error":".."}]}
+fn ack_body(cfg: &Config, results: &[CommandResult]) -> String {
+    let items: Vec<String> = results.iter()
+        .map(|(id, result)| {
+            let id = id.as_ref().map(|id| format!(r#""id":"{}","#, json_escape(id))).unwrap_or_default();
+            match result {
+                Ok(name) => format!(r#"{{{}"accepted":true,"command":"{}"}}"#, id, name),
+                Err(reason) => format!(r#"{{{}"accepted":false,"error":"{}"}}"#, id, reason),
+            }
+        })
+        .collect();
+    format!(
+        r#"{{"device_name":"{}","results":[{}]}}"#,
+        json_escape(&cfg.general.device_name), items.join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_and_ack() {
+        let gpio = r#"{"id":7,"cmd":"gpio","pin":1,"state":"on"}"#;
+        assert_eq!(command_items(gpio), vec![gpio]);
+        assert_eq!(command_items(&format!("[{},{}]", gpio, gpio)).len(), 2);
+        assert_eq!(command_items(&format!(r#"{{"commands":[{}]}}"#, gpio)), vec![gpio]);
+        assert_eq!(command_items(&format!("{}\n{}\n", gpio, gpio)).len(), 2);
+        assert!(command_items("").is_empty());
+        assert!(command_items(r#"{"commands":[]}"#).is_empty());
+
+        let mut cfg = Config::default();
+        cfg.general.device_name = "gw1".into();
+        let results = vec![(Some("7".to_string()), Ok("gpio")), (None, Err("invalid command"))];
+        assert_eq!(
+            ack_body(&cfg, &results),
+            r#"{"device_name":"gw1","results":[{"id":"7","accepted":true,"command":"gpio"},{"accepted":false,"error":"invalid command"}]}"#
+        );
+    }
+}
@@ -2,45 +2,103 @@
 //! Nhận dữ liệu UART qua tokio mpsc channel, POST tới URL đã cấu hình
 //! Dùng ureq (sync) trong spawn_blocking để không block tokio runtime
 //! Tự động reload khi config thay đổi
+//! Store-and-forward: lỗi mạng/5xx/429 → giữ trong OfflineBuffer, thử lại với backoff
+//! (tôn trọng Retry-After); 4xx khác → lỗi vĩnh viễn, bỏ message
+//! Request: POST/PUT/PATCH/GET, header tuỳ chỉnh, auth basic/bearer/API key, body theo
+//! template có placeholder (xem render_body)
+//! Batch (http.batch_max_items > 1): gom message tới đủ N message / M bytes / T ms rồi gửi
+//! 1 request JSON array hoặc NDJSON, tuỳ chọn gzip. Response có thể chứa nhiều lệnh
+//! Ký request (http.sign_secret): HMAC-SHA256 trên "<timestamp>.<body>" gửi trong
+//! X-Ugate-Signature + X-Ugate-Timestamp; tuỳ chọn bắt response ký cùng cách (gắn với chữ ký
+//! của request, xem verify) mới nhận lệnh
 
+use crate::channels::buffer::{OfflineBuffer, Stamped};
+use crate::channels::reconnect::Reconnector;
+use crate::channels::topic::{device_mac, TopicContext};
 use crate::commands::Command;
-use crate::config::AppState;
+use crate::config::{AppState, BatchFormat, Config, HttpAuth, HttpConfig, HttpMethod};
+use crate::web_api::{json_array_objects, json_escape, jval, url_encode};
 use crate::web_api::status::SharedStats;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::hmac;
 use std::sync::Arc;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::io::Write;
 use std::time::Duration;
 use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+/// Số thứ tự message ({seq} trong body_template), tăng theo từng lần gửi
+static SEQ: AtomicU32 = AtomicU32::new(0);
+
+const SIGNATURE_HEADER: &str = "X-Ugate-Signature";
+const TIMESTAMP_HEADER: &str = "X-Ugate-Timestamp";
+/// Lệch giờ tối đa giữa timestamp response và đồng hồ gateway (chống replay)
+const MAX_SKEW_SECS: u64 = 300;
+
+/// Kết quả gửi 1 request (1 message hoặc 1 batch)
+enum Outcome {
+    /// 2xx — kèm lệnh trong response body (gửi ngược MCU)
+    Sent(Vec<Command>),
+    /// Lỗi mạng, 5xx, 429 — thử lại, kèm Retry-After nếu server trả
+    Retry(String, Option<Duration>),
+    /// 4xx còn lại — gửi lại cũng vô ích
+    Permanent(String),
+}
 
 /// Vòng lặp chính: chờ dữ liệu và POST, đọc response body gửi ngược MCU
 pub async fn run(
     state: Arc<AppState>,
-    mut data_rx: mpsc::Receiver<Vec<u8>>,
+    data_rx: &mut mpsc::Receiver<Vec<u8>>,
     cmd_tx: mpsc::Sender<Command>,
     stats: Arc<SharedStats>,
 ) {
+    let bcfg = state.get().buffer;
+    let mut buffer = OfflineBuffer::new(bcfg.ram_msgs, std::path::Path::new(&bcfg.dir).join("http"))
+        .with_limits(bcfg.max_disk_kb as u64 * 1024, bcfg.max_age_secs as u64);
+    if !buffer.is_empty() {
+        log::info!("[HTTP] {} message chờ gửi từ lần chạy trước", buffer.total());
+    }
+    update_buffer_stats(&buffer, &stats);
+
     loop {
+        if crate::shutdown::is_shutting_down() {
+            // Giữ dữ liệu chưa gửi được cho lần chạy sau
+            while let Ok(data) = data_rx.try_recv() {
+                buffer.push(data);
+            }
+            if !buffer.is_empty() {
+                buffer.flush_to_disk();
+                log::info!("[HTTP] Lưu {} message chưa gửi ra disk", buffer.total());
+            }
+            update_buffer_stats(&buffer, &stats);
+            crate::shutdown::done(crate::shutdown::Part::Http);
+            return;
+        }
         let config = state.get();
         if !config.http.enabled || config.http.url.is_empty() {
             stats.http_state.store(0, Ordering::Relaxed);
-            tokio::time::sleep(Duration::from_secs(5)).await;
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(5)) => {}
+                _ = crate::shutdown::wait() => {}
+            }
             while data_rx.try_recv().is_ok() {}
             continue;
         }
         stats.http_state.store(2, Ordering::Relaxed); // active = connected
-        if let Err(e) = run_publish_loop(&state, &mut data_rx, &cmd_tx, &stats).await {
-            log::error!("[HTTP] Lỗi: {}. Thử lại sau 10s...", e);
-            tokio::time::sleep(Duration::from_secs(10)).await;
-        }
+        run_publish_loop(&state, data_rx, &cmd_tx, &stats, &mut buffer).await;
     }
 }
 
-/// Vòng lặp publish: nhận dữ liệu từ channel, POST qua ureq
+/// Vòng lặp publish: nhận dữ liệu vào buffer, gửi theo thứ tự qua ureq
 async fn run_publish_loop(
     state: &AppState,
     data_rx: &mut mpsc::Receiver<Vec<u8>>,
     cmd_tx: &mpsc::Sender<Command>,
     stats: &Arc<SharedStats>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    buffer: &mut OfflineBuffer,
+) {
     let config = state.get();
     let mut config_watch = state.subscribe();
 
@@ -48,92 +106,614 @@ async fn run_publish_loop(
         .timeout(Duration::from_secs(10))
         .build();
 
-    let method = config.http.method.clone();
-    let method_str = match method {
-        crate::config::HttpMethod::Get => "GET",
-        crate::config::HttpMethod::Post => "POST",
+    let http = &config.http;
+    // GET không gộp được nhiều message trong 1 request
+    let batch_items = if http.method == HttpMethod::Get { 1 } else { http.batch_max_items.max(1) as usize };
+    let batch_bytes = http.batch_max_bytes as usize;
+    let batch_window = Duration::from_millis(http.batch_max_ms as u64);
+    let batch_info = match batch_items {
+        1 => String::new(),
+        n => format!(", batch {} message/{} bytes/{}ms", n, batch_bytes, http.batch_max_ms),
     };
-    log::info!("[HTTP] {} tới '{}'", method_str, config.http.url);
+    log::info!("[HTTP] {} tới '{}' ({}{}{}{})", method_name(&http.method), http.url,
+        if http.ordered { "tuần tự" } else { "song song" },
+        if http.body_template.is_empty() { "" } else { ", body template" },
+        batch_info,
+        if http.gzip { ", gzip" } else { "" });
+
+    let max_inflight = if http.ordered { 1 } else { http.max_concurrent as usize };
+    let mut inflight: JoinSet<(Vec<Stamped>, Outcome)> = JoinSet::new();
+    let mut backoff = Reconnector::new(Duration::from_secs(1), Duration::from_secs(300));
+    // Endpoint đang lỗi → tạm dừng gửi tới thời điểm này
+    let mut retry_at: Option<tokio::time::Instant> = None;
+    // Cửa sổ gom batch: số message + bytes nhận từ lúc mở, hạn gửi. Không có cửa sổ mà buffer
+    // còn dữ liệu (replay, phần dư batch trước, gửi lại) → gửi ngay
+    let (mut window_items, mut window_bytes) = (0usize, 0usize);
+    let mut flush_at: Option<tokio::time::Instant> = None;
 
     loop {
+        // Gửi từ buffer khi endpoint sẵn sàng và batch đủ điều kiện
+        while retry_at.is_none() && inflight.len() < max_inflight && !buffer.is_empty() {
+            let ready = window_items >= batch_items
+                || window_bytes >= batch_bytes
+                || flush_at.is_none_or(|at| tokio::time::Instant::now() >= at)
+                || crate::shutdown::is_shutting_down();
+            if !ready {
+                break;
+            }
+            let entries = take_batch(buffer, batch_items, batch_bytes);
+            (window_items, window_bytes, flush_at) = (0, 0, None);
+            if entries.is_empty() {
+                break;
+            }
+            let agent = agent.clone();
+            let config = config.clone();
+            let seq = SEQ.fetch_add(entries.len() as u32, Ordering::Relaxed);
+            inflight.spawn_blocking(move || {
+                let outcome = send(&agent, &config, &entries, seq, batch_items > 1);
+                (entries, outcome)
+            });
+        }
+        update_buffer_stats(buffer, stats);
+        let window_open = flush_at.is_some() && retry_at.is_none() && inflight.len() < max_inflight;
+
         tokio::select! {
             _ = config_watch.changed() => {
                 log::info!("[HTTP] Config thay đổi, reload...");
-                return Ok(());
+                settle(&mut inflight, buffer, cmd_tx, stats, &mut backoff).await;
+                return;
+            }
+
+            // Tắt êm: gửi hết hàng đợi sau khi fan-out xả xong (hoặc hết thời gian)
+            _ = tokio::time::sleep(Duration::from_millis(100)), if crate::shutdown::is_shutting_down() => {
+                let drained = crate::shutdown::is_done(crate::shutdown::Part::FanOut)
+                    && data_rx.is_empty()
+                    && buffer.is_empty()
+                    && inflight.is_empty();
+                if drained || crate::shutdown::deadline_passed() {
+                    settle(&mut inflight, buffer, cmd_tx, stats, &mut backoff).await;
+                    return;
+                }
             }
 
             Some(data) = data_rx.recv() => {
-                let url = config.http.url.clone();
-                let agent = agent.clone();
-                let is_get = method == crate::config::HttpMethod::Get;
-
-                // Detect wrapped JSON (bắt đầu bằng '{') hoặc raw bytes
-                let is_wrapped = data.first() == Some(&b'{');
-                // Text encoding: gửi string UTF-8, ngược lại hex
-                let data_str = if is_wrapped {
-                    // Wrapped JSON từ fan-out → gửi trực tiếp
-                    String::from_utf8_lossy(&data).into_owned()
-                } else if config.general.data_as_text {
-                    // Text mode: thử UTF-8, fallback hex
-                    match std::str::from_utf8(&data) {
-                        Ok(s) => format!(r#"{{"data":"{}","len":{}}}"#, crate::web_api::json_escape(s), data.len()),
-                        Err(_) => {
-                            let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
-                            format!(r#"{{"data":"{}","len":{}}}"#, hex, data.len())
-                        }
-                    }
-                } else {
-                    let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
-                    format!(r#"{{"data":"{}","len":{}}}"#, hex, data.len())
-                };
-                // GET query value: wrapped → parse fields, raw → data field
-                let get_query = if is_wrapped {
-                    let dname = crate::web_api::jval(&data_str, "device_name").unwrap_or_default();
-                    let ts = crate::web_api::jval(&data_str, "timestamp").unwrap_or_default();
-                    let dv = crate::web_api::jval(&data_str, "data").unwrap_or_default();
-                    format!("device_name={}&timestamp={}&data={}", dname, ts, dv)
-                } else {
-                    let dv = crate::web_api::jval(&data_str, "data").unwrap_or_default();
-                    format!("data={}", dv)
-                };
-
-                let stats_c = stats.clone();
-                let cmd_tx_c = cmd_tx.clone();
-                tokio::task::spawn_blocking(move || {
-                    let result = if is_get {
-                        let sep = if url.contains('?') { "&" } else { "?" };
-                        agent.get(&format!("{}{}{}", url, sep, get_query)).call()
-                    } else {
-                        agent.post(&url)
-                            .set("Content-Type", "application/json")
-                            .send_string(&data_str)
-                    };
-                    match result {
-                        Ok(resp) => {
-                            stats_c.http_sent.fetch_add(1, Ordering::Relaxed);
-                            // Đọc response body (giới hạn 10KB, tránh OOM nếu server trả HTML lớn)
-                            let mut body = String::new();
-                            use std::io::Read;
-                            if resp.into_reader().take(10240)
-                                .read_to_string(&mut body).is_ok() {
-                                let trimmed = body.trim();
-                                if !trimmed.is_empty() {
-                                    let cmd = if let Some(cmd) = crate::commands::parse_json_command(trimmed) {
-                                        cmd
-                                    } else {
-                                        Command::UartTx { data: trimmed.to_string() }
-                                    };
-                                    let _ = cmd_tx_c.blocking_send(cmd);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            log::error!("[HTTP] {} thất bại: {}", if is_get { "GET" } else { "POST" }, e);
-                            stats_c.http_failed.fetch_add(1, Ordering::Relaxed);
-                        }
+                if buffer.is_empty() {
+                    flush_at = Some(tokio::time::Instant::now() + batch_window);
+                }
+                window_items += 1;
+                window_bytes += data.len();
+                buffer.push(data);
+            }
+
+            // Hết thời gian gom batch
+            _ = sleep_until(flush_at), if window_open => {}
+
+            Some(joined) = inflight.join_next(), if !inflight.is_empty() => {
+                if let Ok((entries, outcome)) = joined {
+                    if let Some(delay) = handle_outcome(entries, outcome, buffer, cmd_tx, stats, &mut backoff).await {
+                        retry_at = Some(tokio::time::Instant::now() + delay);
                     }
-                });
+                }
+            }
+
+            _ = sleep_until(retry_at), if retry_at.is_some() => {
+                retry_at = None;
+            }
+
+            // Lô ghi disk chưa đầy nhưng không còn dữ liệu mới → ghi xuống sau 2s
+            _ = tokio::time::sleep(Duration::from_secs(2)), if buffer.has_pending_writes() => {
+                buffer.flush_due();
             }
         }
     }
 }
+
+async fn sleep_until(at: Option<tokio::time::Instant>) {
+    if let Some(at) = at {
+        tokio::time::sleep_until(at).await;
+    }
+}
+
+/// Chờ các request đang chạy xong, message cần thử lại được trả về buffer
+async fn settle(
+    inflight: &mut JoinSet<(Vec<Stamped>, Outcome)>,
+    buffer: &mut OfflineBuffer,
+    cmd_tx: &mpsc::Sender<Command>,
+    stats: &SharedStats,
+    backoff: &mut Reconnector,
+) {
+    while let Some(joined) = inflight.join_next().await {
+        if let Ok((entries, outcome)) = joined {
+            handle_outcome(entries, outcome, buffer, cmd_tx, stats, backoff).await;
+        }
+    }
+    update_buffer_stats(buffer, stats);
+}
+
+/// Xử lý kết quả gửi. Trả Some(delay) nếu endpoint lỗi cần tạm dừng gửi
+async fn handle_outcome(
+    entries: Vec<Stamped>,
+    outcome: Outcome,
+    buffer: &mut OfflineBuffer,
+    cmd_tx: &mpsc::Sender<Command>,
+    stats: &SharedStats,
+    backoff: &mut Reconnector,
+) -> Option<Duration> {
+    match outcome {
+        Outcome::Sent(commands) => {
+            stats.http_sent.fetch_add(entries.len() as u32, Ordering::Relaxed);
+            if backoff.attempts() > 0 {
+                log::info!("[HTTP] Endpoint hoạt động trở lại");
+                stats.http_state.store(2, Ordering::Relaxed);
+            }
+            backoff.reset();
+            for cmd in commands {
+                let _ = cmd_tx.send(cmd).await;
+            }
+            None
+        }
+        Outcome::Retry(reason, retry_after) => {
+            buffer.requeue(entries);
+            let delay = retry_after.unwrap_or_else(|| backoff.next_delay());
+            log::warn!("[HTTP] Gửi thất bại: {}. Thử lại sau {}s ({} message trong buffer)",
+                reason, delay.as_secs(), buffer.total());
+            stats.http_state.store(1, Ordering::Relaxed);
+            Some(delay)
+        }
+        Outcome::Permanent(reason) => {
+            log::error!("[HTTP] Bỏ {} message: {}", entries.len(), reason);
+            stats.http_failed.fetch_add(entries.len() as u32, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+fn method_name(method: &HttpMethod) -> &'static str {
+    match method {
+        HttpMethod::Get => "GET",
+        HttpMethod::Post => "POST",
+        HttpMethod::Put => "PUT",
+        HttpMethod::Patch => "PATCH",
+    }
+}
+
+/// Lấy tối đa `max_items` message, tổng không quá `max_bytes` (luôn ít nhất 1 message)
+fn take_batch(buffer: &mut OfflineBuffer, max_items: usize, max_bytes: usize) -> Vec<Stamped> {
+    let mut entries: Vec<Stamped> = Vec::new();
+    let mut bytes = 0;
+    while entries.len() < max_items {
+        let entry = match buffer.pop_stamped() {
+            Some(e) => e,
+            None => break,
+        };
+        if !entries.is_empty() && bytes + entry.1.len() > max_bytes {
+            buffer.requeue(vec![entry]);
+            break;
+        }
+        bytes += entry.1.len();
+        entries.push(entry);
+    }
+    entries
+}
+
+/// Body JSON của 1 message: template, JSON đã wrap (gửi nguyên) hoặc {"data":..,"len":..}
+fn message_body(config: &Config, entry: &Stamped, seq: u32) -> String {
+    let data = entry.1.as_slice();
+    if !config.http.body_template.is_empty() {
+        let (frame, timestamp) = unwrap_envelope(config, data).unwrap_or((data.to_vec(), entry.0));
+        return render_body(&config.http.body_template, config, &frame, timestamp, seq);
+    }
+    // Detect wrapped JSON (bắt đầu bằng '{') hoặc raw bytes
+    if data.first() == Some(&b'{') {
+        String::from_utf8_lossy(data).into_owned()
+    } else {
+        format!(r#"{{"data":"{}","len":{}}}"#, data_field(config, data), data.len())
+    }
+}
+
+/// URL GET: query value lấy từ JSON đã wrap (device_name, timestamp, data) hoặc frame thô (data)
+fn get_url(config: &Config, data: &[u8]) -> String {
+    let http = &config.http;
+    let field = |json: &str, key: &str| url_encode(&jval(json, key).unwrap_or_default());
+    let get_query = if data.first() == Some(&b'{') {
+        let json = String::from_utf8_lossy(data);
+        format!("device_name={}&timestamp={}&data={}",
+            field(&json, "device_name"), field(&json, "timestamp"), field(&json, "data"))
+    } else {
+        format!("data={}", url_encode(&data_field(config, data)))
+    };
+    let sep = if http.url.contains('?') { "&" } else { "?" };
+    format!("{}{}{}", http.url, sep, get_query)
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    // Ghi vào Vec không lỗi
+    let _ = encoder.write_all(data);
+    encoder.finish().unwrap_or_default()
+}
+
+/// Gửi 1 request (chạy trong spawn_blocking) và phân loại kết quả. `batched` = body là
+/// JSON array / NDJSON của `entries` (kể cả khi chỉ có 1 message)
+fn send(agent: &ureq::Agent, config: &Config, entries: &[Stamped], seq: u32, batched: bool) -> Outcome {
+    let http = &config.http;
+    let url = match (&http.method, entries.first()) {
+        (HttpMethod::Get, Some(entry)) => get_url(config, &entry.1),
+        _ => http.url.clone(),
+    };
+    let mut request = agent.request(method_name(&http.method), &url);
+    if http.method != HttpMethod::Get {
+        let content_type = match (batched, http.batch_format) {
+            (true, BatchFormat::Ndjson) => "application/x-ndjson",
+            _ => "application/json",
+        };
+        request = request.set("Content-Type", content_type);
+        if http.gzip {
+            request = request.set("Content-Encoding", "gzip");
+        }
+    }
+    request = authorize(request, http);
+
+    // GET: ký trên body rỗng
+    let body = if http.method == HttpMethod::Get {
+        String::new()
+    } else {
+        let bodies = entries.iter().enumerate().map(|(i, e)| message_body(config, e, seq.wrapping_add(i as u32)));
+        match (batched, http.batch_format) {
+            (false, _) => bodies.collect::<String>(),
+            (true, BatchFormat::Json) => format!("[{}]", bodies.collect::<Vec<_>>().join(",")),
+            (true, BatchFormat::Ndjson) => bodies.map(|b| b + "\n").collect(),
+        }
+    };
+    let (request, request_signature) = sign_request(request, http, body.as_bytes());
+
+    let result = if http.method == HttpMethod::Get {
+        request.call()
+    } else {
+        if http.gzip {
+            request.send_bytes(&gzip(body.as_bytes()))
+        } else {
+            request.send_string(&body)
+        }
+    };
+
+    match result {
+        Ok(resp) => match read_response(resp, http, request_signature.as_deref()) {
+            Ok(body) => Outcome::Sent(response_commands(&body, batched)),
+            Err(reason) => {
+                log::warn!("[HTTP] Bỏ lệnh trong response: {}", reason);
+                Outcome::Sent(Vec::new())
+            }
+        },
+        Err(ureq::Error::Status(code, resp)) => {
+            if code == 429 || code >= 500 {
+                Outcome::Retry(format!("HTTP {}", code), retry_after(resp.header("Retry-After")))
+            } else {
+                Outcome::Permanent(format!("HTTP {}", code))
+            }
+        }
+        Err(e) => Outcome::Retry(e.to_string(), None),
+    }
+}
+
+/// Gửi ngay 1 bản tin telemetry, không qua buffer: lỗi thì bỏ, lần sau gửi bản mới
+/// (chạy trong spawn_blocking). Trả lệnh trong response
+pub(crate) fn send_telemetry(agent: &ureq::Agent, config: &Config, payload: Vec<u8>) -> Result<Vec<Command>, String> {
+    // Endpoint đang nhận batch → telemetry cũng là array 1 phần tử
+    let batched = config.http.method != HttpMethod::Get && config.http.batch_max_items > 1;
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+    match send(agent, config, &[(now_secs(), payload)], seq, batched) {
+        Outcome::Sent(commands) => Ok(commands),
+        Outcome::Retry(reason, _) | Outcome::Permanent(reason) => Err(reason),
+    }
+}
+
+/// Auth + header cấu hình. Header cấu hình đặt sau cùng: ghi đè được Content-Type/Authorization
+pub(crate) fn authorize(mut request: ureq::Request, http: &HttpConfig) -> ureq::Request {
+    request = match http.auth {
+        HttpAuth::None => request,
+        HttpAuth::Basic => {
+            let credentials = BASE64.encode(format!("{}:{}", http.username, http.password));
+            request.set("Authorization", &format!("Basic {}", credentials))
+        }
+        HttpAuth::Bearer => request.set("Authorization", &format!("Bearer {}", http.token)),
+        HttpAuth::ApiKey => request.set(&http.api_key_header, &http.token),
+    };
+    for (name, value) in &http.headers {
+        request = request.set(name, value);
+    }
+    request
+}
+
+/// Thêm X-Ugate-Signature + X-Ugate-Timestamp khi có sign_secret. Trả kèm chữ ký request
+/// (response phải ký lại trên chữ ký này, xem read_response)
+pub(crate) fn sign_request(request: ureq::Request, http: &HttpConfig, body: &[u8]) -> (ureq::Request, Option<String>) {
+    if http.sign_secret.is_empty() {
+        return (request, None);
+    }
+    let timestamp = now_secs();
+    let signature = sign(&http.sign_secret, timestamp, body);
+    let request = request
+        .set(SIGNATURE_HEADER, &signature)
+        .set(TIMESTAMP_HEADER, &timestamp.to_string());
+    (request, Some(signature))
+}
+
+/// Đọc response body (giới hạn 10KB, tránh OOM nếu server trả HTML lớn), kiểm tra chữ ký
+/// khi bật verify_response. Err = body có nội dung nhưng sai/thiếu chữ ký, hoặc không có
+/// sign_secret để kiểm tra (không bao giờ nhận lệnh chưa xác thực)
+pub(crate) fn read_response(
+    resp: ureq::Response,
+    http: &HttpConfig,
+    request_signature: Option<&str>,
+) -> Result<String, &'static str> {
+    let signature = resp.header(SIGNATURE_HEADER).map(str::to_string);
+    let timestamp = resp.header(TIMESTAMP_HEADER).map(str::to_string);
+    let mut body = String::new();
+    use std::io::Read;
+    let _ = resp.into_reader().take(10240).read_to_string(&mut body);
+    if http.verify_response && !body.trim().is_empty() {
+        let request_signature = request_signature
+            .filter(|_| !http.sign_secret.is_empty())
+            .ok_or("verify_response bật nhưng sign_secret rỗng")?;
+        verify(&http.sign_secret, request_signature, timestamp.as_deref(), signature.as_deref(), body.as_bytes(), now_secs())?;
+    }
+    Ok(body)
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn signing_key(secret: &str) -> hmac::Key {
+    hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())
+}
+
+fn signed_data(timestamp: u64, body: &[u8]) -> Vec<u8> {
+    let mut data = format!("{}.", timestamp).into_bytes();
+    data.extend_from_slice(body);
+    data
+}
+
+/// Hex HMAC-SHA256(secret, "<timestamp>.<body>")
+fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    hmac::sign(&signing_key(secret), &signed_data(timestamp, body))
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Kiểm tra chữ ký response = HMAC(secret, "<timestamp>.<chữ ký request>.<body>") (so sánh
+/// constant-time): gắn với request nên không replay được response cũ cho request khác.
+/// Timestamp lệch quá MAX_SKEW_SECS bị từ chối
+fn verify(
+    secret: &str,
+    request_signature: &str,
+    timestamp: Option<&str>,
+    signature: Option<&str>,
+    body: &[u8],
+    now: u64,
+) -> Result<(), &'static str> {
+    let timestamp: u64 = timestamp.ok_or("thiếu X-Ugate-Timestamp")?
+        .trim()
+        .parse()
+        .map_err(|_| "X-Ugate-Timestamp không hợp lệ")?;
+    if timestamp.abs_diff(now) > MAX_SKEW_SECS {
+        return Err("X-Ugate-Timestamp lệch quá 300s");
+    }
+    let signature = signature.ok_or("thiếu X-Ugate-Signature")?.trim();
+    let tag = (0..signature.len() / 2)
+        .map(|i| u8::from_str_radix(signature.get(i * 2..i * 2 + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()
+        .ok_or("X-Ugate-Signature không phải hex")?;
+    let bound = [request_signature.as_bytes(), b".", body].concat();
+    hmac::verify(&signing_key(secret), &signed_data(timestamp, &bound), &tag)
+        .map_err(|_| "sai chữ ký")
+}
+
+/// Lệnh trong response: lệnh JSON, không phải JSON → gửi thẳng xuống UART.
+/// Batch: server có thể trả nhiều lệnh dạng JSON array hoặc NDJSON (phần tử không phải lệnh bị bỏ qua)
+fn response_commands(body: &str, batched: bool) -> Vec<Command> {
+    let body = body.trim();
+    if body.is_empty() {
+        return Vec::new();
+    }
+    if batched {
+        let items = if body.starts_with('[') {
+            json_array_objects(body)
+        } else {
+            body.lines().map(str::trim).filter(|l| !l.is_empty()).collect()
+        };
+        if body.starts_with('[') || items.len() > 1 {
+            return items.into_iter().filter_map(crate::commands::parse_json_command).collect();
+        }
+    }
+    let cmd = crate::commands::parse_json_command(body)
+        .unwrap_or_else(|| Command::UartTx { data: body.to_string() });
+    vec![cmd]
+}
+
+/// Frame dạng chuỗi trong JSON: text (data_as_text + UTF-8) hoặc hex
+fn data_field(config: &Config, frame: &[u8]) -> String {
+    match std::str::from_utf8(frame) {
+        Ok(s) if config.general.data_as_text => json_escape(s),
+        _ => frame.iter().map(|b| format!("{:02x}", b)).collect(),
+    }
+}
+
+/// Envelope wrap_json `{"device_name":..,"timestamp":..,"data":..}` → (frame gốc, timestamp)
+/// Message khác (frame thô, telemetry) → None
+fn unwrap_envelope(config: &Config, data: &[u8]) -> Option<(Vec<u8>, u64)> {
+    if !config.general.wrap_json || !data.starts_with(br#"{"device_name":""#) {
+        return None;
+    }
+    let json = std::str::from_utf8(data).ok()?;
+    let timestamp = jval(json, "timestamp")?.parse().ok()?;
+    let field = jval(json, "data")?;
+    let frame = if config.general.data_as_text {
+        field.into_bytes()
+    } else {
+        (0..field.len() / 2)
+            .map(|i| u8::from_str_radix(field.get(i * 2..i * 2 + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?
+    };
+    Some((frame, timestamp))
+}
+
+/// Thay placeholder trong body_template (1 lượt, giá trị chèn vào không bị thay tiếp):
+/// {data} text hoặc hex theo data_as_text, {hex}, {base64}, {len}, {timestamp}, {seq},
+/// {device_name}, {mac}, {port}, {slave_id}, {field:<tên>} trường JSON trong frame.
+/// Giá trị chuỗi đã escape JSON; `{...}` khác giữ nguyên (JSON trong template). Trường
+/// {field:x} không có trong frame → `null` (hoặc chuỗi rỗng khi nằm trong ngoặc kép)
+fn render_body(template: &str, config: &Config, frame: &[u8], timestamp: u64, seq: u32) -> String {
+    let ctx = TopicContext::frame(config, frame);
+    let text = std::str::from_utf8(frame).ok();
+    let value = |name: &str, quoted: bool| -> Option<String> {
+        Some(match name {
+            "data" => data_field(config, frame),
+            "hex" => frame.iter().map(|b| format!("{:02x}", b)).collect(),
+            "base64" => BASE64.encode(frame),
+            "len" => frame.len().to_string(),
+            "timestamp" => timestamp.to_string(),
+            "seq" => seq.to_string(),
+            "device_name" => json_escape(ctx.device_name),
+            "mac" => device_mac().unwrap_or("").to_string(),
+            "port" => json_escape(ctx.port),
+            "slave_id" => ctx.slave_id.map(|id| id.to_string()).unwrap_or_default(),
+            _ => {
+                let field = name.strip_prefix("field:")?;
+                match text.and_then(|t| jval(t, field)) {
+                    Some(v) => json_escape(&v),
+                    None if quoted => String::new(),
+                    None => "null".to_string(),
+                }
+            }
+        })
+    };
+    let mut out = String::with_capacity(template.len() + frame.len() * 2);
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let placeholder = after.find('}').and_then(|end| {
+            let quoted = out.ends_with('"') && after[end + 1..].starts_with('"');
+            value(&after[..end], quoted).map(|v| (v, end))
+        });
+        match placeholder {
+            Some((v, end)) => {
+                out.push_str(&v);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Parse header Retry-After dạng số giây (dạng HTTP-date → dùng backoff thường)
+fn retry_after(header: Option<&str>) -> Option<Duration> {
+    header
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(|secs| Duration::from_secs(secs.min(3600)))
+}
+
+fn update_buffer_stats(buffer: &OfflineBuffer, stats: &SharedStats) {
+    stats.http_buffered.store(buffer.total() as u32, Ordering::Relaxed);
+    stats.http_dropped.store(buffer.dropped(), Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channels::buffer::tests::{cleanup, unique_dir};
+
+    #[test]
+    fn test_retry_after() {
+        assert_eq!(retry_after(Some("120")), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(Some(" 5 ")), Some(Duration::from_secs(5)));
+        // Giới hạn 1 giờ
+        assert_eq!(retry_after(Some("99999")), Some(Duration::from_secs(3600)));
+        assert_eq!(retry_after(Some("Wed, 21 Oct 2015 07:28:00 GMT")), None);
+        assert_eq!(retry_after(None), None);
+    }
+
+    #[test]
+    fn test_render_body() {
+        let mut cfg = Config::default();
+        cfg.general.device_name = "gw1".into();
+        let frame = br#"{"temp":21.5,"id":"a"}"#;
+        assert_eq!(
+            render_body(r#"{"dev":"{device_name}","t":{field:temp},"id":"{field:id}","n":{len},"seq":{seq},"ts":{timestamp}}"#, &cfg, frame, 1700, 7),
+            r#"{"dev":"gw1","t":21.5,"id":"a","n":22,"seq":7,"ts":1700}"#
+        );
+        assert_eq!(render_body(r#"{"h":"{hex}","b":"{base64}"}"#, &cfg, &[0x01, 0xff], 0, 0), r#"{"h":"01ff","b":"Af8="}"#);
+        // Trường thiếu: null khi không có ngoặc kép, chuỗi rỗng khi có
+        assert_eq!(render_body(r#"{"t":{field:x},"s":"{field:x}"}"#, &cfg, frame, 0, 0), r#"{"t":null,"s":""}"#);
+        // Giá trị chèn vào không bị thay tiếp
+        assert_eq!(render_body("{data}", &cfg, b"{len}", 0, 0), "{len}");
+
+        cfg.general.wrap_json = true;
+        cfg.general.data_as_text = false;
+        let envelope = br#"{"device_name":"gw1","timestamp":1700,"data":"01ff"}"#;
+        assert_eq!(unwrap_envelope(&cfg, envelope), Some((vec![0x01, 0xff], 1700)));
+        assert_eq!(unwrap_envelope(&cfg, br#"{"type":"telemetry"}"#), None);
+    }
+
+    #[test]
+    fn test_batch() {
+        let dir = unique_dir("http_batch");
+        let mut buffer = OfflineBuffer::new(10, dir.clone());
+        for msg in [&b"aaaa"[..], b"bbbb", b"cccc", b"dd"] {
+            buffer.push(msg.to_vec());
+        }
+        // Giới hạn bytes cắt batch, message quá lớn vẫn đi 1 mình
+        assert_eq!(take_batch(&mut buffer, 10, 8).len(), 2);
+        assert_eq!(take_batch(&mut buffer, 1, 8).len(), 1);
+        assert_eq!(take_batch(&mut buffer, 10, 1).len(), 1);
+        assert!(buffer.is_empty());
+        cleanup(&dir);
+
+        let cmds = response_commands(r#"[{"ok":true},{"cmd":"gpio","pin":1,"state":"on"},{"cmd":"uart_tx","data":"x"}]"#, true);
+        assert_eq!(cmds.len(), 2);
+        let ndjson = "{\"cmd\":\"uart_tx\",\"data\":\"a\"}\n{\"cmd\":\"uart_tx\",\"data\":\"b\"}\n";
+        assert_eq!(response_commands(ndjson, true).len(), 2);
+        assert!(matches!(&response_commands("OK", true)[..], [Command::UartTx { data }] if data == "OK"));
+        assert!(response_commands("  ", false).is_empty());
+
+        use std::io::Read;
+        let mut out = String::new();
+        flate2::read::GzDecoder::new(&gzip(b"[1,2,3]")[..]).read_to_string(&mut out).unwrap();
+        assert_eq!(out, "[1,2,3]");
+    }
+
+    #[test]
+    fn test_signature() {
+        // echo -n '1700000000.{"a":1}' | openssl dgst -sha256 -hmac secret
+        let sig = sign("secret", 1_700_000_000, br#"{"a":1}"#);
+        assert_eq!(sig, "49f24e537407743fa4a0242bb63b94b9a47ee99cbbe071ccd8a22550ae411686");
+        // Response ký trên "<ts>.<chữ ký request>.<body>"
+        let ts = Some("1700000000");
+        let resp_sig = sign("secret", 1_700_000_000, format!(r#"{}.{{"a":1}}"#, sig).as_bytes());
+        assert!(verify("secret", &sig, ts, Some(&resp_sig), br#"{"a":1}"#, 1_700_000_100).is_ok());
+        assert!(verify("secret", &sig, ts, Some(&resp_sig), br#"{"a":2}"#, 1_700_000_100).is_err());
+        assert!(verify("other", &sig, ts, Some(&resp_sig), br#"{"a":1}"#, 1_700_000_100).is_err());
+        // Response của request khác (replay trong cửa sổ 300s)
+        assert!(verify("secret", "00ff", ts, Some(&resp_sig), br#"{"a":1}"#, 1_700_000_100).is_err());
+        // Response quá cũ
+        assert!(verify("secret", &sig, ts, Some(&resp_sig), br#"{"a":1}"#, 1_700_001_000).is_err());
+        assert!(verify("secret", &sig, None, Some(&resp_sig), br#"{"a":1}"#, 1_700_000_000).is_err());
+        // Secret rỗng: ai cũng ký được → không bao giờ chấp nhận
+        let mut http = Config::default().http;
+        http.verify_response = true;
+        let resp = ureq::Response::new(200, "OK", r#"{"cmd":"uart_tx","data":"x"}"#).unwrap();
+        assert!(read_response(resp, &http, None).is_err());
+    }
+}
@@ -1,12 +1,25 @@
 //! Các kênh truyền dữ liệu (outbound + bidirectional)
-//! MQTT: publish dữ liệu UART tới broker
-//! HTTP: POST dữ liệu UART tới server
+//! MQTT: publish dữ liệu UART tới broker (3.1.1 hoặc 5)
+//! HTTP: POST dữ liệu UART tới server, poll lệnh downlink
 //! TCP: server + client song hướng (gửi dữ liệu + nhận lệnh)
 //! Buffer: lưu dữ liệu offline khi mất kết nối
 //! Reconnect: tự kết nối lại với exponential backoff
+//! TLS: CA riêng, chứng chỉ client (mTLS), pin fingerprint cho MQTT
+//! Mở rộng MQTT: Home Assistant discovery, device shadow, Sparkplug B, ThingsBoard gateway,
+//! Azure IoT Hub
 
+pub mod azure;
 pub mod buffer;
+pub mod ha_discovery;
+pub mod http_poll;
 pub mod http_pub;
 pub mod mqtt;
+pub mod mqtt_client;
 pub mod reconnect;
+pub mod shadow;
+pub mod sparkplug;
+pub mod sparkplug_pb;
 pub mod tcp;
+pub mod thingsboard;
+pub mod tls;
+pub mod topic;
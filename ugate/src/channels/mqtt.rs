@@ -1,36 +1,169 @@
 //! Kênh MQTT publisher chạy trên OS thread riêng (sync)
 //! Dùng rumqttc sync Client vì AsyncClient có vấn đề trên MIPS
-//! Hỗ trợ: TLS (rustls), auth (username/password), QoS cấu hình được
+//! Hỗ trợ: TLS (rustls, CA riêng + mTLS), auth (username/password), QoS cấu hình được
 //! Tự động reconnect khi mất kết nối hoặc thay đổi config
+//! Store-and-forward: mọi message đi qua OfflineBuffer, mất kết nối thì giữ lại và
+//! replay theo thứ tự sau ConnAck. QoS 1/2 chỉ tính published khi broker PubAck/PubComp
+//! Presence (status_topic): "online" retained sau ConnAck, LWT "offline" retained,
+//! publish "offline" chủ động khi tắt êm hoặc kết nối lại do đổi config
+//! Giao thức 3.1.1 hoặc 5 (xem mqtt_client). MQTT 5: lệnh có response topic được phản hồi
+//! kèm correlation data, reason code từ broker hiện trong log + status
+//! Topic: template + rule theo nội dung frame (xem topic.rs), topic lưu cùng message trong buffer
+//! Nhiều broker: mỗi kết nối (section `mqtt` + các `mqtt_broker`) 1 thread, 1 buffer, 1 bộ đếm
+//! riêng — broker chậm/mất kết nối không chặn các broker khác
+//! Home Assistant (mqtt.ha_discovery): discovery config retained sau ConnAck, state GPIO/hệ
+//! thống retained, lệnh switch → Command::Gpio (xem ha_discovery.rs)
+//! Device shadow (mqtt.shadow_topic): get desired sau mỗi lần kết nối, áp phần khác biệt
+//! qua Command::Gpio / AppState::update, publish reported (xem shadow.rs)
+//! Sparkplug B (mqtt.sparkplug): dữ liệu UART thành DDATA, NBIRTH/DBIRTH sau kết nối, LWT là
+//! NDEATH thay cho presence, NCMD/DCMD → Command (xem sparkplug.rs)
+//! ThingsBoard (mqtt.preset): dữ liệu UART thành telemetry của sub-device qua Gateway API,
+//! RPC → Command + phản hồi (xem thingsboard.rs)
+//! Azure IoT Hub (mqtt.preset): kết nối theo connection string, SAS token làm mới bằng cách
+//! kết nối lại trước khi hết hạn, direct method → Command + phản hồi (xem azure.rs)
 
-use crate::config::AppState;
-use rumqttc::{Client, MqttOptions, QoS, Transport};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use crate::channels::azure::{self, Hub};
+use crate::channels::buffer::{OfflineBuffer, Stamped};
+use crate::channels::ha_discovery::{self, Discovery};
+use crate::channels::shadow::{self, Incoming, Shadow};
+use crate::channels::sparkplug::{self, Action, Node};
+use crate::channels::thingsboard::{self, Gateway};
+use crate::channels::mqtt_client::{self, ConnectParams, MqttClient, NetEvent, PublishError, PublishProps, Will};
+use crate::channels::topic::{self, Routed, TopicContext};
+use crate::config::{AppState, MqttPreset, MqttVersion};
+use crate::watchdog::Task;
+use crate::web_api::status::{MqttStats, SharedStats};
+use rumqttc::QoS;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-/// Chạy MQTT publisher trong vòng lặp vô hạn
+/// Số kết nối MQTT tối đa (section chính + 3 broker phụ)
+pub const MAX_BROKERS: usize = 4;
+
+/// Số MQTT thread chưa xả xong khi tắt — thread cuối cùng báo shutdown::Part::Mqtt
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Message đã publish nhưng broker chưa xác nhận — trả lại buffer nếu mất kết nối
+#[derive(Default)]
+struct Inflight {
+    /// Đã gọi publish, chưa ra socket (chưa có pkid), theo thứ tự gửi.
+    /// None = message hệ thống (presence, phản hồi lệnh) không thuộc buffer
+    queued: VecDeque<Option<Stamped>>,
+    /// Đã ra socket, chờ PubAck (QoS 1) / PubComp (QoS 2)
+    sent: Vec<(u16, Stamped)>,
+}
+
+/// 1 lần publish (topic + payload + thuộc tính MQTT 5)
+struct Outbound<'a> {
+    topic: &'a str,
+    qos: QoS,
+    retain: bool,
+    payload: Vec<u8>,
+    props: PublishProps,
+}
+
+impl Inflight {
+    fn len(&self) -> usize {
+        self.queued.iter().flatten().count() + self.sent.len()
+    }
+
+    /// Lấy lại toàn bộ theo thứ tự gửi để replay
+    fn take_all(&mut self) -> Vec<Stamped> {
+        let mut all: Vec<Stamped> = self.sent.drain(..).map(|(_, e)| e).collect();
+        all.extend(self.queued.drain(..).flatten());
+        all
+    }
+
+    /// Publish khi đang giữ lock để thứ tự `queued` khớp thứ tự gói ra socket
+    /// (IO thread cũng publish phản hồi lệnh). Lỗi → trả lại entry
+    fn publish(&mut self, client: &MqttClient, entry: Option<Stamped>, msg: Outbound) -> Result<(), (Option<Stamped>, PublishError)> {
+        match client.try_publish(msg.topic, msg.qos, msg.retain, msg.payload, msg.props) {
+            Ok(()) => {
+                self.queued.push_back(entry);
+                Ok(())
+            }
+            Err(e) => Err((entry, e)),
+        }
+    }
+}
+
+/// main gọi trước khi spawn các MQTT thread
+pub fn set_broker_count(n: usize) {
+    RUNNING.store(n, Ordering::Relaxed);
+}
+
+/// Tiền tố log của kết nối: "[MQTT]" (section chính) hoặc "[MQTT:cloud2]"
+fn log_tag(slot: usize, name: &str) -> String {
+    if slot == 0 {
+        "[MQTT]".to_string()
+    } else {
+        format!("[MQTT:{}]", name)
+    }
+}
+
+/// Chạy MQTT publisher cho kết nối thứ `slot` (xem Config::mqtt_slot) trong vòng lặp vô hạn
 /// Tự khởi động lại khi lỗi hoặc config thay đổi
 pub fn run_sync(
     state: Arc<AppState>,
-    data_rx: std::sync::mpsc::Receiver<Vec<u8>>,
-    config_rx: std::sync::mpsc::Receiver<()>,
+    slot: usize,
+    data_rx: &Receiver<Routed>,
+    config_rx: &Receiver<()>,
     cmd_tx: std::sync::mpsc::Sender<crate::commands::Command>,
-    stats: Arc<crate::web_api::status::SharedStats>,
+    stats: Arc<SharedStats>,
 ) {
+    let (bcfg, name) = {
+        let config = state.get();
+        let name = config.mqtt_slot(slot).map(|m| m.name.clone()).unwrap_or_default();
+        (config.buffer, name)
+    };
+    let tag = log_tag(slot, &name);
+    let counters = &stats.mqtt[slot];
+    // Mỗi broker 1 thư mục buffer: mqtt (chính), mqtt-<tên> (phụ)
+    let dir = if slot == 0 { "mqtt".to_string() } else { format!("mqtt-{}", name) };
+    let mut buffer = OfflineBuffer::new(bcfg.ram_msgs, std::path::Path::new(&bcfg.dir).join(dir))
+        .with_limits(bcfg.max_disk_kb as u64 * 1024, bcfg.max_age_secs as u64);
+    if !buffer.is_empty() {
+        log::info!("{} {} message chờ gửi từ lần chạy trước", tag, buffer.total());
+    }
+    update_buffer_stats(&buffer, counters);
+
     loop {
+        if crate::shutdown::is_shutting_down() {
+            // Giữ dữ liệu chưa gửi được cho lần chạy sau
+            absorb(data_rx, &mut buffer);
+            if !buffer.is_empty() {
+                buffer.flush_to_disk();
+                log::info!("{} Lưu {} message chưa gửi ra disk", tag, buffer.total());
+            }
+            update_buffer_stats(&buffer, counters);
+            if RUNNING.fetch_sub(1, Ordering::Relaxed) <= 1 {
+                crate::shutdown::done(crate::shutdown::Part::Mqtt);
+            }
+            return;
+        }
         let config = state.get();
-        if !config.mqtt.enabled {
-            stats.mqtt_state.store(0, Ordering::Relaxed); // disabled
-            let _ = config_rx.recv();
+        // Section bị xoá khi đang chạy → coi như tắt
+        if !config.mqtt_slot(slot).is_some_and(|m| m.enabled) {
+            counters.state.store(0, Ordering::Relaxed); // disabled
+            // Chờ config thay đổi, vẫn báo tiến độ mỗi giây cho watchdog
+            while !crate::shutdown::is_shutting_down() {
+                stats.tasks.beat(Task::Mqtt(slot as u8));
+                match config_rx.recv_timeout(Duration::from_secs(1)) {
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                    _ => break,
+                }
+            }
             while data_rx.try_recv().is_ok() {}
             continue;
         }
-        stats.mqtt_state.store(1, Ordering::Relaxed); // disconnected
-        if let Err(e) = run_publish_loop(&state, &data_rx, &config_rx, &cmd_tx, &stats) {
-            log::error!("[MQTT] Lỗi: {}. Thử lại sau 10s...", e);
-            stats.mqtt_state.store(1, Ordering::Relaxed);
-            std::thread::sleep(Duration::from_secs(10));
+        counters.state.store(1, Ordering::Relaxed); // disconnected
+        if let Err(e) = run_publish_loop(&state, slot, data_rx, config_rx, &cmd_tx, &stats, &mut buffer) {
+            log::error!("{} Lỗi: {}. Thử lại sau 10s ({} message trong buffer)...", tag, e, buffer.total());
+            counters.state.store(1, Ordering::Relaxed);
+            wait_buffering(data_rx, &mut buffer, &stats, slot, Duration::from_secs(10));
         }
     }
 }
@@ -38,48 +171,112 @@ pub fn run_sync(
 /// Vòng lặp publish chính: kết nối broker, nhận dữ liệu từ channel, publish
 fn run_publish_loop(
     state: &AppState,
-    data_rx: &std::sync::mpsc::Receiver<Vec<u8>>,
-    config_rx: &std::sync::mpsc::Receiver<()>,
+    slot: usize,
+    data_rx: &Receiver<Routed>,
+    config_rx: &Receiver<()>,
     cmd_tx: &std::sync::mpsc::Sender<crate::commands::Command>,
-    stats: &crate::web_api::status::SharedStats,
+    stats: &Arc<SharedStats>,
+    buffer: &mut OfflineBuffer,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let config = state.get();
+    let mut mqtt = config.mqtt_slot(slot).ok_or("Không còn section broker")?.clone();
+    let tag = log_tag(slot, &mqtt.name);
+    let counters = &stats.mqtt[slot];
 
-    // Client ID ngẫu nhiên mỗi lần connect — broker không giữ session cũ
-    let random_suffix: u32 = (std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.as_nanos())
-        .unwrap_or(0) as u32)
-        ^ (std::process::id() << 16);
-    let client_id = format!("{}-{:08x}", config.general.device_name, random_suffix);
+    // Azure IoT Hub: broker, client ID, username, SAS token lấy từ connection string
+    let azure = match mqtt.preset {
+        MqttPreset::Azure => Some(Hub::parse(&mqtt.azure_connection_string, mqtt.azure_sas_ttl_secs)?),
+        _ => None,
+    };
+    let token_refresh_at = azure.as_ref().map(|hub| {
+        let (token, expiry) = hub.sas_token(now_secs());
+        hub.apply(&mut mqtt, token);
+        log::info!("{} Azure IoT Hub: SAS token hết hạn lúc {} (unix)", tag, expiry);
+        Instant::now() + hub.refresh_after()
+    });
+
+    // Client ID cố định giữa các lần connect để broker nhận ra session cũ
+    // (broker phụ thêm hậu tố tên để không đá nhau khi cùng 1 broker)
+    let client_id = match (mqtt.client_id.is_empty(), slot) {
+        (false, _) => mqtt.client_id.clone(),
+        (true, 0) => default_client_id(&config.general.device_name),
+        (true, _) => format!("{}-{}", default_client_id(&config.general.device_name), mqtt.name),
+    };
     // Lưu client_id để UI hiển thị
-    *stats.mqtt_client_id.lock().unwrap() = client_id.clone();
+    *counters.client_id.lock().unwrap() = client_id.clone();
 
-    let mut opts = MqttOptions::new(&client_id, &config.mqtt.broker, config.mqtt.port);
-    opts.set_keep_alive(Duration::from_secs(30));
+    let max_inflight = mqtt.inflight as usize;
 
-    // Xác thực (tuỳ chọn)
-    if !config.mqtt.username.is_empty() {
-        opts.set_credentials(&config.mqtt.username, &config.mqtt.password);
-    }
+    // TLS qua rustls (không phụ thuộc OpenSSL): CA hệ thống/riêng, mTLS, pin fingerprint
+    let tls = if mqtt.tls {
+        let tls_config = crate::channels::tls::build_client_config(&mqtt)
+            .map_err(|e| format!("TLS: {}", e))?;
+        Some(tls_config)
+    } else {
+        None
+    };
 
-    // TLS qua rustls (không phụ thuộc OpenSSL)
-    if config.mqtt.tls {
-        let root_store = rustls::RootCertStore {
-            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
-        };
-        let tls_config = rustls::ClientConfig::builder()
-            .with_root_certificates(root_store)
-            .with_no_client_auth();
-        opts.set_transport(Transport::tls_with_config(rumqttc::TlsConfiguration::Rustls(
-            Arc::new(tls_config),
-        )));
+    // Broker tự publish "offline" khi gateway mất kết nối đột ngột
+    // Home Assistant cần availability: chưa cấu hình status_topic thì dùng ugate/<node>/status
+    let status_topic = match topic::render(&mqtt.status_topic, &TopicContext::device(&config)) {
+        t if t.is_empty() && mqtt.ha_discovery => Discovery::default_status_topic(&config),
+        t => t,
+    };
+    let ha = mqtt.ha_discovery.then(|| Arc::new(Discovery::new(&config, &mqtt, &status_topic)));
+    // Sparkplug: NBIRTH/NDEATH thay cho presence → không birth/offline lên status_topic
+    let status_topic = if mqtt.sparkplug { String::new() } else { status_topic };
+    // Home Assistant khởi động lại (birth "online") → IO thread bật cờ publish lại discovery
+    let ha_republish = Arc::new(AtomicBool::new(false));
+    let shadow_topic = topic::render(&mqtt.shadow_topic, &TopicContext::device(&config));
+    let mut shadow = (!shadow_topic.is_empty()).then(|| Shadow::new(&shadow_topic));
+    let mut sparkplug = mqtt.sparkplug.then(|| Node::new(&config, &mqtt, slot));
+    let mut thingsboard = (mqtt.preset == MqttPreset::ThingsBoard).then(|| Gateway::new(&config, &mqtt));
+    // Message shadow/Sparkplug/RPC ThingsBoard: IO thread chuyển về vòng lặp chính (áp cấu hình có ghi UCI,
+    // rebirth cần seq của node — không làm ở IO thread)
+    let mut inbox_prefixes = Vec::new();
+    if shadow.is_some() {
+        inbox_prefixes.push(format!("{}/", shadow_topic.trim_end_matches('/')));
+    }
+    if let Some(node) = &sparkplug {
+        inbox_prefixes.extend(node.command_prefixes());
     }
+    if thingsboard.is_some() {
+        inbox_prefixes.push(thingsboard::RPC.to_string());
+    }
+    let (inbox_tx, inbox_rx) = std::sync::mpsc::channel::<(String, Vec<u8>)>();
+    // Sparkplug: LWT là NDEATH (không retained), thay cho presence
+    let will = match &sparkplug {
+        Some(node) => {
+            let (topic, payload) = node.death();
+            Some(Will { topic, payload, retain: false })
+        }
+        None => (!status_topic.is_empty()).then(|| Will {
+            topic: status_topic.clone(),
+            payload: presence_payload(&config.general.device_name, false, "").into_bytes(),
+            retain: true,
+        }),
+    };
 
-    let (client, mut connection) = Client::new(opts, 10);
+    // MQTT 5: metadata thiết bị gắn vào CONNECT + mọi PUBLISH
+    let mut user_properties = vec![
+        ("device".to_string(), config.general.device_name.clone()),
+        ("fw".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+    ];
+    user_properties.extend(mqtt.user_properties.iter().cloned());
 
-    let proto = if config.mqtt.tls { "MQTTS" } else { "MQTT" };
-    log::info!("[MQTT] Đang kết nối {}:{} ({})...", config.mqtt.broker, config.mqtt.port, proto);
+    // clean_session=false: broker giữ subscription + xếp hàng lệnh QoS 1/2 khi gateway offline
+    let (client, connection) = mqtt_client::connect(
+        &mqtt,
+        ConnectParams { client_id, will, tls, user_properties },
+    );
+
+    let proto = match (mqtt.tls, mqtt.version) {
+        (true, MqttVersion::V5) => "MQTTS v5",
+        (true, MqttVersion::V311) => "MQTTS",
+        (false, MqttVersion::V5) => "MQTT v5",
+        (false, MqttVersion::V311) => "MQTT",
+    };
+    log::info!("{} Đang kết nối {}:{} ({})...", tag, mqtt.broker, mqtt.port, proto);
 
     // Theo dõi trạng thái kết nối: 0=đang kết nối, 1=đã kết nối, 2=mất kết nối
     let conn_state = Arc::new(std::sync::atomic::AtomicU8::new(0));
@@ -89,51 +286,151 @@ fn run_publish_loop(
     let io_stop = Arc::new(AtomicBool::new(false));
     let io_stop_clone = io_stop.clone();
 
+    // Chuyển QoS từ config (Sparkplug B quy định QoS 0 cho dữ liệu, subscribe lệnh vẫn theo config)
+    let sub_qos = match mqtt.qos {
+        0 => QoS::AtMostOnce,
+        2 => QoS::ExactlyOnce,
+        _ => QoS::AtLeastOnce,
+    };
+    let qos = if mqtt.sparkplug { QoS::AtMostOnce } else { sub_qos };
+
+    let inflight = Arc::new(Mutex::new(Inflight::default()));
+    let inflight_io = inflight.clone();
+    let stats_io = stats.clone();
+    let tag_io = tag.clone();
+    let client_io = client.clone();
+    let ha_io = ha.clone();
+    let ha_republish_io = ha_republish.clone();
+    let azure_io = azure.is_some();
+
     // Thread xử lý I/O mạng cho MQTT + nhận message từ subscribe topic
     let cmd_tx_clone = cmd_tx.clone();
     std::thread::spawn(move || {
-        for notification in connection.iter() {
+        connection.run(|event| {
             if io_stop_clone.load(Ordering::Relaxed) {
-                break;
+                return false;
             }
-            match notification {
-                Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_))) => {
-                    log::info!("[MQTT] Đã kết nối!");
+            match event {
+                NetEvent::ConnAck { session_present } => {
+                    log::info!("{} Đã kết nối! (session cũ: {})", tag_io, session_present);
                     conn_state_clone.store(1, Ordering::Relaxed);
                 }
                 // Xử lý message nhận từ subscribe topic → chuyển thành Command
-                Ok(rumqttc::Event::Incoming(rumqttc::Packet::Publish(msg))) => {
+                NetEvent::Message { topic, payload, response_topic, correlation_data } => {
                     // Giới hạn 10KB — command JSON chỉ vài trăm bytes, tránh OOM
-                    if msg.payload.len() > 10240 {
-                        log::warn!("[MQTT] Bỏ qua message quá lớn: {} bytes", msg.payload.len());
-                        continue;
+                    if payload.len() > 10240 {
+                        log::warn!("{} Bỏ qua message quá lớn: {} bytes", tag_io, payload.len());
+                        return true;
+                    }
+                    if inbox_prefixes.iter().any(|p| topic.starts_with(p.as_str())) {
+                        let _ = inbox_tx.send((topic, payload));
+                        return true;
+                    }
+                    let payload = String::from_utf8_lossy(&payload);
+                    log::debug!("{} Nhận từ '{}': {}", tag_io, topic, payload);
+                    // Azure direct method: chuyển lệnh cho dispatcher + phản hồi status 202/400 theo $rid
+                    if let Some((method, rid)) = azure::method_request(&topic).filter(|_| azure_io) {
+                        let (status, reply) = match azure::method_command(method, &payload) {
+                            Some(cmd) => {
+                                let reply = cmd.accepted_json();
+                                let _ = cmd_tx_clone.send(cmd);
+                                (202, reply)
+                            }
+                            None => (400, format!(
+                                r#"{{"accepted":false,"error":"unknown method or params '{}'"}}"#,
+                                crate::web_api::json_escape(method)
+                            )),
+                        };
+                        let reply_topic = azure::method_response_topic(status, rid);
+                        let msg = Outbound {
+                            topic: &reply_topic,
+                            qos: QoS::AtLeastOnce,
+                            retain: false,
+                            payload: reply.into_bytes(),
+                            props: PublishProps::default(),
+                        };
+                        if let Err((_, e)) = inflight_io.lock().unwrap().publish(&client_io, None, msg) {
+                            log::warn!("{} Không gửi được phản hồi direct method '{}': {}", tag_io, method, e);
+                        }
+                        return true;
+                    }
+                    if let Some(ha) = &ha_io {
+                        if topic == ha.birth_topic() {
+                            if payload.trim() == "online" {
+                                ha_republish_io.store(true, Ordering::Relaxed);
+                            }
+                            return true;
+                        }
+                        if let Some(cmd) = ha.parse_command(&topic, &payload) {
+                            let _ = cmd_tx_clone.send(cmd);
+                            return true;
+                        }
                     }
-                    let payload = String::from_utf8_lossy(&msg.payload);
-                    log::debug!("[MQTT] Nhận từ '{}': {}", msg.topic, payload);
-                    if let Some(cmd) = crate::commands::parse_json_command(&payload) {
-                        let _ = cmd_tx_clone.send(cmd);
-                    } else {
-                        // Nếu không phải JSON command, gửi raw xuống UART
-                        let _ = cmd_tx_clone.send(crate::commands::Command::UartTx {
-                            data: payload.into_owned(),
-                        });
+                    // Nếu không phải JSON command, gửi raw xuống UART
+                    let cmd = crate::commands::parse_json_command(&payload).unwrap_or_else(|| {
+                        crate::commands::Command::UartTx { data: payload.into_owned() }
+                    });
+                    let reply = cmd.accepted_json();
+                    let _ = cmd_tx_clone.send(cmd);
+                    // MQTT 5 request/response: phản hồi tới response topic kèm correlation data
+                    if let Some(reply_topic) = response_topic {
+                        let msg = Outbound {
+                            topic: &reply_topic,
+                            qos: QoS::AtLeastOnce,
+                            retain: false,
+                            payload: reply.into_bytes(),
+                            props: PublishProps { correlation_data, ..Default::default() },
+                        };
+                        if let Err((_, e)) = inflight_io.lock().unwrap().publish(&client_io, None, msg) {
+                            log::warn!("{} Không gửi được phản hồi tới '{}': {}", tag_io, reply_topic, e);
+                        }
                     }
                 }
-                Ok(_) => {
+                // Publish đã ra socket: QoS 0 coi như xong, QoS 1/2 chờ ack theo pkid
+                NetEvent::PublishSent(pkid) => {
+                    let mut inf = inflight_io.lock().unwrap();
+                    if let Some(Some(entry)) = inf.queued.pop_front() {
+                        if qos == QoS::AtMostOnce {
+                            stats_io.mqtt[slot].published.fetch_add(1, Ordering::Relaxed);
+                        } else {
+                            inf.sent.push((pkid, entry));
+                        }
+                    }
+                }
+                NetEvent::Acked { pkid, rejected } => {
+                    acknowledge(&inflight_io, pkid, rejected, &stats_io.mqtt[slot], &tag_io);
+                }
+                // Broker chủ động ngắt (MQTT 5): ghi lại reason code
+                NetEvent::ServerDisconnect(reason) => {
+                    log::error!("{} Broker ngắt kết nối: {}", tag_io, reason);
+                    set_last_reason(&stats_io.mqtt[slot], &format!("disconnect: {}", reason));
+                    conn_state_clone.store(2, Ordering::Relaxed);
+                    return false;
+                }
+                // DISCONNECT đã ghi ra socket → IO thread xong việc
+                NetEvent::DisconnectSent => {
+                    conn_state_clone.store(2, Ordering::Relaxed);
+                    return false;
+                }
+                NetEvent::Other => {
                     std::thread::sleep(Duration::from_millis(1));
                 }
-                Err(e) => {
-                    log::error!("[MQTT] Lỗi kết nối: {}", e);
+                NetEvent::Error(e) => {
+                    log::error!("{} Lỗi kết nối: {}", tag_io, e);
+                    set_last_reason(&stats_io.mqtt[slot], &e);
                     conn_state_clone.store(2, Ordering::Relaxed);
-                    return;
+                    return false;
                 }
             }
-        }
+            true
+        });
         conn_state_clone.store(2, Ordering::Relaxed);
     });
 
     // Chờ ConnAck tối đa 10 giây
     for _ in 0..100 {
+        stats.tasks.beat(Task::Mqtt(slot as u8));
+        absorb(data_rx, buffer);
         match conn_state.load(Ordering::Relaxed) {
             1 => break,
             2 => return Err("Kết nối thất bại".into()),
@@ -143,52 +440,323 @@ fn run_publish_loop(
     if conn_state.load(Ordering::Relaxed) != 1 {
         return Err("Hết thời gian chờ kết nối".into());
     }
-    stats.mqtt_state.store(2, Ordering::Relaxed); // connected
+    counters.state.store(2, Ordering::Relaxed); // connected
 
-    // Chuyển QoS từ config
-    let qos = match config.mqtt.qos {
-        0 => QoS::AtMostOnce,
-        2 => QoS::ExactlyOnce,
-        _ => QoS::AtLeastOnce,
-    };
-
-    // Subscribe topic để nhận lệnh từ broker → MCU
-    if !config.mqtt.sub_topic.is_empty() {
-        match client.subscribe(&config.mqtt.sub_topic, qos) {
-            Ok(()) => log::info!("[MQTT] Subscribe '{}'", config.mqtt.sub_topic),
-            Err(e) => log::error!("[MQTT] Subscribe lỗi: {}", e),
+    // Subscribe các topic nhận lệnh từ broker → MCU (cho phép wildcard)
+    let mut subs = topic::subscribe_topics(&config, &mqtt);
+    if let Some(ha) = &ha {
+        subs.push(ha.command_filter());
+        subs.push(ha.birth_topic());
+    }
+    if let Some(shadow) = &shadow {
+        subs.extend(shadow.subscriptions());
+    }
+    if let Some(node) = &sparkplug {
+        subs.extend(node.command_prefixes());
+    }
+    if thingsboard.is_some() {
+        subs.push(thingsboard::RPC.to_string());
+    }
+    if let Some(hub) = &azure {
+        subs.extend(hub.subscriptions());
+    }
+    for sub in subs {
+        match client.subscribe(&sub, sub_qos) {
+            Ok(()) => log::info!("{} Subscribe '{}'", tag, sub),
+            Err(e) => log::error!("{} Subscribe '{}' lỗi: {}", tag, sub, e),
         }
     }
 
-    log::info!("[MQTT] Publish tới '{}' (QoS={})", config.mqtt.topic, config.mqtt.qos);
+    // Birth message: báo online (retained) cho dashboard
+    if !status_topic.is_empty() {
+        let ip = local_ip(&mqtt.broker, mqtt.port);
+        let birth = presence_payload(&config.general.device_name, true, &ip);
+        publish_presence(&client, &inflight, &status_topic, birth, &tag);
+    }
+
+    // Message hệ thống (Sparkplug, discovery, state HA, shadow): hàng đợi request của rumqttc
+    // nhỏ (= inflight) nên xếp vào `pending`, mỗi vòng lặp gửi được bao nhiêu thì gửi
+    let mut pending: VecDeque<Pending> = VecDeque::new();
+    // Sparkplug: NBIRTH (+ DBIRTH) phải đi trước mọi NDATA/DDATA
+    let mut device_up = stats.uart_state.load(Ordering::Relaxed) == 2;
+    let mut ndata_signature = stats.gpio_signature();
+    let mut ndata_at = Instant::now();
+    if let Some(node) = &mut sparkplug {
+        log::info!("{} Sparkplug B: NBIRTH{}", tag, if device_up { " + DBIRTH" } else { "" });
+        queue_messages(node.birth(&config, stats, device_up), QoS::AtMostOnce, &mut pending);
+    }
+    // Telemetry: publish thẳng mỗi interval_secs, không qua buffer (mất kết nối thì bỏ, không
+    // replay trạng thái cũ). Azure: lên topic events như dữ liệu
+    let telemetry_topic = match &azure {
+        _ if mqtt.telemetry_topic.is_empty() || config.general.interval_secs == 0 => None,
+        Some(hub) => Some(hub.events_topic()),
+        None => Some(topic::render(&mqtt.telemetry_topic, &TopicContext::device(&config))),
+    };
+    let telemetry_every = Duration::from_secs(config.general.interval_secs);
+    let mut telemetry_at = Instant::now();
+    let mut ha_signature = None;
+    let mut ha_state_at = Instant::now();
+    if let Some(ha) = &ha {
+        queue_discovery(ha, &config, &mqtt, &mut pending);
+        log::info!("{} Home Assistant discovery: {} entity", tag, pending.len());
+    }
+    // Shadow: lấy desired (kể cả phần đặt khi gateway offline), reported gửi khi GPIO đổi
+    // hoặc sau khi áp desired (chờ GPIO task đổi mức xong)
+    let mut shadow_signature = stats.gpio_signature();
+    let mut shadow_report_at: Option<Instant> = None;
+    if let Some(shadow) = &shadow {
+        log::info!("{} Shadow '{}': đồng bộ desired", tag, shadow_topic);
+        pending.push_back((shadow.get_topic(), b"{}".to_vec(), QoS::AtLeastOnce, false));
+    }
+
+    let default_topic = topic::render(&mqtt.topic, &TopicContext::device(&config));
+    log::info!(
+        "{} Publish tới '{}' (QoS={}, {} topic rule)",
+        tag, default_topic, mqtt.qos, mqtt.topic_rules.len()
+    );
+    if !buffer.is_empty() {
+        log::info!("{} Replay {} message trong buffer", tag, buffer.total());
+    }
 
-    let topic = config.mqtt.topic.clone();
+    // Trả message chưa được ack về buffer trước khi rời vòng lặp
+    let requeue = |buffer: &mut OfflineBuffer| {
+        buffer.requeue(inflight.lock().unwrap().take_all());
+        update_buffer_stats(buffer, counters);
+    };
 
     loop {
-        // Nhận dữ liệu với timeout ngắn để phản hồi config nhanh
-        match data_rx.recv_timeout(Duration::from_millis(100)) {
-            Ok(data) => {
-                log::debug!("[MQTT] Gửi {} bytes", data.len());
-                if let Err(e) = client.publish(&topic, qos, false, data) {
-                    log::error!("[MQTT] Lỗi publish: {}", e);
-                    stats.mqtt_failed.fetch_add(1, Ordering::Relaxed);
-                } else {
-                    stats.mqtt_published.fetch_add(1, Ordering::Relaxed);
-                }
-            }
-            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
-            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+        stats.tasks.beat(Task::Mqtt(slot as u8));
+        // Nhận dữ liệu mới vào buffer: chờ tối đa 100ms nếu không có gì để gửi
+        let idle = buffer.is_empty() || inflight.lock().unwrap().len() >= max_inflight;
+        let received = if idle {
+            data_rx.recv_timeout(Duration::from_millis(100))
+        } else {
+            data_rx.try_recv().map_err(|e| match e {
+                TryRecvError::Empty => RecvTimeoutError::Timeout,
+                TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+            })
+        };
+        match received {
+            Ok(routed) => {
+                buffer.push(topic::pack(&routed));
+                absorb(data_rx, buffer);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
                 io_stop.store(true, Ordering::Relaxed);
                 let _ = client.disconnect();
+                requeue(buffer);
                 return Err("Kênh dữ liệu đã đóng".into());
             }
         }
 
+        // Home Assistant: discovery khi HA khởi động lại, state khi GPIO đổi mức hoặc định kỳ
+        if let Some(ha) = &ha {
+            if ha_republish.swap(false, Ordering::Relaxed) {
+                log::info!("{} Home Assistant online, publish lại discovery", tag);
+                queue_discovery(ha, &config, &mqtt, &mut pending);
+                ha_signature = None;
+            }
+            let signature = stats.gpio_signature();
+            if ha_signature != Some(signature) || ha_state_at.elapsed() >= ha_discovery::STATE_EVERY {
+                ha_signature = Some(signature);
+                ha_state_at = Instant::now();
+                pending.push_back((ha.state_topic(), stats.to_ha_state_json(&config).into_bytes(), QoS::AtLeastOnce, true));
+            }
+        }
+
+        if let Some(telemetry_topic) = &telemetry_topic {
+            if telemetry_at.elapsed() >= telemetry_every {
+                telemetry_at = Instant::now();
+                pending.push_back((telemetry_topic.clone(), stats.to_telemetry_json(&config).into_bytes(), qos, false));
+            }
+        }
+
+        while let Ok((inbox_topic, payload)) = inbox_rx.try_recv() {
+            if let Some(node) = &mut sparkplug {
+                for action in node.handle(&inbox_topic, &payload) {
+                    match action {
+                        Action::Rebirth => {
+                            log::info!("{} Sparkplug: yêu cầu rebirth", tag);
+                            queue_messages(node.birth(&config, stats, device_up), QoS::AtMostOnce, &mut pending);
+                        }
+                        Action::Command(cmd) => {
+                            let _ = cmd_tx.send(cmd);
+                        }
+                    }
+                }
+            }
+            if thingsboard.is_some() && inbox_topic == thingsboard::RPC {
+                let gpio = gpio_levels(stats, &config);
+                match thingsboard::handle_rpc(&String::from_utf8_lossy(&payload), &gpio) {
+                    Some(rpc) => {
+                        if let Some(cmd) = rpc.command {
+                            let _ = cmd_tx.send(cmd);
+                        }
+                        queue_messages(vec![rpc.reply], QoS::AtLeastOnce, &mut pending);
+                    }
+                    None => log::warn!("{} ThingsBoard: RPC không hợp lệ", tag),
+                }
+            }
+            if let Some(shadow) = &mut shadow {
+                match shadow.handle(&inbox_topic, &String::from_utf8_lossy(&payload)) {
+                    Incoming::Desired(desired) => {
+                        apply_desired(state, stats, cmd_tx, &desired, &tag);
+                        shadow_report_at = Some(Instant::now() + Duration::from_millis(300));
+                    }
+                    Incoming::Rejected(reason) => {
+                        log::warn!("{} Shadow từ chối reported: {}, đồng bộ lại", tag, reason);
+                        pending.push_back((shadow.get_topic(), b"{}".to_vec(), QoS::AtLeastOnce, false));
+                    }
+                    Incoming::Ignored => {}
+                }
+            }
+        }
+
+        // UART mất/có lại: Sparkplug DDEATH/DBIRTH, ThingsBoard disconnect sub-device
+        let up = stats.uart_state.load(Ordering::Relaxed) == 2;
+        if up != device_up {
+            device_up = up;
+            if let Some(node) = &mut sparkplug {
+                queue_messages(vec![if up { node.device_birth() } else { node.device_death() }], QoS::AtMostOnce, &mut pending);
+            }
+            if let (Some(tb), false) = (&mut thingsboard, up) {
+                queue_messages(tb.disconnect_all(), QoS::AtLeastOnce, &mut pending);
+            }
+        }
+        // Sparkplug: NDATA khi GPIO đổi hoặc định kỳ
+        if let Some(node) = &mut sparkplug {
+            let signature = stats.gpio_signature();
+            if signature != ndata_signature || ndata_at.elapsed() >= sparkplug::NDATA_EVERY {
+                ndata_signature = signature;
+                ndata_at = Instant::now();
+                queue_messages(vec![node.node_data(&config, stats)], QoS::AtMostOnce, &mut pending);
+            }
+        }
+
+        if let Some(shadow) = &shadow {
+            let signature = stats.gpio_signature();
+            if signature != shadow_signature {
+                shadow_signature = signature;
+                shadow_report_at.get_or_insert_with(Instant::now);
+            }
+            if shadow_report_at.is_some_and(|at| Instant::now() >= at) {
+                shadow_report_at = None;
+                let current = state.get();
+                let reported = shadow.reported(&current, &gpio_levels(stats, &current));
+                pending.push_back((shadow.update_topic(), reported.into_bytes(), QoS::AtLeastOnce, false));
+            }
+        }
+        send_pending(&client, &inflight, &mut pending);
+
+        // Gửi từ buffer theo thứ tự, giới hạn số message chờ ack. Message hệ thống còn chờ
+        // (vd NBIRTH) thì dữ liệu đợi vòng sau. Sparkplug: sau DDEATH (UART mất) giữ DDATA
+        // trong buffer tới DBIRTH kế tiếp
+        let data_ready = sparkplug.is_none() || device_up;
+        while data_ready && pending.is_empty() && inflight.lock().unwrap().len() < max_inflight {
+            let entry = match buffer.pop_stamped() {
+                Some(e) => e,
+                None => break,
+            };
+            // Message expiry: phần thời gian sống còn lại tính từ lúc nhận từ UART
+            let message_expiry = match mqtt.message_expiry_secs {
+                0 => None,
+                ttl => {
+                    let age = now_secs().saturating_sub(entry.0);
+                    if age >= ttl as u64 {
+                        log::debug!("{} Bỏ message quá hạn ({}s)", tag, age);
+                        counters.failed.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                    Some(ttl - age as u32)
+                }
+            };
+            // Record cũ (trước khi có topic rule) → topic mặc định. Sparkplug: frame → DDATA,
+            // ThingsBoard: telemetry của sub-device (sub-device mới thì connect trước),
+            // Azure: mọi message lên topic events (bỏ topic rule)
+            let (routed_topic, payload) = topic::unpack(&entry.1);
+            let routed_topic = routed_topic.filter(|_| azure.is_none());
+            let (routed_topic, payload) = match (&sparkplug, &mut thingsboard) {
+                (Some(node), _) => node.device_data(payload, entry.0 * 1000),
+                (None, Some(tb)) => {
+                    let device = tb.device_name(&config, payload);
+                    let hello = tb.announce(&config, &device, payload);
+                    if !hello.is_empty() {
+                        log::info!("{} ThingsBoard: sub-device '{}' connect", tag, device);
+                        queue_messages(hello, QoS::AtLeastOnce, &mut pending);
+                        buffer.requeue(vec![entry]);
+                        break;
+                    }
+                    let telemetry = thingsboard::telemetry(&config, &device, payload, entry.0 * 1000);
+                    (thingsboard::TELEMETRY.to_string(), telemetry)
+                }
+                (None, None) => (routed_topic.unwrap_or(&default_topic).to_string(), payload.to_vec()),
+            };
+            log::debug!("{} Gửi {} bytes tới '{}'", tag, payload.len(), routed_topic);
+            let msg = Outbound {
+                topic: &routed_topic,
+                qos,
+                retain: false,
+                payload,
+                props: PublishProps { message_expiry, ..Default::default() },
+            };
+            let sent = inflight.lock().unwrap().publish(&client, Some(entry), msg);
+            match sent {
+                Ok(()) => {}
+                // Hàng đợi rumqttc đầy → để lần sau
+                Err((entry, PublishError::Busy)) => {
+                    log::debug!("{} Chưa publish được: hàng đợi đầy", tag);
+                    buffer.requeue(entry.into_iter().collect());
+                    break;
+                }
+                // Lỗi không gửi lại được → bỏ message, tính failed
+                Err((_, PublishError::Rejected(reason))) => {
+                    log::error!("{} Lỗi publish: {}", tag, reason);
+                    set_last_reason(counters, &format!("publish: {}", reason));
+                    counters.failed.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            }
+            if let Some(node) = &mut sparkplug {
+                node.advance();
+            }
+        }
+        update_buffer_stats(buffer, counters);
+
+        // Tắt êm: fan-out đã xả xong, buffer trống (hoặc đang giữ DDATA), broker đã ack hết → DISCONNECT
+        if crate::shutdown::is_shutting_down() {
+            let drained = crate::shutdown::is_done(crate::shutdown::Part::FanOut)
+                && (buffer.is_empty() || !data_ready)
+                && inflight.lock().unwrap().len() == 0;
+            if !drained && !crate::shutdown::deadline_passed() {
+                continue;
+            }
+            if !drained {
+                log::warn!("{} Hết thời gian xả hàng đợi, ngắt kết nối", tag);
+            }
+            announce_offline(&client, &inflight, &status_topic, &config.general.device_name, &tag);
+            farewell(&client, &inflight, sparkplug.as_ref(), thingsboard.as_mut());
+            disconnect_clean(&client, &conn_state, &tag);
+            requeue(buffer);
+            return Ok(());
+        }
+
+        // Azure: SAS token sắp hết hạn → kết nối lại với token mới (hub không nhận token mới
+        // trên kết nối đang mở qua MQTT). Message chưa ack trả về buffer, replay sau ConnAck
+        if token_refresh_at.is_some_and(|at| Instant::now() >= at) {
+            log::info!("{} Azure IoT Hub: làm mới SAS token, kết nối lại...", tag);
+            disconnect_clean(&client, &conn_state, &tag);
+            requeue(buffer);
+            return Ok(());
+        }
+
         // Kiểm tra thay đổi config
         if config_rx.try_recv().is_ok() {
-            log::info!("[MQTT] Config thay đổi, kết nối lại...");
-            io_stop.store(true, Ordering::Relaxed);
-            let _ = client.disconnect();
+            log::info!("{} Config thay đổi, kết nối lại...", tag);
+            announce_offline(&client, &inflight, &status_topic, &config.general.device_name, &tag);
+            farewell(&client, &inflight, sparkplug.as_ref(), thingsboard.as_mut());
+            disconnect_clean(&client, &conn_state, &tag);
+            requeue(buffer);
             return Ok(());
         }
 
@@ -196,7 +764,214 @@ fn run_publish_loop(
         if conn_state.load(Ordering::Relaxed) == 2 {
             io_stop.store(true, Ordering::Relaxed);
             let _ = client.disconnect();
+            requeue(buffer);
             return Err("Mất kết nối".into());
         }
     }
 }
+
+/// Broker xác nhận message (PubAck/PubComp) → mới tính là đã publish
+/// MQTT 5 broker từ chối (reason code lỗi, vd QuotaExceeded) → tính failed, không gửi lại
+fn acknowledge(inflight: &Mutex<Inflight>, pkid: u16, rejected: Option<String>, counters: &MqttStats, tag: &str) {
+    let mut inf = inflight.lock().unwrap();
+    if let Some(pos) = inf.sent.iter().position(|(id, _)| *id == pkid) {
+        inf.sent.remove(pos);
+        match rejected {
+            None => {
+                counters.published.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(reason) => {
+                log::warn!("{} Broker từ chối message (pkid {}): {}", tag, pkid, reason);
+                set_last_reason(counters, &format!("publish: {}", reason));
+                counters.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Reason code / lỗi gần nhất từ broker (hiện trong status)
+fn set_last_reason(counters: &MqttStats, reason: &str) {
+    *counters.last_reason.lock().unwrap() = reason.to_string();
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Chuyển mọi dữ liệu đang chờ trong channel vào buffer
+fn absorb(data_rx: &Receiver<Routed>, buffer: &mut OfflineBuffer) {
+    while let Ok(routed) = data_rx.try_recv() {
+        buffer.push(topic::pack(&routed));
+    }
+}
+
+/// Chờ trước khi kết nối lại, vẫn nhận dữ liệu vào buffer + báo tiến độ cho watchdog
+fn wait_buffering(
+    data_rx: &Receiver<Routed>,
+    buffer: &mut OfflineBuffer,
+    stats: &SharedStats,
+    slot: usize,
+    dur: Duration,
+) {
+    let counters = &stats.mqtt[slot];
+    let deadline = Instant::now() + dur;
+    while Instant::now() < deadline && !crate::shutdown::is_shutting_down() {
+        stats.tasks.beat(Task::Mqtt(slot as u8));
+        if let Ok(routed) = data_rx.recv_timeout(Duration::from_millis(500)) {
+            buffer.push(topic::pack(&routed));
+            absorb(data_rx, buffer);
+            update_buffer_stats(buffer, counters);
+        }
+        buffer.flush_due();
+    }
+}
+
+fn update_buffer_stats(buffer: &OfflineBuffer, counters: &MqttStats) {
+    counters.buffered.store(buffer.total() as u32, Ordering::Relaxed);
+    counters.dropped.store(buffer.dropped(), Ordering::Relaxed);
+}
+
+/// Client ID mặc định: "<device_name>-<6 số hex cuối MAC>" (không đổi qua reboot)
+fn default_client_id(device_name: &str) -> String {
+    match topic::device_mac() {
+        Some(hex) => format!("{}-{}", device_name, &hex[6..]),
+        None => device_name.to_string(),
+    }
+}
+
+/// JSON presence: {"state":"online","device_name":"..","version":"..","ip":".."}
+fn presence_payload(device_name: &str, online: bool, ip: &str) -> String {
+    let name = crate::web_api::json_escape(device_name);
+    if online {
+        format!(
+            r#"{{"state":"online","device_name":"{}","version":"{}","ip":"{}"}}"#,
+            name, env!("CARGO_PKG_VERSION"), ip
+        )
+    } else {
+        format!(r#"{{"state":"offline","device_name":"{}"}}"#, name)
+    }
+}
+
+/// Publish retained lên status topic (QoS 1, không đi qua buffer)
+fn publish_presence(client: &MqttClient, inflight: &Mutex<Inflight>, topic: &str, payload: String, tag: &str) {
+    let msg = Outbound {
+        topic,
+        qos: QoS::AtLeastOnce,
+        retain: true,
+        payload: payload.into_bytes(),
+        props: PublishProps::default(),
+    };
+    if let Err((_, e)) = inflight.lock().unwrap().publish(client, None, msg) {
+        log::error!("{} Publish status lỗi: {}", tag, e);
+    }
+}
+
+/// Message hệ thống chờ gửi: (topic, payload, QoS, retain)
+type Pending = (String, Vec<u8>, QoS, bool);
+
+fn queue_discovery(ha: &Discovery, cfg: &crate::config::Config, mqtt: &crate::config::MqttConfig, pending: &mut VecDeque<Pending>) {
+    pending.extend(ha.messages(cfg, mqtt).into_iter().map(|(topic, payload)| (topic, payload.into_bytes(), QoS::AtLeastOnce, true)));
+}
+
+/// Xếp message không retained (Sparkplug B quy định QoS 0, ThingsBoard QoS 1)
+fn queue_messages(messages: Vec<(String, Vec<u8>)>, qos: QoS, pending: &mut VecDeque<Pending>) {
+    pending.extend(messages.into_iter().map(|(topic, payload)| (topic, payload, qos, false)));
+}
+
+/// Gửi (không qua buffer) tới khi hàng đợi rumqttc đầy — phần còn lại để vòng sau
+fn send_pending(client: &MqttClient, inflight: &Mutex<Inflight>, pending: &mut VecDeque<Pending>) {
+    while let Some((topic, payload, qos, retain)) = pending.pop_front() {
+        let msg = Outbound {
+            topic: &topic,
+            qos,
+            retain,
+            payload: payload.clone(),
+            props: PublishProps::default(),
+        };
+        match inflight.lock().unwrap().publish(client, None, msg) {
+            Ok(()) => {}
+            Err((_, PublishError::Busy)) => {
+                pending.push_front((topic, payload, qos, retain));
+                break;
+            }
+            Err((_, e)) => log::error!("[MQTT] Bỏ message hệ thống '{}': {}", topic, e),
+        }
+    }
+}
+
+/// Mức GPIO output đã cấu hình
+fn gpio_levels(stats: &SharedStats, cfg: &crate::config::Config) -> Vec<bool> {
+    stats.gpio_states.iter()
+        .take(cfg.gpio.pins.len())
+        .map(|v| v.load(Ordering::Relaxed) != 0)
+        .collect()
+}
+
+/// Áp desired của shadow: GPIO qua Command::Gpio, cấu hình lưu UCI + AppState::update
+/// (MQTT kết nối lại rồi get lại shadow → reported phản ánh cấu hình mới)
+fn apply_desired(
+    state: &AppState,
+    stats: &SharedStats,
+    cmd_tx: &std::sync::mpsc::Sender<crate::commands::Command>,
+    desired: &str,
+    tag: &str,
+) {
+    let current = state.get();
+    let changes = shadow::diff(desired, &current, &gpio_levels(stats, &current));
+    for cmd in changes.commands {
+        log::info!("{} Shadow: {:?}", tag, cmd);
+        let _ = cmd_tx.send(cmd);
+    }
+    if let Some(cfg) = changes.config {
+        log::info!("{} Shadow: áp cấu hình mới", tag);
+        cfg.save_to_uci();
+        state.update(cfg);
+    }
+}
+
+/// Ngắt kết nối chủ động: broker không gửi LWT nên tự publish "offline"
+fn announce_offline(client: &MqttClient, inflight: &Mutex<Inflight>, topic: &str, device_name: &str, tag: &str) {
+    if !topic.is_empty() {
+        publish_presence(client, inflight, topic, presence_payload(device_name, false, ""), tag);
+    }
+}
+
+/// Ngắt kết nối chủ động thì broker không gửi LWT: Sparkplug tự publish NDEATH,
+/// ThingsBoard báo disconnect các sub-device
+fn farewell(client: &MqttClient, inflight: &Mutex<Inflight>, node: Option<&Node>, tb: Option<&mut Gateway>) {
+    let mut messages: Vec<(String, Vec<u8>)> = node.map(|n| vec![n.death()]).unwrap_or_default();
+    if let Some(tb) = tb {
+        messages.extend(tb.disconnect_all());
+    }
+    for (topic, payload) in messages {
+        let msg = Outbound { topic: &topic, qos: QoS::AtLeastOnce, retain: false, payload, props: PublishProps::default() };
+        let _ = inflight.lock().unwrap().publish(client, None, msg);
+    }
+}
+
+/// IP local dùng để tới broker (UDP connect chỉ chọn route, không gửi gói nào)
+pub(crate) fn local_ip(broker: &str, port: u16) -> String {
+    std::net::UdpSocket::bind("0.0.0.0:0")
+        .and_then(|sock| {
+            sock.connect((broker, port))?;
+            sock.local_addr()
+        })
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default()
+}
+
+/// Gửi DISCONNECT và chờ IO thread đẩy gói ra socket (tối đa 1s)
+fn disconnect_clean(client: &MqttClient, conn_state: &std::sync::atomic::AtomicU8, tag: &str) {
+    if client.disconnect().is_ok() {
+        for _ in 0..20 {
+            if conn_state.load(Ordering::Relaxed) == 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+    log::info!("{} Đã ngắt kết nối broker", tag);
+}
@@ -0,0 +1,265 @@
+//! Lớp mỏng che khác biệt giữa rumqttc MQTT 3.1.1 và MQTT 5 (sync Client)
+//! mqtt.rs chỉ làm việc với MqttClient + NetEvent, không phụ thuộc phiên bản giao thức
+//! MQTT 5: user properties (metadata thiết bị) gắn vào mọi publish, message expiry,
+//! response topic + correlation data cho lệnh, reason code của broker trong log/status
+
+use crate::config::{MqttConfig, MqttVersion};
+use rumqttc::v5::mqttbytes::v5 as p5;
+use rumqttc::v5::mqttbytes::QoS as QoS5;
+use rumqttc::QoS;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Tham số kết nối ngoài MqttConfig
+pub struct ConnectParams {
+    pub client_id: String,
+    pub will: Option<Will>,
+    pub tls: Option<rustls::ClientConfig>,
+    /// Chỉ MQTT 5: gắn vào CONNECT và mọi PUBLISH
+    pub user_properties: Vec<(String, String)>,
+}
+
+/// LWT, gửi QoS 1
+pub struct Will {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+/// Thuộc tính MQTT 5 của 1 publish (bỏ qua khi dùng 3.1.1)
+#[derive(Default)]
+pub struct PublishProps {
+    /// Broker bỏ message nếu chưa giao được sau N giây
+    pub message_expiry: Option<u32>,
+    pub correlation_data: Option<Vec<u8>>,
+}
+
+/// Sự kiện từ IO thread, đã chuẩn hoá cho cả 2 phiên bản
+pub enum NetEvent {
+    ConnAck { session_present: bool },
+    Message {
+        topic: String,
+        payload: Vec<u8>,
+        /// MQTT 5 request/response: nơi gửi phản hồi + dữ liệu tương quan cần trả lại
+        response_topic: Option<String>,
+        correlation_data: Option<Vec<u8>>,
+    },
+    /// Publish đã ghi ra socket (pkid = 0 với QoS 0)
+    PublishSent(u16),
+    /// Broker xác nhận (PubAck/PubComp). `rejected` = reason code lỗi (chỉ MQTT 5)
+    Acked { pkid: u16, rejected: Option<String> },
+    /// Broker chủ động ngắt kết nối (MQTT 5 DISCONNECT kèm reason code)
+    ServerDisconnect(String),
+    /// DISCONNECT của mình đã ghi ra socket
+    DisconnectSent,
+    Error(String),
+    Other,
+}
+
+/// Lỗi publish không chặn
+#[derive(Debug)]
+pub enum PublishError {
+    /// Hàng đợi request đầy (hoặc IO thread đã dừng) → thử lại vòng sau
+    Busy,
+    /// Không bao giờ gửi được (topic sai sau khi render) → bỏ message, tính failed
+    Rejected(String),
+}
+
+impl std::fmt::Display for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Busy => write!(f, "hàng đợi gửi đầy"),
+            Self::Rejected(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum MqttClient {
+    V311(rumqttc::Client),
+    V5 {
+        client: rumqttc::v5::Client,
+        user_properties: Arc<Vec<(String, String)>>,
+    },
+}
+
+pub enum Connection {
+    V311(Box<rumqttc::Connection>),
+    V5(Box<rumqttc::v5::Connection>),
+}
+
+pub fn connect(cfg: &MqttConfig, params: ConnectParams) -> (MqttClient, Connection) {
+    let cap = cfg.inflight as usize;
+    match cfg.version {
+        MqttVersion::V311 => {
+            let mut opts = rumqttc::MqttOptions::new(&params.client_id, &cfg.broker, cfg.port);
+            opts.set_keep_alive(Duration::from_secs(cfg.keep_alive_secs as u64));
+            opts.set_clean_session(cfg.clean_session);
+            opts.set_inflight(cfg.inflight);
+            if !cfg.username.is_empty() {
+                opts.set_credentials(&cfg.username, &cfg.password);
+            }
+            if let Some(w) = params.will {
+                opts.set_last_will(rumqttc::LastWill::new(w.topic, w.payload, QoS::AtLeastOnce, w.retain));
+            }
+            if let Some(tls) = params.tls {
+                opts.set_transport(rumqttc::Transport::tls_with_config(
+                    rumqttc::TlsConfiguration::Rustls(Arc::new(tls)),
+                ));
+            }
+            let (client, connection) = rumqttc::Client::new(opts, cap);
+            (MqttClient::V311(client), Connection::V311(Box::new(connection)))
+        }
+        MqttVersion::V5 => {
+            let mut opts = rumqttc::v5::MqttOptions::new(&params.client_id, &cfg.broker, cfg.port);
+            opts.set_keep_alive(Duration::from_secs(cfg.keep_alive_secs as u64));
+            opts.set_clean_start(cfg.clean_session);
+            opts.set_outgoing_inflight_upper_limit(cfg.inflight);
+            opts.set_user_properties(params.user_properties.clone());
+            if !cfg.username.is_empty() {
+                opts.set_credentials(&cfg.username, &cfg.password);
+            }
+            if let Some(w) = params.will {
+                opts.set_last_will(p5::LastWill::new(w.topic, w.payload, QoS5::AtLeastOnce, w.retain, None));
+            }
+            if let Some(tls) = params.tls {
+                opts.set_transport(rumqttc::Transport::tls_with_config(
+                    rumqttc::TlsConfiguration::Rustls(Arc::new(tls)),
+                ));
+            }
+            let (client, connection) = rumqttc::v5::Client::new(opts, cap);
+            let user_properties = Arc::new(params.user_properties);
+            (MqttClient::V5 { client, user_properties }, Connection::V5(Box::new(connection)))
+        }
+    }
+}
+
+impl MqttClient {
+    /// Publish không chặn: hàng đợi request đầy → Busy (gọi được khi đang giữ lock)
+    pub fn try_publish(
+        &self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: Vec<u8>,
+        props: PublishProps,
+    ) -> Result<(), PublishError> {
+        // rumqttc trả cùng 1 lỗi cho topic sai và hàng đợi đầy → tự kiểm tra topic trước
+        if topic.is_empty() || !rumqttc::valid_topic(topic) {
+            return Err(PublishError::Rejected(format!("topic không hợp lệ '{}'", topic)));
+        }
+        match self {
+            Self::V311(c) => c.try_publish(topic, qos, retain, payload).map_err(|_| PublishError::Busy),
+            Self::V5 { client, user_properties } => {
+                let properties = p5::PublishProperties {
+                    message_expiry_interval: props.message_expiry,
+                    correlation_data: props.correlation_data.map(Into::into),
+                    user_properties: user_properties.as_ref().clone(),
+                    ..Default::default()
+                };
+                client
+                    .try_publish_with_properties(topic, qos5(qos), retain, payload, properties)
+                    .map_err(|_| PublishError::Busy)
+            }
+        }
+    }
+
+    pub fn subscribe(&self, topic: &str, qos: QoS) -> Result<(), String> {
+        match self {
+            Self::V311(c) => c.subscribe(topic, qos).map_err(|e| e.to_string()),
+            Self::V5 { client, .. } => client.subscribe(topic, qos5(qos)).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn disconnect(&self) -> Result<(), String> {
+        match self {
+            Self::V311(c) => c.disconnect().map_err(|e| e.to_string()),
+            Self::V5 { client, .. } => client.disconnect().map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl Connection {
+    /// Vòng lặp I/O: gọi `handler` cho từng sự kiện tới khi handler trả false hoặc kết nối đóng
+    pub fn run(mut self, mut handler: impl FnMut(NetEvent) -> bool) {
+        match &mut self {
+            Self::V311(conn) => {
+                for notification in conn.iter() {
+                    if !handler(map_v311(notification)) {
+                        return;
+                    }
+                }
+            }
+            Self::V5(conn) => {
+                for notification in conn.iter() {
+                    if !handler(map_v5(notification)) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn qos5(qos: QoS) -> QoS5 {
+    match qos {
+        QoS::AtMostOnce => QoS5::AtMostOnce,
+        QoS::AtLeastOnce => QoS5::AtLeastOnce,
+        QoS::ExactlyOnce => QoS5::ExactlyOnce,
+    }
+}
+
+fn map_v311(n: Result<rumqttc::Event, rumqttc::ConnectionError>) -> NetEvent {
+    use rumqttc::{Event, Outgoing, Packet};
+    match n {
+        Ok(Event::Incoming(Packet::ConnAck(ack))) => NetEvent::ConnAck { session_present: ack.session_present },
+        Ok(Event::Incoming(Packet::Publish(msg))) => NetEvent::Message {
+            topic: msg.topic,
+            payload: msg.payload.to_vec(),
+            response_topic: None,
+            correlation_data: None,
+        },
+        Ok(Event::Incoming(Packet::PubAck(ack))) => NetEvent::Acked { pkid: ack.pkid, rejected: None },
+        Ok(Event::Incoming(Packet::PubComp(comp))) => NetEvent::Acked { pkid: comp.pkid, rejected: None },
+        Ok(Event::Outgoing(Outgoing::Publish(pkid))) => NetEvent::PublishSent(pkid),
+        Ok(Event::Outgoing(Outgoing::Disconnect)) => NetEvent::DisconnectSent,
+        Ok(_) => NetEvent::Other,
+        Err(e) => NetEvent::Error(e.to_string()),
+    }
+}
+
+fn map_v5(n: Result<rumqttc::v5::Event, rumqttc::v5::ConnectionError>) -> NetEvent {
+    use rumqttc::v5::Event;
+    use rumqttc::Outgoing;
+    match n {
+        Ok(Event::Incoming(p5::Packet::ConnAck(ack))) => NetEvent::ConnAck { session_present: ack.session_present },
+        Ok(Event::Incoming(p5::Packet::Publish(msg))) => {
+            let (response_topic, correlation_data) = match msg.properties {
+                Some(p) => (p.response_topic, p.correlation_data.map(|d| d.to_vec())),
+                None => (None, None),
+            };
+            NetEvent::Message {
+                topic: String::from_utf8_lossy(&msg.topic).into_owned(),
+                payload: msg.payload.to_vec(),
+                response_topic,
+                correlation_data,
+            }
+        }
+        Ok(Event::Incoming(p5::Packet::PubAck(ack))) => {
+            let ok = matches!(ack.reason, p5::PubAckReason::Success | p5::PubAckReason::NoMatchingSubscribers);
+            NetEvent::Acked { pkid: ack.pkid, rejected: (!ok).then(|| format!("{:?}", ack.reason)) }
+        }
+        // QoS 2: PubRec lỗi kết thúc luồng (không có PubComp)
+        Ok(Event::Incoming(p5::Packet::PubRec(rec)))
+            if !matches!(rec.reason, p5::PubRecReason::Success | p5::PubRecReason::NoMatchingSubscribers) =>
+        {
+            NetEvent::Acked { pkid: rec.pkid, rejected: Some(format!("{:?}", rec.reason)) }
+        }
+        Ok(Event::Incoming(p5::Packet::PubComp(comp))) => NetEvent::Acked { pkid: comp.pkid, rejected: None },
+        Ok(Event::Incoming(p5::Packet::Disconnect(d))) => NetEvent::ServerDisconnect(format!("{:?}", d.reason_code)),
+        Ok(Event::Outgoing(Outgoing::Publish(pkid))) => NetEvent::PublishSent(pkid),
+        Ok(Event::Outgoing(Outgoing::Disconnect)) => NetEvent::DisconnectSent,
+        Ok(_) => NetEvent::Other,
+        Err(e) => NetEvent::Error(e.to_string()),
+    }
+}
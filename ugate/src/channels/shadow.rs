@@ -0,0 +1,209 @@
+//! Device shadow (digital twin) kiểu AWS IoT qua MQTT, topic gốc `mqtt.shadow_topic`:
+//!   <base>/get                       ← "{}" sau mỗi lần kết nối: lấy desired đã đặt khi offline
+//!   <base>/get/accepted              → {"state":{"desired":{...},"reported":{...}},"version":N}
+//!   <base>/update/delta              → {"state":{<desired khác reported>},"version":N}
+//!   <base>/update                    ← {"state":{"reported":{...}},"version":N}
+//!   <base>/update/accepted|rejected  → version mới / xung đột version thì get lại
+//!
+//! Desired/reported cùng dạng body POST /api/config (section general, uart, http, tcp)
+//! cộng GPIO output `"gpio":{"1":"ON","2":"OFF"}`. Section mqtt không nhận qua shadow:
+//! cấu hình broker sai sẽ cắt luôn đường quay lại cloud. Bí mật HTTP (password, token, header,
+//! sign_secret) không lên reported; endpoint + auth HTTP (url, poll_url, poll_ack_url, auth,
+//! username, api_key_header, verify_response) cũng không đổi được qua desired — đổi URL sẽ gửi
+//! token sang host lạ, tắt verify_response thì server không ký cũng điều khiển được GPIO
+
+use crate::commands::{Command, GpioState};
+use crate::config::Config;
+use crate::web_api::server::{apply_config_json, section_json};
+use crate::web_api::{jval, json_object};
+
+/// Section cấu hình đồng bộ qua shadow
+const SECTIONS: [&str; 4] = ["general", "uart", "http", "tcp"];
+
+pub struct Shadow {
+    base: String,
+    /// Version document mới nhất đã thấy, gửi kèm reported để broker phát hiện ghi đè
+    version: Option<u64>,
+}
+
+/// Message shadow đã phân loại
+pub enum Incoming {
+    /// Trạng thái mong muốn (object JSON), cần so với hiện tại
+    Desired(String),
+    /// Broker từ chối reported (thường do lệch version) → get lại document
+    Rejected(String),
+    Ignored,
+}
+
+/// Thay đổi cần áp từ desired
+#[derive(Default)]
+pub struct Changes {
+    pub commands: Vec<Command>,
+    /// Some = cấu hình đổi (lưu UCI + AppState::update)
+    pub config: Option<Config>,
+}
+
+impl Shadow {
+    pub fn new(base: &str) -> Self {
+        Self { base: base.trim_end_matches('/').to_string(), version: None }
+    }
+
+    pub fn subscriptions(&self) -> Vec<String> {
+        ["get/accepted", "update/delta", "update/accepted", "update/rejected"]
+            .iter()
+            .map(|t| format!("{}/{}", self.base, t))
+            .collect()
+    }
+
+    pub fn get_topic(&self) -> String {
+        format!("{}/get", self.base)
+    }
+
+    pub fn update_topic(&self) -> String {
+        format!("{}/update", self.base)
+    }
+
+    pub fn handle(&mut self, topic: &str, payload: &str) -> Incoming {
+        let kind = match topic.strip_prefix(&self.base).and_then(|t| t.strip_prefix('/')) {
+            Some(k) => k,
+            None => return Incoming::Ignored,
+        };
+        if kind != "update/rejected" {
+            if let Some(v) = jval(payload, "version").and_then(|v| v.parse().ok()) {
+                self.version = Some(v);
+            }
+        }
+        let desired = match kind {
+            "get/accepted" => json_object(payload, "desired"),
+            "update/delta" => json_object(payload, "state"),
+            "update/rejected" => {
+                let reason = jval(payload, "message").unwrap_or_else(|| payload.to_string());
+                return Incoming::Rejected(reason);
+            }
+            _ => None,
+        };
+        match desired {
+            Some(d) => Incoming::Desired(d.to_string()),
+            None => Incoming::Ignored,
+        }
+    }
+
+    /// Document reported: cấu hình hiện tại + mức GPIO output
+    pub fn reported(&self, cfg: &Config, gpio: &[bool]) -> String {
+        let version = self.version.map(|v| format!(r#","version":{}"#, v)).unwrap_or_default();
+        format!(r#"{{"state":{{"reported":{}}}{}}}"#, state_json(cfg, gpio), version)
+    }
+}
+
+/// Chép các trường bí mật của section http từ `from` sang `cfg`
+fn keep_secrets(cfg: &mut Config, from: &Config) {
+    cfg.http.headers = from.http.headers.clone();
+    cfg.http.password = from.http.password.clone();
+    cfg.http.token = from.http.token.clone();
+    cfg.http.sign_secret = from.http.sign_secret.clone();
+}
+
+/// Chép endpoint + cách xác thực HTTP từ `from` sang `cfg` (chỉ đổi tại chỗ qua web/UCI)
+fn keep_endpoint(cfg: &mut Config, from: &Config) {
+    cfg.http.url = from.http.url.clone();
+    cfg.http.poll_url = from.http.poll_url.clone();
+    cfg.http.poll_ack_url = from.http.poll_ack_url.clone();
+    cfg.http.auth = from.http.auth;
+    cfg.http.username = from.http.username.clone();
+    cfg.http.api_key_header = from.http.api_key_header.clone();
+    cfg.http.verify_response = from.http.verify_response;
+}
+
+fn state_json(cfg: &Config, gpio: &[bool]) -> String {
+    let mut cfg = cfg.clone();
+    keep_secrets(&mut cfg, &Config::default());
+    let pins: Vec<String> = gpio.iter()
+        .enumerate()
+        .map(|(i, on)| format!(r#""{}":"{}""#, i + 1, if *on { "ON" } else { "OFF" }))
+        .collect();
+    let mut parts = vec![format!(r#""gpio":{{{}}}"#, pins.join(","))];
+    parts.extend(SECTIONS.iter().filter_map(|s| section_json(&cfg, s).map(|j| format!(r#""{}":{}"#, s, j))));
+    format!("{{{}}}", parts.join(","))
+}
+
+/// So desired với cấu hình + GPIO hiện tại, chỉ trả phần khác
+pub fn diff(desired: &str, cfg: &Config, gpio: &[bool]) -> Changes {
+    let mut changes = Changes::default();
+    if let Some(pins) = json_object(desired, "gpio") {
+        for (i, current) in gpio.iter().enumerate() {
+            let want = match jval(pins, &(i + 1).to_string()).as_deref() {
+                Some("ON" | "on" | "true" | "1") => true,
+                Some("OFF" | "off" | "false" | "0") => false,
+                _ => continue,
+            };
+            if want != *current {
+                let state = if want { GpioState::On } else { GpioState::Off };
+                changes.commands.push(Command::Gpio { pin: i as u8 + 1, state });
+            }
+        }
+    }
+    let mut next = cfg.clone();
+    apply_config_json(&mut next, desired, &SECTIONS);
+    keep_secrets(&mut next, cfg);
+    keep_endpoint(&mut next, cfg);
+    let changed = SECTIONS.iter().any(|s| section_json(cfg, s) != section_json(&next, s));
+    if changed {
+        changes.config = Some(next);
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle_and_diff() {
+        let mut shadow = Shadow::new("$aws/things/gw1/shadow");
+        let doc = r#"{"state":{"desired":{"gpio":{"1":"ON","2":"OFF"},"uart":{"baudrate":9600}},"reported":{}},"version":7}"#;
+        let desired = match shadow.handle("$aws/things/gw1/shadow/get/accepted", doc) {
+            Incoming::Desired(d) => d,
+            _ => panic!("expected desired"),
+        };
+        let mut cfg = Config::default();
+        cfg.gpio.pins = vec![17, 18];
+        let changes = diff(&desired, &cfg, &[false, false]);
+        assert_eq!(changes.commands.len(), 1);
+        assert_eq!(changes.config.map(|c| c.uart.baudrate), Some(9600));
+        assert!(shadow.reported(&cfg, &[true, false]).contains(r#""gpio":{"1":"ON","2":"OFF"}"#));
+        assert!(shadow.reported(&cfg, &[]).ends_with(r#","version":7}"#));
+
+        // Desired đã khớp → không có gì để áp
+        cfg.uart.baudrate = 9600;
+        let changes = diff(&desired, &cfg, &[true, false]);
+        assert!(changes.commands.is_empty() && changes.config.is_none());
+
+        let delta = r#"{"version":8,"state":{"tcp":{"enabled":true}}}"#;
+        assert!(matches!(shadow.handle("$aws/things/gw1/shadow/update/delta", delta), Incoming::Desired(d) if d.contains("tcp")));
+        assert!(matches!(shadow.handle("other/topic", delta), Incoming::Ignored));
+    }
+
+    #[test]
+    fn test_secrets_and_template() {
+        let shadow = Shadow::new("gw1/shadow");
+        let mut cfg = Config::default();
+        cfg.http.token = "tk-123".into();
+        cfg.http.sign_secret = "s3cret".into();
+        cfg.http.body_template = r#"{"t":{timestamp},"v":"{data}"}"#.into();
+        let reported = shadow.reported(&cfg, &[]);
+        assert!(!reported.contains("tk-123") && !reported.contains("s3cret"));
+        assert!(reported.contains(r#""body_template":"{\"t\":{timestamp},\"v\":\"{data}\"}""#));
+
+        // Desired không ghi được secret; template có ngoặc kép giải escape đúng
+        let desired = r#"{"http":{"sign_secret":"x","body_template":"{\"a\":\"{data}\"}"}}"#;
+        let next = diff(desired, &cfg, &[]).config.unwrap();
+        assert_eq!(next.http.sign_secret, "s3cret");
+        assert_eq!(next.http.body_template, r#"{"a":"{data}"}"#);
+
+        // Endpoint/auth không đổi được từ cloud: token không đi sang host khác
+        cfg.http.url = "https://api.example.com".into();
+        cfg.http.verify_response = true;
+        let evil = r#"{"http":{"url":"http://evil","poll_url":"http://evil","auth":"none","verify_response":false}}"#;
+        assert!(diff(evil, &cfg, &[]).config.is_none());
+    }
+}
@@ -0,0 +1,269 @@
+//! Sparkplug B trên kênh MQTT (mqtt.sparkplug): gateway là edge node, UART là device
+//! Topic: spBv1.0/<group>/{NBIRTH,NDEATH,NDATA,NCMD}/<node> và
+//!        spBv1.0/<group>/{DBIRTH,DDEATH,DDATA,DCMD}/<node>/<device>
+//! - NDEATH là LWT (kèm bdSeq), NBIRTH cùng bdSeq sau mỗi lần kết nối, bdSeq tăng theo kết nối
+//! - seq 0..255 tăng theo từng message của node, NBIRTH luôn = 0
+//! - Node metric: GPIO/<n> (ghi được qua NCMD), Inputs/<n>, System/*, UART/* (bộ đếm)
+//! - Device metric: trường giải mã từ frame UART JSON (mqtt.sparkplug_fields), Raw (frame
+//!   không giải mã được), TX (ghi qua DCMD → gửi xuống UART)
+//! - NCMD "Node Control/Rebirth" = true → publish lại NBIRTH + DBIRTH
+//! - UART mất/có lại → DDEATH/DBIRTH
+
+use super::sparkplug_pb::{self as pb, Metric, Value};
+use crate::commands::{Command, GpioState};
+use crate::config::{Config, MqttConfig};
+use crate::web_api::status::SharedStats;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Chu kỳ NDATA (CPU, RAM, bộ đếm). GPIO đổi mức thì gửi ngay
+pub const NDATA_EVERY: Duration = Duration::from_secs(30);
+
+/// bdSeq kế tiếp của từng broker, giữ qua các lần kết nối lại
+static BD_SEQ: Mutex<[u8; crate::channels::mqtt::MAX_BROKERS]> = Mutex::new([0; crate::channels::mqtt::MAX_BROKERS]);
+
+/// Message đã mã hoá: (topic, payload)
+pub type Message = (String, Vec<u8>);
+
+/// Việc vòng lặp MQTT cần làm sau NCMD/DCMD
+pub enum Action {
+    Rebirth,
+    Command(Command),
+}
+
+pub struct Node {
+    group: String,
+    node: String,
+    device: String,
+    /// Trường giải mã từ frame UART: (tên, datatype)
+    fields: Vec<(String, u32)>,
+    bd_seq: u8,
+    seq: u8,
+}
+
+/// ID Sparkplug không được chứa '/', '+', '#'
+fn sanitize(id: &str) -> String {
+    id.chars().map(|c| if matches!(c, '/' | '+' | '#') { '_' } else { c }).collect()
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// "double"/"int"/"bool"/"string" → datatype (mặc định Double)
+fn datatype(name: &str) -> u32 {
+    match name {
+        "int" | "uint" | "long" => pb::UINT64,
+        "bool" | "boolean" => pb::BOOLEAN,
+        "string" | "str" => pb::STRING,
+        _ => pb::DOUBLE,
+    }
+}
+
+impl Node {
+    /// Tạo node cho 1 lần kết nối của broker `slot` (lấy bdSeq mới)
+    pub fn new(cfg: &Config, mqtt: &MqttConfig, slot: usize) -> Self {
+        let bd_seq = {
+            let mut seqs = BD_SEQ.lock().unwrap();
+            let seq = seqs[slot];
+            seqs[slot] = seq.wrapping_add(1);
+            seq
+        };
+        let node = if mqtt.sparkplug_node.is_empty() { &cfg.general.device_name } else { &mqtt.sparkplug_node };
+        Self {
+            group: sanitize(&mqtt.sparkplug_group),
+            node: sanitize(node),
+            device: sanitize(&mqtt.sparkplug_device),
+            fields: mqtt.sparkplug_fields.iter().map(|(n, t)| (n.clone(), datatype(t))).collect(),
+            bd_seq,
+            seq: 0,
+        }
+    }
+
+    fn node_topic(&self, kind: &str) -> String {
+        format!("spBv1.0/{}/{}/{}", self.group, kind, self.node)
+    }
+
+    fn device_topic(&self, kind: &str) -> String {
+        format!("spBv1.0/{}/{}/{}/{}", self.group, kind, self.node, self.device)
+    }
+
+    /// Tiền tố topic lệnh (NCMD + DCMD) để IO thread nhận ra
+    pub fn command_prefixes(&self) -> Vec<String> {
+        vec![self.node_topic("NCMD"), self.device_topic("DCMD")]
+    }
+
+    fn next_seq(&mut self) -> u8 {
+        let seq = self.seq;
+        self.seq = seq.wrapping_add(1);
+        seq
+    }
+
+    /// NDEATH: dùng làm LWT và gửi chủ động khi ngắt kết nối êm
+    pub fn death(&self) -> Message {
+        let metrics = [Metric::new("bdSeq", Value::U64(self.bd_seq as u64))];
+        (self.node_topic("NDEATH"), pb::encode(now_ms(), None, &metrics))
+    }
+
+    /// NBIRTH (seq = 0) + DBIRTH nếu UART đang chạy
+    pub fn birth(&mut self, cfg: &Config, stats: &SharedStats, device_up: bool) -> Vec<Message> {
+        self.seq = 0;
+        let mut metrics = vec![
+            Metric::new("bdSeq", Value::U64(self.bd_seq as u64)),
+            Metric::new("Node Control/Rebirth", Value::Bool(false)),
+            Metric::new("Properties/Firmware", Value::Str(env!("CARGO_PKG_VERSION").into())),
+        ];
+        metrics.extend(node_metrics(cfg, stats));
+        let seq = self.next_seq();
+        let mut out = vec![(self.node_topic("NBIRTH"), pb::encode(now_ms(), Some(seq), &metrics))];
+        if device_up {
+            out.push(self.device_birth());
+        }
+        out
+    }
+
+    pub fn device_birth(&mut self) -> Message {
+        let mut metrics: Vec<Metric> = self.fields.iter().map(|(n, t)| Metric::new(n, Value::Null(*t))).collect();
+        metrics.push(Metric::new("Raw", Value::Null(pb::BYTES)));
+        metrics.push(Metric::new("TX", Value::Str(String::new())));
+        let seq = self.next_seq();
+        (self.device_topic("DBIRTH"), pb::encode(now_ms(), Some(seq), &metrics))
+    }
+
+    pub fn device_death(&mut self) -> Message {
+        let seq = self.next_seq();
+        (self.device_topic("DDEATH"), pb::encode(now_ms(), Some(seq), &[]))
+    }
+
+    pub fn node_data(&mut self, cfg: &Config, stats: &SharedStats) -> Message {
+        let seq = self.next_seq();
+        (self.node_topic("NDATA"), pb::encode(now_ms(), Some(seq), &node_metrics(cfg, stats)))
+    }
+
+    /// DDATA cho 1 frame UART (nhận lúc `timestamp_ms`). Chưa tăng seq: gọi `advance` khi
+    /// publish thành công, publish lỗi thì frame quay lại buffer và seq không bị hổng
+    pub fn device_data(&self, frame: &[u8], timestamp_ms: u64) -> Message {
+        let text = std::str::from_utf8(frame).ok();
+        let mut metrics: Vec<Metric> = self.fields.iter()
+            .filter_map(|(name, t)| {
+                let raw = crate::web_api::jval(text?, name)?;
+                let value = match *t {
+                    pb::UINT64 => Value::U64(raw.parse().ok()?),
+                    pb::BOOLEAN => Value::Bool(raw == "true" || raw == "1"),
+                    pb::STRING => Value::Str(raw),
+                    _ => Value::Double(raw.parse().ok()?),
+                };
+                Some(Metric::new(name, value))
+            })
+            .collect();
+        if metrics.is_empty() {
+            metrics.push(Metric::new("Raw", Value::Bytes(frame.to_vec())));
+        }
+        (self.device_topic("DDATA"), pb::encode(timestamp_ms, Some(self.seq), &metrics))
+    }
+
+    pub fn advance(&mut self) {
+        self.seq = self.seq.wrapping_add(1);
+    }
+
+    /// NCMD: GPIO/<n> + Node Control/Rebirth; DCMD: TX → UART
+    pub fn handle(&self, topic: &str, payload: &[u8]) -> Vec<Action> {
+        let node_cmd = topic == self.node_topic("NCMD");
+        if !node_cmd && topic != self.device_topic("DCMD") {
+            return Vec::new();
+        }
+        let metrics = match pb::decode(payload) {
+            Some(m) => m,
+            None => {
+                log::warn!("[Sparkplug] Payload lệnh hỏng trên '{}'", topic);
+                return Vec::new();
+            }
+        };
+        metrics.into_iter().filter_map(|m| match (node_cmd, m.name.as_str(), m.value) {
+            (true, "Node Control/Rebirth", Value::Bool(true)) => Some(Action::Rebirth),
+            (true, name, Value::Bool(on)) => {
+                let pin = name.strip_prefix("GPIO/")?.parse().ok()?;
+                let state = if on { GpioState::On } else { GpioState::Off };
+                Some(Action::Command(Command::Gpio { pin, state }))
+            }
+            (false, "TX", Value::Str(data)) => Some(Action::Command(Command::UartTx { data })),
+            (false, "TX", Value::Bytes(data)) => Some(Action::Command(Command::UartTx {
+                data: String::from_utf8_lossy(&data).into_owned(),
+            })),
+            (_, name, _) => {
+                log::debug!("[Sparkplug] Bỏ qua metric '{}'", name);
+                None
+            }
+        }).collect()
+    }
+}
+
+fn node_metrics(cfg: &Config, stats: &SharedStats) -> Vec<Metric> {
+    let level = |v: &std::sync::atomic::AtomicU8| Value::Bool(v.load(Ordering::Relaxed) != 0);
+    let mut metrics = Vec::new();
+    for (i, v) in stats.gpio_states.iter().take(cfg.gpio.pins.len()).enumerate() {
+        metrics.push(Metric::new(&format!("GPIO/{}", i + 1), level(v)));
+    }
+    for (i, v) in stats.gpio_inputs.iter().take(cfg.gpio.inputs.len()).enumerate() {
+        metrics.push(Metric::new(&format!("Inputs/{}", i + 1), level(v)));
+    }
+    let (cpu, ram_used, uptime) = stats.system_metrics();
+    let counter = |v: &std::sync::atomic::AtomicU32| Value::U64(v.load(Ordering::Relaxed) as u64);
+    metrics.extend([
+        Metric::new("System/CPU", Value::U64(cpu as u64)),
+        Metric::new("System/RAM Used", Value::U64(ram_used as u64)),
+        Metric::new("System/Uptime", Value::U64(uptime)),
+        Metric::new("UART/RX Frames", counter(&stats.uart_rx_frames)),
+        Metric::new("UART/TX Frames", counter(&stats.uart_tx_frames)),
+        Metric::new("UART/RX Bytes", counter(&stats.uart_rx_bytes)),
+        Metric::new("UART/Errors", counter(&stats.uart_failed)),
+    ]);
+    metrics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node() -> Node {
+        let mut cfg = Config::default();
+        cfg.general.device_name = "gw/1".into();
+        cfg.mqtt.sparkplug_fields = vec![("temp".into(), String::new()), ("on".into(), "bool".into())];
+        Node::new(&cfg, &cfg.mqtt, 3)
+    }
+
+    #[test]
+    fn test_topics_and_data() {
+        let mut n = node();
+        assert_eq!(n.death().0, "spBv1.0/ugate/NDEATH/gw_1");
+        let data = n.device_data(br#"{"temp":21.5,"on":true}"#, 1000);
+        assert_eq!(data.0, "spBv1.0/ugate/DDATA/gw_1/uart");
+        let metrics = pb::decode(&data.1).unwrap();
+        assert_eq!(metrics[0], Metric::new("temp", Value::Double(21.5)));
+        assert_eq!(metrics[1], Metric::new("on", Value::Bool(true)));
+        let raw = pb::decode(&n.device_data(b"\x01\x02", 1000).1).unwrap();
+        assert_eq!(raw, vec![Metric::new("Raw", Value::Bytes(vec![1, 2]))]);
+        n.advance();
+        assert_eq!(n.seq, 1);
+    }
+
+    #[test]
+    fn test_commands() {
+        let n = node();
+        let ncmd = pb::encode(0, None, &[
+            Metric::new("Node Control/Rebirth", Value::Bool(true)),
+            Metric::new("GPIO/2", Value::Bool(true)),
+        ]);
+        let actions = n.handle("spBv1.0/ugate/NCMD/gw_1", &ncmd);
+        assert!(matches!(actions[0], Action::Rebirth));
+        assert!(matches!(actions[1], Action::Command(Command::Gpio { pin: 2, state: GpioState::On })));
+        let dcmd = pb::encode(0, None, &[Metric::new("TX", Value::Str("hi".into()))]);
+        let actions = n.handle("spBv1.0/ugate/DCMD/gw_1/uart", &dcmd);
+        assert!(matches!(&actions[0], Action::Command(Command::UartTx { data }) if data == "hi"));
+        assert!(n.handle("spBv1.0/ugate/NCMD/other", &ncmd).is_empty());
+    }
+}
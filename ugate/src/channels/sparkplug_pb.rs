@@ -0,0 +1,209 @@
+//! Mã hoá/giải mã protobuf tối giản cho Sparkplug B Payload (sparkplug_b.proto)
+//! Chỉ các trường gateway dùng: Payload{timestamp=1, metrics=2, seq=3},
+//! Metric{name=1, timestamp=3, datatype=4, is_null=7, int=10, long=11, float=12, double=13,
+//! boolean=14, string=15, bytes=16}. Trường khác khi giải mã được bỏ qua
+
+/// DataType theo spec Sparkplug B
+pub const UINT64: u32 = 8;
+pub const DOUBLE: u32 = 10;
+pub const BOOLEAN: u32 = 11;
+pub const STRING: u32 = 12;
+pub const BYTES: u32 = 17;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    U64(u64),
+    Double(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    /// Chưa có giá trị (is_null), giữ datatype để khai báo trong BIRTH
+    Null(u32),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub value: Value,
+}
+
+impl Metric {
+    pub fn new(name: &str, value: Value) -> Self {
+        Self { name: name.to_string(), value }
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn put_key(out: &mut Vec<u8>, field: u32, wire: u8) {
+    put_varint(out, ((field as u64) << 3) | wire as u64);
+}
+
+fn put_bytes(out: &mut Vec<u8>, field: u32, data: &[u8]) {
+    put_key(out, field, 2);
+    put_varint(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+fn put_uint(out: &mut Vec<u8>, field: u32, v: u64) {
+    put_key(out, field, 0);
+    put_varint(out, v);
+}
+
+fn encode_metric(m: &Metric, timestamp_ms: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(m.name.len() + 16);
+    put_bytes(&mut out, 1, m.name.as_bytes());
+    put_uint(&mut out, 3, timestamp_ms);
+    match &m.value {
+        Value::Bool(b) => {
+            put_uint(&mut out, 4, BOOLEAN as u64);
+            put_uint(&mut out, 14, *b as u64);
+        }
+        Value::U64(v) => {
+            put_uint(&mut out, 4, UINT64 as u64);
+            put_uint(&mut out, 11, *v);
+        }
+        Value::Double(v) => {
+            put_uint(&mut out, 4, DOUBLE as u64);
+            put_key(&mut out, 13, 1);
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        Value::Str(s) => {
+            put_uint(&mut out, 4, STRING as u64);
+            put_bytes(&mut out, 15, s.as_bytes());
+        }
+        Value::Bytes(b) => {
+            put_uint(&mut out, 4, BYTES as u64);
+            put_bytes(&mut out, 16, b);
+        }
+        Value::Null(datatype) => {
+            put_uint(&mut out, 4, *datatype as u64);
+            put_uint(&mut out, 7, 1);
+        }
+    }
+    out
+}
+
+/// Payload đầy đủ. `seq` = None cho NDEATH (spec không có seq)
+pub fn encode(timestamp_ms: u64, seq: Option<u8>, metrics: &[Metric]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(64 + metrics.len() * 32);
+    put_uint(&mut out, 1, timestamp_ms);
+    for m in metrics {
+        put_bytes(&mut out, 2, &encode_metric(m, timestamp_ms));
+    }
+    if let Some(seq) = seq {
+        put_uint(&mut out, 3, seq as u64);
+    }
+    out
+}
+
+/// Đọc dần buffer protobuf: (số trường, wire type, giá trị)
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Fixed64([u8; 8]),
+    Fixed32([u8; 4]),
+    Len(&'a [u8]),
+}
+
+impl<'a> Reader<'a> {
+    fn varint(&mut self) -> Option<u64> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = *self.data.get(self.pos)?;
+            self.pos += 1;
+            v |= ((b & 0x7f) as u64) << shift;
+            if b < 0x80 {
+                return Some(v);
+            }
+        }
+        None
+    }
+
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(n)?;
+        let s = self.data.get(self.pos..end)?;
+        self.pos = end;
+        Some(s)
+    }
+
+    fn next(&mut self) -> Option<Option<(u32, Field<'a>)>> {
+        if self.pos >= self.data.len() {
+            return Some(None);
+        }
+        let key = self.varint()?;
+        let field = match key & 7 {
+            0 => Field::Varint(self.varint()?),
+            1 => Field::Fixed64(self.take(8)?.try_into().ok()?),
+            2 => {
+                let len = self.varint()? as usize;
+                Field::Len(self.take(len)?)
+            }
+            5 => Field::Fixed32(self.take(4)?.try_into().ok()?),
+            _ => return None,
+        };
+        Some(Some(((key >> 3) as u32, field)))
+    }
+}
+
+fn decode_metric(data: &[u8]) -> Option<Metric> {
+    let mut r = Reader { data, pos: 0 };
+    let mut name = String::new();
+    let mut value = None;
+    let mut datatype = 0;
+    while let Some((field, v)) = r.next()? {
+        match (field, v) {
+            (1, Field::Len(s)) => name = String::from_utf8_lossy(s).into_owned(),
+            (4, Field::Varint(t)) => datatype = t as u32,
+            (10 | 11, Field::Varint(n)) => value = Some(Value::U64(n)),
+            (12, Field::Fixed32(b)) => value = Some(Value::Double(f32::from_le_bytes(b) as f64)),
+            (13, Field::Fixed64(b)) => value = Some(Value::Double(f64::from_le_bytes(b))),
+            (14, Field::Varint(n)) => value = Some(Value::Bool(n != 0)),
+            (15, Field::Len(s)) => value = Some(Value::Str(String::from_utf8_lossy(s).into_owned())),
+            (16, Field::Len(s)) => value = Some(Value::Bytes(s.to_vec())),
+            _ => {}
+        }
+    }
+    Some(Metric { name, value: value.unwrap_or(Value::Null(datatype)) })
+}
+
+/// Danh sách metric trong payload NCMD/DCMD. None = payload hỏng
+pub fn decode(data: &[u8]) -> Option<Vec<Metric>> {
+    let mut r = Reader { data, pos: 0 };
+    let mut metrics = Vec::new();
+    while let Some((field, v)) = r.next()? {
+        if let (2, Field::Len(m)) = (field, v) {
+            metrics.push(decode_metric(m)?);
+        }
+    }
+    Some(metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let metrics = vec![
+            Metric::new("Node Control/Rebirth", Value::Bool(true)),
+            Metric::new("bdSeq", Value::U64(300)),
+            Metric::new("temp", Value::Double(21.5)),
+            Metric::new("TX", Value::Str("hello".into())),
+            Metric::new("Raw", Value::Bytes(vec![0, 1, 0xff])),
+        ];
+        let data = encode(1_700_000_000_000, Some(3), &metrics);
+        assert_eq!(decode(&data).unwrap(), metrics);
+        assert!(decode(&data[..data.len() - 3]).is_none());
+    }
+}
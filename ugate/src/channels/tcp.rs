@@ -32,6 +32,7 @@ pub async fn run_server(
             tokio::select! {
                 _ = config_watch.changed() => {}
                 _ = tokio::time::sleep(Duration::from_secs(5)) => {}
+                _ = crate::shutdown::wait() => return,
             }
             continue;
         }
@@ -40,7 +41,7 @@ pub async fn run_server(
         let listener = match TcpListener::bind(&addr).await {
             Ok(l) => {
                 log::info!("[TCP Server] Lắng nghe tại {}", addr);
-                stats.tcp_state.store(1, std::sync::atomic::Ordering::Relaxed);
+                stats.tcp_state.store(3, std::sync::atomic::Ordering::Relaxed);
                 l
             }
             Err(e) => {
@@ -57,6 +58,12 @@ pub async fn run_server(
                     break;
                 }
 
+                // Tắt êm: đóng listener, các kết nối tự đóng qua shutdown::wait()
+                _ = crate::shutdown::wait() => {
+                    log::info!("[TCP Server] Đang tắt, ngừng nhận kết nối");
+                    return;
+                }
+
                 result = listener.accept() => {
                     match result {
                         Ok((stream, addr)) => {
@@ -89,11 +96,15 @@ pub async fn run_client(
     let mut config_watch = state.subscribe();
 
     loop {
+        if crate::shutdown::is_shutting_down() {
+            return;
+        }
         let config = state.get();
         if !config.tcp.enabled || config.tcp.mode == crate::config::TcpMode::Server {
             tokio::select! {
                 _ = config_watch.changed() => {}
                 _ = tokio::time::sleep(Duration::from_secs(5)) => {}
+                _ = crate::shutdown::wait() => return,
             }
             continue;
         }
@@ -108,6 +119,8 @@ pub async fn run_client(
                 continue;
             }
 
+            _ = crate::shutdown::wait() => return,
+
             result = TcpStream::connect(&addr) => {
                 match result {
                     Ok(stream) => {
@@ -151,6 +164,13 @@ async fn handle_connection(
                 break;
             }
 
+            // Tắt êm: gửi FIN để phía bên kia biết kết nối đóng chủ động
+            _ = crate::shutdown::wait() => {
+                let _ = writer.shutdown().await;
+                log::info!("[TCP] Đang tắt, đóng kết nối");
+                break;
+            }
+
             // Đọc dữ liệu từ TCP (raw bytes, không cần newline)
             result = tokio::io::AsyncReadExt::read(&mut reader, &mut buf) => {
                 match result {
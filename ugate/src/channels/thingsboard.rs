@@ -0,0 +1,167 @@
+//! ThingsBoard MQTT Gateway API (mqtt.preset = thingsboard). Gateway đăng nhập bằng access
+//! token (mqtt.username), mỗi nguồn dữ liệu UART là 1 sub-device:
+//! - Tên sub-device theo cổng UART, hoặc slave ID khi frame_mode=modbus (mqtt.tb_device_name)
+//! - Sub-device mới: v1/gateway/connect + v1/gateway/attributes (cổng, baudrate, slave ID...)
+//! - Frame UART → v1/gateway/telemetry {"<dev>":[{"ts":..,"values":{..}}]}
+//! - UART mất kết nối / gateway ngắt kết nối chủ động → v1/gateway/disconnect cho mọi sub-device
+//! - RPC v1/gateway/rpc: setGpio, getGpio, uartTx → Command, phản hồi kết quả cùng topic
+
+use super::topic::{render, TopicContext};
+use crate::commands::{Command, GpioState};
+use crate::config::{Config, FrameMode, MqttConfig};
+use crate::web_api::{json_escape, json_object, jval};
+
+pub const TELEMETRY: &str = "v1/gateway/telemetry";
+pub const ATTRIBUTES: &str = "v1/gateway/attributes";
+pub const CONNECT: &str = "v1/gateway/connect";
+pub const DISCONNECT: &str = "v1/gateway/disconnect";
+pub const RPC: &str = "v1/gateway/rpc";
+
+/// Message đã định dạng: (topic, payload)
+pub type Message = (String, Vec<u8>);
+
+pub struct Gateway {
+    name_template: String,
+    /// Sub-device đã gửi connect trong phiên hiện tại
+    known: Vec<String>,
+}
+
+/// Kết quả 1 RPC: lệnh cần thực thi + phản hồi gửi lại ThingsBoard
+pub struct Rpc {
+    pub command: Option<Command>,
+    pub reply: Message,
+}
+
+impl Gateway {
+    pub fn new(cfg: &Config, mqtt: &MqttConfig) -> Self {
+        let name_template = match (mqtt.tb_device_name.is_empty(), &cfg.uart.frame_mode) {
+            (false, _) => mqtt.tb_device_name.clone(),
+            (true, FrameMode::Modbus) => "{device_name}-slave{slave_id}".into(),
+            (true, _) => "{device_name}-{port}".into(),
+        };
+        Self { name_template, known: Vec::new() }
+    }
+
+    pub fn device_name(&self, cfg: &Config, frame: &[u8]) -> String {
+        render(&self.name_template, &TopicContext::frame(cfg, frame))
+    }
+
+    /// Sub-device chưa báo connect → connect + attributes; đã biết → rỗng
+    pub fn announce(&mut self, cfg: &Config, device: &str, frame: &[u8]) -> Vec<Message> {
+        if self.known.iter().any(|d| d == device) {
+            return Vec::new();
+        }
+        self.known.push(device.to_string());
+        let name = json_escape(device);
+        let ctx = TopicContext::frame(cfg, frame);
+        let slave = ctx.slave_id.map(|id| format!(r#","slave_id":{}"#, id)).unwrap_or_default();
+        let attributes = format!(
+            r#"{{"{}":{{"gateway":"{}","port":"{}","baudrate":{},"firmware":"{}"{}}}}}"#,
+            name, json_escape(&cfg.general.device_name), json_escape(ctx.port), cfg.uart.baudrate,
+            env!("CARGO_PKG_VERSION"), slave,
+        );
+        vec![
+            (CONNECT.into(), format!(r#"{{"device":"{}","type":"ugate-uart"}}"#, name).into_bytes()),
+            (ATTRIBUTES.into(), attributes.into_bytes()),
+        ]
+    }
+
+    /// Báo disconnect mọi sub-device đã connect (UART mất, ngắt kết nối chủ động)
+    pub fn disconnect_all(&mut self) -> Vec<Message> {
+        self.known.drain(..)
+            .map(|d| (DISCONNECT.into(), format!(r#"{{"device":"{}"}}"#, json_escape(&d)).into_bytes()))
+            .collect()
+    }
+}
+
+/// Telemetry 1 frame: frame JSON object dùng nguyên làm values, ngược lại {"data":"<text|hex>"}
+pub fn telemetry(cfg: &Config, device: &str, frame: &[u8], ts_ms: u64) -> Vec<u8> {
+    let text = std::str::from_utf8(frame).ok().map(str::trim);
+    let values = match text {
+        Some(t) if t.starts_with('{') && t.ends_with('}') => t.to_string(),
+        Some(t) if cfg.general.data_as_text => format!(r#"{{"data":"{}"}}"#, json_escape(t)),
+        _ => format!(r#"{{"data":"{}"}}"#, frame.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+    };
+    format!(r#"{{"{}":[{{"ts":{},"values":{}}}]}}"#, json_escape(device), ts_ms, values).into_bytes()
+}
+
+/// {"device":"..","data":{"id":1,"method":"setGpio","params":{"pin":1,"state":true}}}
+/// Phương thức: setGpio (pin, state true/false/1/0/"on"/"off"/"toggle"), getGpio (pin), uartTx (chuỗi hoặc {"data":..})
+pub fn handle_rpc(payload: &str, gpio: &[bool]) -> Option<Rpc> {
+    let device = jval(payload, "device")?;
+    let data = json_object(payload, "data")?;
+    let id = jval(data, "id")?;
+    let method = jval(data, "method").unwrap_or_default();
+    let params = json_object(data, "params").map(str::to_string).or_else(|| jval(data, "params"));
+    let pin = || -> Option<u8> {
+        let pin: u8 = jval(params.as_deref()?, "pin")?.parse().ok()?;
+        (pin >= 1 && pin as usize <= gpio.len()).then_some(pin)
+    };
+    let (command, result) = match method.as_str() {
+        "setGpio" => {
+            // State lạ không được coi là "off" — trả lỗi, không đụng GPIO
+            let state = params.as_deref().and_then(|p| jval(p, "state")).and_then(|state| match state.as_str() {
+                "true" | "1" | "on" | "ON" => Some(GpioState::On),
+                "false" | "0" | "off" | "OFF" => Some(GpioState::Off),
+                "toggle" => Some(GpioState::Toggle),
+                _ => None,
+            });
+            match (pin(), state) {
+                (Some(pin), Some(state)) => (Some(Command::Gpio { pin, state }), r#"{"success":true}"#.to_string()),
+                _ => (None, r#"{"success":false,"error":"invalid pin/state"}"#.to_string()),
+            }
+        }
+        "getGpio" => match pin() {
+            Some(pin) => (None, format!(r#"{{"pin":{},"state":{}}}"#, pin, gpio[pin as usize - 1])),
+            None => (None, r#"{"success":false,"error":"invalid pin"}"#.to_string()),
+        },
+        "uartTx" => match params.as_deref().map(|p| jval(p, "data").unwrap_or_else(|| p.to_string())) {
+            Some(data) if !data.is_empty() => (Some(Command::UartTx { data }), r#"{"success":true}"#.to_string()),
+            _ => (None, r#"{"success":false,"error":"empty data"}"#.to_string()),
+        },
+        _ => (None, format!(r#"{{"success":false,"error":"unknown method '{}'"}}"#, json_escape(&method))),
+    };
+    let reply = format!(r#"{{"device":"{}","id":{},"data":{}}}"#, json_escape(&device), id, result);
+    Some(Rpc { command, reply: (RPC.into(), reply.into_bytes()) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_devices_and_telemetry() {
+        let mut cfg = Config::default();
+        cfg.general.device_name = "gw1".into();
+        let mut gw = Gateway::new(&cfg, &cfg.mqtt);
+        assert_eq!(gw.device_name(&cfg, b"x"), "gw1-ttyS1");
+        assert_eq!(gw.announce(&cfg, "gw1-ttyS1", b"x").len(), 2);
+        assert!(gw.announce(&cfg, "gw1-ttyS1", b"x").is_empty());
+        assert_eq!(gw.disconnect_all()[0].1, br#"{"device":"gw1-ttyS1"}"#.to_vec());
+        assert_eq!(
+            telemetry(&cfg, "d", br#"{"temp":21}"#, 5),
+            br#"{"d":[{"ts":5,"values":{"temp":21}}]}"#.to_vec()
+        );
+        assert_eq!(telemetry(&cfg, "d", &[0x01, 0xab], 5), br#"{"d":[{"ts":5,"values":{"data":"01ab"}}]}"#.to_vec());
+
+        cfg.uart.frame_mode = FrameMode::Modbus;
+        assert_eq!(Gateway::new(&cfg, &cfg.mqtt).device_name(&cfg, &[7, 3]), "gw1-slave7");
+    }
+
+    #[test]
+    fn test_rpc() {
+        let rpc = handle_rpc(r#"{"device":"d","data":{"id":4,"method":"setGpio","params":{"pin":2,"state":true}}}"#, &[false, false]).unwrap();
+        assert!(matches!(rpc.command, Some(Command::Gpio { pin: 2, state: GpioState::On })));
+        assert_eq!(rpc.reply.1, br#"{"device":"d","id":4,"data":{"success":true}}"#.to_vec());
+        let rpc = handle_rpc(r#"{"device":"d","data":{"id":7,"method":"setGpio","params":{"pin":1,"state":"off"}}}"#, &[true]).unwrap();
+        assert!(matches!(rpc.command, Some(Command::Gpio { pin: 1, state: GpioState::Off })));
+        let rpc = handle_rpc(r#"{"device":"d","data":{"id":8,"method":"setGpio","params":{"pin":1,"state":"blink"}}}"#, &[true]).unwrap();
+        assert!(rpc.command.is_none());
+        assert_eq!(rpc.reply.1, br#"{"device":"d","id":8,"data":{"success":false,"error":"invalid pin/state"}}"#.to_vec());
+        let rpc = handle_rpc(r#"{"device":"d","data":{"id":5,"method":"getGpio","params":{"pin":1}}}"#, &[true]).unwrap();
+        assert_eq!(rpc.reply.1, br#"{"device":"d","id":5,"data":{"pin":1,"state":true}}"#.to_vec());
+        let rpc = handle_rpc(r#"{"device":"d","data":{"id":6,"method":"uartTx","params":"hello"}}"#, &[]).unwrap();
+        assert!(matches!(rpc.command, Some(Command::UartTx { ref data }) if data == "hello"));
+        assert!(handle_rpc(r#"{"device":"d","data":{"id":7,"method":"reboot"}}"#, &[]).unwrap().command.is_none());
+    }
+}
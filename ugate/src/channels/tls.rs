@@ -0,0 +1,279 @@
+//! TLS client config cho MQTT: CA hệ thống (webpki-roots) / CA riêng / cả hai,
+//! chứng chỉ client (mTLS, vd AWS IoT Core) và pin SHA-256 chứng chỉ server
+//! File PEM upload qua web API, lưu tại /etc/ugate/certs (giữ qua reboot),
+//! broker phụ dùng thư mục con /etc/ugate/certs/<tên broker>
+
+use crate::config::{MqttConfig, TlsRoots};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub const CERT_DIR: &str = "/etc/ugate/certs";
+
+/// Giới hạn file PEM upload (CA bundle thường < 250KB)
+pub const MAX_PEM_BYTES: usize = 256 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CertKind {
+    /// CA bundle tin cậy (1 hoặc nhiều chứng chỉ)
+    Ca,
+    /// Chứng chỉ client (kèm chain trung gian nếu có)
+    Cert,
+    /// Private key của chứng chỉ client (PKCS#1/PKCS#8/SEC1)
+    Key,
+}
+
+impl CertKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "ca" => Some(Self::Ca),
+            "cert" => Some(Self::Cert),
+            "key" => Some(Self::Key),
+            _ => None,
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            Self::Ca => "ca.pem",
+            Self::Cert => "client.crt",
+            Self::Key => "client.key",
+        }
+    }
+
+    pub fn path(self, broker: &str) -> PathBuf {
+        cert_dir(broker).join(self.file_name())
+    }
+}
+
+/// Thư mục chứng chỉ của 1 kết nối MQTT (section chính: CERT_DIR)
+pub fn cert_dir(broker: &str) -> PathBuf {
+    if broker == PRIMARY {
+        PathBuf::from(CERT_DIR)
+    } else {
+        PathBuf::from(CERT_DIR).join(broker)
+    }
+}
+
+/// Tên kết nối của section `mqtt` chính
+const PRIMARY: &str = "mqtt";
+
+/// Kiểm tra PEM hợp lệ. Trả mô tả ngắn (vd "2 chứng chỉ") hoặc lỗi
+pub fn validate(kind: CertKind, pem: &[u8]) -> Result<String, String> {
+    match kind {
+        CertKind::Ca => {
+            let certs = parse_certs(pem)?;
+            let mut store = RootCertStore::empty();
+            let (added, ignored) = store.add_parsable_certificates(certs);
+            if added == 0 {
+                return Err("không có chứng chỉ CA hợp lệ".into());
+            }
+            Ok(format!("{} chứng chỉ CA ({} bỏ qua)", added, ignored))
+        }
+        CertKind::Cert => {
+            let certs = parse_certs(pem)?;
+            Ok(format!("{} chứng chỉ, sha256 {}", certs.len(), sha256_hex(&certs[0])))
+        }
+        CertKind::Key => {
+            parse_key(pem)?;
+            Ok("private key".into())
+        }
+    }
+}
+
+/// Ghi file PEM (file tạm + rename). Private key chỉ root đọc được
+pub fn store(kind: CertKind, broker: &str, pem: &[u8]) -> Result<String, String> {
+    let summary = validate(kind, pem)?;
+    std::fs::create_dir_all(cert_dir(broker)).map_err(|e| e.to_string())?;
+    let path = kind.path(broker);
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, pem).map_err(|e| e.to_string())?;
+    if kind == CertKind::Key {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600));
+    }
+    std::fs::rename(&tmp, &path).map_err(|e| e.to_string())?;
+    log::info!("[TLS] Lưu {}: {}", path.display(), summary);
+    Ok(summary)
+}
+
+pub fn remove(kind: CertKind, broker: &str) -> bool {
+    std::fs::remove_file(kind.path(broker)).is_ok()
+}
+
+/// JSON trạng thái các file: {"ca":{"present":true,"info":"..."},"cert":{...},"key":{...}}
+pub fn info_json(broker: &str) -> String {
+    let item = |kind: CertKind| -> String {
+        match std::fs::read(kind.path(broker)) {
+            Ok(pem) => {
+                let info = validate(kind, &pem).unwrap_or_else(|e| format!("lỗi: {}", e));
+                format!(r#"{{"present":true,"info":"{}"}}"#, crate::web_api::json_escape(&info))
+            }
+            Err(_) => r#"{"present":false}"#.to_string(),
+        }
+    };
+    format!(
+        r#"{{"ca":{},"cert":{},"key":{}}}"#,
+        item(CertKind::Ca), item(CertKind::Cert), item(CertKind::Key)
+    )
+}
+
+/// Tạo rustls ClientConfig theo cấu hình MQTT + các file đã upload
+pub fn build_client_config(cfg: &MqttConfig) -> Result<ClientConfig, String> {
+    let mut roots = RootCertStore::empty();
+    if cfg.tls_roots != TlsRoots::Custom {
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    }
+    if cfg.tls_roots != TlsRoots::System {
+        let ca_path = CertKind::Ca.path(&cfg.name);
+        let pem = std::fs::read(&ca_path).map_err(|e| format!("đọc {}: {}", ca_path.display(), e))?;
+        roots.add_parsable_certificates(parse_certs(&pem)?);
+    }
+    if roots.is_empty() {
+        return Err("không có CA nào để xác thực broker".into());
+    }
+
+    let builder = if cfg.tls_fingerprint.is_empty() {
+        ClientConfig::builder().with_root_certificates(roots)
+    } else {
+        let pin = parse_fingerprint(&cfg.tls_fingerprint)
+            .ok_or("tls_fingerprint không hợp lệ (cần SHA-256 hex)")?;
+        let inner = WebPkiServerVerifier::builder(Arc::new(roots))
+            .build()
+            .map_err(|e| e.to_string())?;
+        ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedVerifier { inner, pin }))
+    };
+
+    // mTLS khi có đủ chứng chỉ + key client
+    let (cert_path, key_path) = (CertKind::Cert.path(&cfg.name), CertKind::Key.path(&cfg.name));
+    if cert_path.exists() && key_path.exists() {
+        let certs = parse_certs(&std::fs::read(&cert_path).map_err(|e| e.to_string())?)?;
+        let key = parse_key(&std::fs::read(&key_path).map_err(|e| e.to_string())?)?;
+        builder
+            .with_client_auth_cert(certs, key)
+            .map_err(|e| format!("chứng chỉ/key client: {}", e))
+    } else {
+        Ok(builder.with_no_client_auth())
+    }
+}
+
+fn parse_certs(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs: Vec<_> = rustls_pemfile::certs(&mut &pem[..])
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("PEM lỗi: {}", e))?;
+    if certs.is_empty() {
+        return Err("không tìm thấy CERTIFICATE trong PEM".into());
+    }
+    Ok(certs)
+}
+
+fn parse_key(pem: &[u8]) -> Result<PrivateKeyDer<'static>, String> {
+    rustls_pemfile::private_key(&mut &pem[..])
+        .map_err(|e| format!("PEM lỗi: {}", e))?
+        .ok_or_else(|| "không tìm thấy PRIVATE KEY trong PEM".into())
+}
+
+pub fn sha256_hex(der: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, der)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Fingerprint dạng hex, chấp nhận dấu ':' và chữ hoa (vd output của openssl x509 -fingerprint)
+pub(crate) fn parse_fingerprint(s: &str) -> Option<Vec<u8>> {
+    let hex: String = s.chars().filter(|c| *c != ':' && !c.is_whitespace()).collect();
+    // Chỉ ASCII hex: ký tự nhiều byte làm lệch biên khi cắt theo byte
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..64)
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Xác thực chain như bình thường + chứng chỉ server phải khớp fingerprint đã pin
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pin: Vec<u8>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        let digest = ring::digest::digest(&ring::digest::SHA256, end_entity);
+        if digest.as_ref() != self.pin.as_slice() {
+            log::error!("[TLS] Fingerprint broker không khớp: {}", sha256_hex(end_entity));
+            return Err(rustls::Error::General("server certificate fingerprint mismatch".into()));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fingerprint() {
+        let colon = "AB:".repeat(31) + "AB";
+        assert_eq!(parse_fingerprint(&colon), Some(vec![0xAB; 32]));
+        assert_eq!(parse_fingerprint(&"0f".repeat(32)), Some(vec![0x0F; 32]));
+        assert_eq!(parse_fingerprint("abcd"), None);
+        assert_eq!(parse_fingerprint(&"zz".repeat(32)), None);
+        // Ký tự nhiều byte ở vị trí lẻ: đủ 64 byte nhưng không phải hex
+        assert_eq!(parse_fingerprint(&format!("aé{}", "0".repeat(61))), None);
+    }
+
+    #[test]
+    fn test_validate_rejects_garbage() {
+        assert!(validate(CertKind::Ca, b"not a pem").is_err());
+        assert!(validate(CertKind::Cert, b"").is_err());
+        assert!(validate(CertKind::Key, b"-----BEGIN CERTIFICATE-----\n-----END CERTIFICATE-----\n").is_err());
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
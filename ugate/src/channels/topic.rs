@@ -0,0 +1,194 @@
+//! Topic MQTT: template placeholder + chọn topic theo nội dung frame
+//! Placeholder: {device_name}, {mac}, {port}, {slave_id} (byte đầu frame khi frame_mode=modbus)
+//! Rule (UCI `config topic_rule`, xét theo thứ tự, rule đầu tiên khớp thắng):
+//!   byte_offset + byte_value — byte tại offset bằng giá trị (vd 0x01)
+//!   prefix — frame bắt đầu bằng chuỗi (hoặc "hex:0103" cho byte nhị phân)
+//!   field + value — frame là JSON, trường `field` bằng `value`
+//! Không rule nào khớp → topic của broker. Mỗi broker có topic + rule riêng
+
+use crate::config::{Config, FrameMode, MqttConfig, TopicRule};
+use std::sync::OnceLock;
+
+/// Message đã chọn topic: (topic, payload)
+pub type Routed = (String, Vec<u8>);
+
+/// Giá trị thay cho placeholder trong topic
+pub struct TopicContext<'a> {
+    pub device_name: &'a str,
+    pub port: &'a str,
+    pub slave_id: Option<u8>,
+}
+
+impl<'a> TopicContext<'a> {
+    /// Context không gắn với frame (presence, subscribe): {slave_id} rỗng
+    pub fn device(cfg: &'a Config) -> Self {
+        Self { device_name: &cfg.general.device_name, port: port_name(&cfg.uart.port), slave_id: None }
+    }
+
+    pub fn frame(cfg: &'a Config, frame: &[u8]) -> Self {
+        let slave_id = match cfg.uart.frame_mode {
+            FrameMode::Modbus => frame.first().copied(),
+            _ => None,
+        };
+        Self { slave_id, ..Self::device(cfg) }
+    }
+}
+
+/// Thay placeholder trong template. Placeholder không biết giữ nguyên
+pub fn render(template: &str, ctx: &TopicContext) -> String {
+    if !template.contains('{') {
+        return template.to_string();
+    }
+    template
+        .replace("{device_name}", ctx.device_name)
+        .replace("{mac}", device_mac().unwrap_or(""))
+        .replace("{port}", ctx.port)
+        .replace("{slave_id}", &ctx.slave_id.map(|id| id.to_string()).unwrap_or_default())
+}
+
+/// Chọn topic publish trên broker `mqtt` cho 1 frame UART (raw, trước khi wrap JSON)
+pub fn route(cfg: &Config, mqtt: &MqttConfig, frame: &[u8]) -> String {
+    let template = mqtt.topic_rules.iter()
+        .find(|r| rule_matches(r, frame))
+        .map(|r| r.topic.as_str())
+        .unwrap_or(&mqtt.topic);
+    render(template, &TopicContext::frame(cfg, frame))
+}
+
+/// Danh sách topic subscribe (cách nhau bởi dấu cách/phẩy, cho phép wildcard + và #)
+pub fn subscribe_topics(cfg: &Config, mqtt: &MqttConfig) -> Vec<String> {
+    let ctx = TopicContext::device(cfg);
+    mqtt.sub_topic
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|t| !t.is_empty())
+        .map(|t| render(t, &ctx))
+        .collect()
+}
+
+fn rule_matches(rule: &TopicRule, frame: &[u8]) -> bool {
+    match rule {
+        TopicRule { byte: Some((offset, value)), .. } => frame.get(*offset) == Some(value),
+        TopicRule { prefix: Some(prefix), .. } => frame.starts_with(prefix),
+        TopicRule { field: Some((name, value)), .. } => std::str::from_utf8(frame)
+            .ok()
+            .and_then(|json| crate::web_api::jval(json, name))
+            .is_some_and(|v| v == *value),
+        _ => false,
+    }
+}
+
+/// Prefix của rule: chuỗi thường hoặc "hex:0103" → byte. Hex rỗng, lẻ ký tự hoặc có ký tự
+/// không phải hex → None (rule bị bỏ, không âm thầm khớp prefix khác)
+pub fn parse_prefix(prefix: &str) -> Option<Vec<u8>> {
+    let hex = match prefix.strip_prefix("hex:") {
+        Some(hex) => hex,
+        None => return Some(prefix.as_bytes().to_vec()),
+    };
+    if hex.is_empty() || hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len() / 2)
+        .map(|j| hex.get(j * 2..j * 2 + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+/// "/dev/ttyS1" → "ttyS1"
+fn port_name(port: &str) -> &str {
+    port.rsplit('/').next().unwrap_or(port)
+}
+
+/// MAC của thiết bị (12 số hex, không dấu ':'), đọc 1 lần
+pub fn device_mac() -> Option<&'static str> {
+    static MAC: OnceLock<Option<String>> = OnceLock::new();
+    MAC.get_or_init(|| {
+        ["eth0", "br-lan", "wlan0"].iter().find_map(|iface| {
+            let addr = std::fs::read_to_string(format!("/sys/class/net/{}/address", iface)).ok()?;
+            let hex: String = addr.trim().split(':').collect();
+            (hex.len() == 12 && hex != "000000000000").then_some(hex)
+        })
+    })
+    .as_deref()
+}
+
+/// Đầu record có topic trong offline buffer: 0x00 0xFE (không phải đầu text/JSON/UTF-8
+/// hợp lệ) + 'T' + version format. Record cũ là payload thô, gần như không thể trùng 4 byte này
+const RECORD_MAGIC: [u8; 4] = [0x00, 0xFE, b'T', 1];
+
+/// Gói topic vào payload để lưu trong offline buffer: magic + topic + 0x00 + payload
+/// (topic MQTT không được chứa U+0000 nên 0x00 làm dấu phân cách an toàn)
+pub fn pack(routed: &Routed) -> Vec<u8> {
+    let mut out = Vec::with_capacity(RECORD_MAGIC.len() + routed.0.len() + routed.1.len() + 1);
+    out.extend_from_slice(&RECORD_MAGIC);
+    out.extend_from_slice(routed.0.as_bytes());
+    out.push(0);
+    out.extend_from_slice(&routed.1);
+    out
+}
+
+/// Ngược lại `pack`. Record cũ (không có magic) → (None, nguyên payload)
+pub fn unpack(data: &[u8]) -> (Option<&str>, &[u8]) {
+    if let Some(rest) = data.strip_prefix(&RECORD_MAGIC[..]) {
+        if let Some(end) = rest.iter().position(|&b| b == 0) {
+            if let Ok(topic) = std::str::from_utf8(&rest[..end]) {
+                return (Some(topic), &rest[end + 1..]);
+            }
+        }
+    }
+    (None, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(topic: &str) -> TopicRule {
+        TopicRule { topic: topic.into(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_render_and_route() {
+        let mut cfg = Config::default();
+        cfg.general.device_name = "gw1".into();
+        cfg.uart.frame_mode = FrameMode::Modbus;
+        cfg.mqtt.topic = "site/{device_name}/{port}/{slave_id}".into();
+        cfg.mqtt.topic_rules = vec![
+            TopicRule { byte: Some((1, 0x03)), ..rule("read/{slave_id}") },
+            TopicRule { prefix: Some(b"$GP".to_vec()), ..rule("gps") },
+            TopicRule { field: Some(("type".into(), "temp".into())), ..rule("temp") },
+        ];
+        let mqtt = &cfg.mqtt;
+        assert_eq!(route(&cfg, mqtt, &[0x11, 0x03, 0x00]), "read/17");
+        assert_eq!(route(&cfg, mqtt, b"$GPGGA,1"), "gps");
+        assert_eq!(route(&cfg, mqtt, br#"{"type":"temp","v":1}"#), "temp");
+        assert_eq!(route(&cfg, mqtt, &[0x05, 0x10]), "site/gw1/ttyS1/5");
+    }
+
+    #[test]
+    fn test_subscribe_topics() {
+        let mut cfg = Config::default();
+        cfg.general.device_name = "gw1".into();
+        cfg.mqtt.sub_topic = "ugate/{device_name}/cmd/#, ugate/all/+/cmd".into();
+        assert_eq!(subscribe_topics(&cfg, &cfg.mqtt), vec!["ugate/gw1/cmd/#", "ugate/all/+/cmd"]);
+    }
+
+    #[test]
+    fn test_pack_unpack() {
+        let packed = pack(&("a/b".into(), vec![0, 1, 2]));
+        assert_eq!(unpack(&packed), (Some("a/b"), &[0u8, 1, 2][..]));
+        assert_eq!(unpack(b"legacy"), (None, &b"legacy"[..]));
+        // Record cũ bắt đầu bằng 0x00 và có 0x00 khác (vd frame Modbus nhị phân)
+        let frame = [0x00, 0x03, 0x00, 0x01];
+        assert_eq!(unpack(&frame), (None, &frame[..]));
+    }
+
+    #[test]
+    fn test_parse_prefix() {
+        assert_eq!(parse_prefix("$GP"), Some(b"$GP".to_vec()));
+        assert_eq!(parse_prefix("hex:0103"), Some(vec![0x01, 0x03]));
+        assert_eq!(parse_prefix("hex:010"), None);
+        assert_eq!(parse_prefix("hex:01zz"), None);
+        assert_eq!(parse_prefix("hex:"), None);
+        // Ký tự nhiều byte: cắt giữa ký tự không panic
+        assert_eq!(parse_prefix("hex:0é0"), None);
+    }
+}
@@ -1,16 +1,35 @@
 #![allow(dead_code)]
 //! Bộ phân tích lệnh điều khiển GPIO và gửi dữ liệu UART TX
 //! Hỗ trợ 2 định dạng:
-//!   - Text từ UART: "GPIO:1:ON\n"
-//!   - JSON từ WebSocket/TCP/MQTT: {"cmd":"gpio","pin":1,"state":"on"}
+//!   - Text từ UART: "GPIO:1:ON\n", "PWM:0:40\n"
+//!   - JSON từ WebSocket/TCP/MQTT: {"cmd":"gpio","pin":1,"state":"on"}, {"cmd":"pwm","ch":0,"duty":40}
 
 /// Commands that can be received from any source
 #[derive(Debug, Clone)]
 pub enum Command {
     Gpio { pin: u8, state: GpioState },
+    /// duty: 0-100%, period_ns: None = giữ period hiện tại
+    Pwm { ch: u8, duty: u8, period_ns: Option<u32> },
     UartTx { data: String },
 }
 
+impl Command {
+    /// Tên lệnh như trường "cmd" của JSON (dùng trong phản hồi/ack)
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Gpio { .. } => "gpio",
+            Self::Pwm { .. } => "pwm",
+            Self::UartTx { .. } => "uart_tx",
+        }
+    }
+
+    /// Phản hồi khi đã nhận lệnh: lệnh mới được chuyển cho dispatcher, chưa chắc đã thực thi
+    /// xong (GPIO/PWM/UART chạy ở task khác) nên báo "accepted" thay vì "ok"
+    pub fn accepted_json(&self) -> String {
+        format!(r#"{{"accepted":true,"command":"{}"}}"#, self.name())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum GpioState {
     On,
@@ -35,12 +54,21 @@ pub fn parse_uart_command(line: &str) -> Option<Command> {
             };
             Some(Command::Gpio { pin, state })
         }
+        "PWM" => {
+            let ch: u8 = parts[1].parse().ok()?;
+            let duty = pwm_duty(parts[2])?;
+            let period_ns = match parts.get(3) {
+                Some(p) => Some(p.parse().ok()?),
+                None => None,
+            };
+            Some(Command::Pwm { ch, duty, period_ns })
+        }
         _ => None,
     }
 }
 
 /// Parse JSON command: {"cmd":"gpio","pin":1,"state":"on"}
-/// or {"cmd":"uart_tx","data":"hello"}
+/// or {"cmd":"pwm","ch":0,"duty":40,"period_ns":1000000} or {"cmd":"uart_tx","data":"hello"}
 /// Minimal JSON parser — no serde_json dependency
 pub fn parse_json_command(json: &str) -> Option<Command> {
     let cmd = json_str_val(json, "cmd")?;
@@ -55,6 +83,15 @@ pub fn parse_json_command(json: &str) -> Option<Command> {
             };
             Some(Command::Gpio { pin, state })
         }
+        "pwm" => {
+            let ch: u8 = json_str_val(json, "ch")?.parse().ok()?;
+            let duty = pwm_duty(&json_str_val(json, "duty")?)?;
+            let period_ns = match json_str_val(json, "period_ns") {
+                Some(p) => Some(p.parse().ok()?),
+                None => None,
+            };
+            Some(Command::Pwm { ch, duty, period_ns })
+        }
         "uart_tx" => {
             let data = json_str_val(json, "data")?;
             Some(Command::UartTx { data })
@@ -63,6 +100,17 @@ pub fn parse_json_command(json: &str) -> Option<Command> {
     }
 }
 
+/// Duty 0-100%; ngoài khoảng → bỏ lệnh (giống POST /api/pwm trả 400), không tự cắt về 100
+fn pwm_duty(s: &str) -> Option<u8> {
+    match s.trim().parse::<u8>() {
+        Ok(duty) if duty <= 100 => Some(duty),
+        _ => {
+            log::warn!("[Cmd] PWM duty '{}' không hợp lệ (0-100), bỏ lệnh", s.trim());
+            None
+        }
+    }
+}
+
 /// Extract string value for a key from JSON (minimal, no serde)
 fn json_str_val(json: &str, key: &str) -> Option<String> {
     let pattern = format!("\"{}\"", key);
@@ -128,6 +176,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_json_pwm() {
+        let cmd = parse_json_command(r#"{"cmd":"pwm","ch":0,"duty":40}"#).unwrap();
+        match cmd {
+            Command::Pwm { ch, duty, period_ns } => {
+                assert_eq!(ch, 0);
+                assert_eq!(duty, 40);
+                assert_eq!(period_ns, None);
+            }
+            _ => panic!("Expected PWM command"),
+        }
+        assert!(parse_uart_command("PWM:1:150:20000").is_none());
+        assert!(parse_json_command(r#"{"cmd":"pwm","ch":0,"duty":101}"#).is_none());
+        let cmd = parse_uart_command("PWM:1:100:20000").unwrap();
+        match cmd {
+            Command::Pwm { ch, duty, period_ns } => {
+                assert_eq!(ch, 1);
+                assert_eq!(duty, 100);
+                assert_eq!(period_ns, Some(20000));
+            }
+            _ => panic!("Expected PWM command"),
+        }
+    }
+
     #[test]
     fn test_parse_json_uart_tx() {
         let cmd = parse_json_command(r#"{"cmd":"uart_tx","data":"hello"}"#).unwrap();
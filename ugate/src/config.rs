@@ -11,16 +11,24 @@ const UCI_PKG: &str = "ugate";
 #[derive(Clone, Debug)]
 pub struct Config {
     pub mqtt: MqttConfig,
+    /// Broker phụ publish song song (section `config mqtt_broker`), mỗi broker kết nối riêng
+    pub mqtt_brokers: Vec<MqttConfig>,
     pub http: HttpConfig,
     pub tcp: TcpConfig,
     pub uart: UartConfig,
     pub gpio: GpioConfig,
+    pub pwm: PwmConfig,
+    pub button: ButtonConfig,
+    pub watchdog: WatchdogConfig,
+    pub buffer: BufferConfig,
     pub web: WebConfig,
     pub general: GeneralConfig,
 }
 
 #[derive(Clone, Debug)]
 pub struct MqttConfig {
+    /// Tên kết nối hiện trong log/status/thư mục buffer ("mqtt" với section chính)
+    pub name: String,
     pub enabled: bool,
     pub broker: String,
     pub port: u16,
@@ -30,6 +38,89 @@ pub struct MqttConfig {
     pub username: String,
     pub password: String,
     pub qos: u8,
+    /// Topic trạng thái online/offline (retained, LWT). Rỗng = tắt
+    pub status_topic: String,
+    /// Topic telemetry (trạng thái gateway mỗi general.interval_secs). Rỗng = tắt
+    pub telemetry_topic: String,
+    /// Home Assistant MQTT discovery (retained config dưới `ha_prefix`)
+    pub ha_discovery: bool,
+    pub ha_prefix: String,
+    /// Trường JSON trong frame UART hiện thành sensor: (tên, đơn vị)
+    pub ha_fields: Vec<(String, String)>,
+    /// Topic gốc device shadow kiểu AWS IoT (vd `$aws/things/{device_name}/shadow`). Rỗng = tắt
+    pub shadow_topic: String,
+    /// Sparkplug B: thay dữ liệu thường bằng NBIRTH/NDATA/DDATA..., LWT là NDEATH
+    pub sparkplug: bool,
+    pub sparkplug_group: String,
+    /// Edge node ID. Rỗng = device_name
+    pub sparkplug_node: String,
+    pub sparkplug_device: String,
+    /// Trường JSON trong frame UART thành metric của device: (tên, kiểu)
+    pub sparkplug_fields: Vec<(String, String)>,
+    /// Định dạng theo nền tảng cloud (topic + payload cố định)
+    pub preset: MqttPreset,
+    /// ThingsBoard: tên sub-device, hỗ trợ placeholder. Rỗng = theo cổng UART / slave ID
+    pub tb_device_name: String,
+    /// Azure IoT Hub: device connection string (HostName=..;DeviceId=..;SharedAccessKey=..)
+    pub azure_connection_string: String,
+    /// Azure IoT Hub: thời hạn SAS token (giây), tự làm mới trước khi hết hạn
+    pub azure_sas_ttl_secs: u32,
+    /// Client ID cố định. Rỗng = device_name + 6 số cuối MAC
+    pub client_id: String,
+    /// false = broker giữ session (subscription + lệnh QoS 1/2) khi gateway offline
+    pub clean_session: bool,
+    pub keep_alive_secs: u16,
+    /// Số message tối đa chờ broker xác nhận
+    pub inflight: u16,
+    /// CA dùng xác thực broker khi bật TLS
+    pub tls_roots: TlsRoots,
+    /// SHA-256 chứng chỉ broker (hex). Rỗng = không pin
+    pub tls_fingerprint: String,
+    pub version: MqttVersion,
+    /// MQTT 5 message expiry (giây, 0 = không hết hạn). Message trong buffer quá hạn bị bỏ
+    pub message_expiry_secs: u32,
+    /// MQTT 5 user properties thêm vào mọi publish (ngoài device + fw)
+    pub user_properties: Vec<(String, String)>,
+    /// Chọn topic publish theo nội dung frame (section `config topic_rule`)
+    pub topic_rules: Vec<TopicRule>,
+}
+
+/// Rule chọn topic: đặt đúng 1 điều kiện (byte / prefix / field), rule đầu tiên khớp thắng
+#[derive(Clone, Debug, Default)]
+pub struct TopicRule {
+    /// (offset, giá trị) byte trong frame
+    pub byte: Option<(usize, u8)>,
+    /// Frame bắt đầu bằng các byte này
+    pub prefix: Option<Vec<u8>>,
+    /// (tên trường, giá trị) khi frame là JSON
+    pub field: Option<(String, String)>,
+    /// Template topic, hỗ trợ placeholder như mqtt.topic
+    pub topic: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MqttPreset {
+    /// Topic/payload theo cấu hình
+    Generic,
+    /// ThingsBoard MQTT Gateway API (v1/gateway/*)
+    ThingsBoard,
+    /// Azure IoT Hub (SAS token, devices/<id>/messages/events/, direct method)
+    Azure,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MqttVersion {
+    V311,
+    V5,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TlsRoots {
+    /// CA công khai (webpki-roots)
+    System,
+    /// Chỉ CA đã upload (/etc/ugate/certs/ca.pem)
+    Custom,
+    Both,
 }
 
 #[derive(Clone, Debug)]
@@ -37,12 +128,73 @@ pub struct HttpConfig {
     pub enabled: bool,
     pub url: String,
     pub method: HttpMethod,
+    /// true = gửi tuần tự, message lỗi chặn các message sau tới khi gửi được (giữ thứ tự)
+    /// false = gửi song song, message lỗi thử lại sau (không đảm bảo thứ tự)
+    pub ordered: bool,
+    /// Gửi kèm telemetry (trạng thái gateway mỗi general.interval_secs) tới url
+    pub telemetry: bool,
+    /// Header thêm vào mọi request: (tên, giá trị)
+    pub headers: Vec<(String, String)>,
+    pub auth: HttpAuth,
+    /// Basic auth
+    pub username: String,
+    pub password: String,
+    /// Bearer token / API key
+    pub token: String,
+    /// Header chứa API key khi auth = api_key
+    pub api_key_header: String,
+    /// Template body (POST/PUT/PATCH) có placeholder. Rỗng = {"data":..,"len":..} hoặc JSON đã wrap
+    pub body_template: String,
+    /// Số message tối đa mỗi request. 1 = không gộp (mỗi message 1 request)
+    pub batch_max_items: u32,
+    /// Tổng bytes tối đa mỗi batch (tính trên message trước khi định dạng)
+    pub batch_max_bytes: u32,
+    /// Thời gian gom tối đa kể từ message đầu tiên của batch (ms)
+    pub batch_max_ms: u32,
+    pub batch_format: BatchFormat,
+    /// Nén body gzip (Content-Encoding: gzip)
+    pub gzip: bool,
+    /// Số request chạy song song khi ordered = false
+    pub max_concurrent: u8,
+    /// Secret HMAC-SHA256 ký request (X-Ugate-Signature). Rỗng = không ký
+    pub sign_secret: String,
+    /// Chỉ nhận lệnh từ response có chữ ký hợp lệ (cùng secret)
+    pub verify_response: bool,
+    /// URL GET lấy lệnh (downlink poll, độc lập với `enabled`). Rỗng = tắt
+    pub poll_url: String,
+    /// Khoảng nghỉ giữa 2 lần poll (giây). 0 = long-poll liên tục
+    pub poll_interval_secs: u32,
+    /// Timeout request poll (giây), dài hơn thời gian server giữ long-poll
+    pub poll_timeout_secs: u32,
+    /// URL POST kết quả lệnh. Rỗng = poll_url
+    pub poll_ack_url: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BatchFormat {
+    /// [{...},{...}]
+    Json,
+    /// 1 JSON mỗi dòng (application/x-ndjson)
+    Ndjson,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum HttpMethod {
     Post,
     Get,
+    Put,
+    Patch,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HttpAuth {
+    None,
+    /// Authorization: Basic base64(username:password)
+    Basic,
+    /// Authorization: Bearer <token>
+    Bearer,
+    /// <api_key_header>: <token>
+    ApiKey,
 }
 
 #[derive(Clone, Debug)]
@@ -92,7 +244,84 @@ pub enum FrameMode {
 #[derive(Clone, Debug)]
 pub struct GpioConfig {
     pub pins: Vec<u8>,
+    /// Trạng thái an toàn khi tắt, song song với `pins`: Some(level) hoặc None = giữ nguyên.
+    /// Thiếu phần tử → giữ nguyên (relay không bị tắt khi restart)
+    pub safe_states: Vec<Option<bool>>,
+    /// GPIO input (số line kernel, tối đa 4), trạng thái trong status + Home Assistant
+    pub inputs: Vec<u8>,
     pub led_pin: u8,
+    /// LED thứ 2 nháy theo hoạt động UART RX/TX (None = tắt)
+    pub uart_led_pin: Option<u8>,
+    pub led_patterns: LedPatternConfig,
+}
+
+/// Bảng mẫu nháy heartbeat LED theo trạng thái hệ thống
+/// Cú pháp: "on" | "off" | danh sách ms xen kẽ ON,OFF,... (vd "150,150,150,1000")
+#[derive(Clone, Debug)]
+pub struct LedPatternConfig {
+    /// Mọi kênh đang bật đều đã kết nối
+    pub ok: String,
+    /// MQTT hoặc TCP bật nhưng mất kết nối
+    pub degraded: String,
+    /// UART bật nhưng không mở được port
+    pub uart_down: String,
+    /// Đang cài firmware
+    pub upgrade: String,
+}
+
+/// PWM phần cứng: mỗi kênh là 1 section `config pwm` riêng trong UCI
+#[derive(Clone, Debug, Default)]
+pub struct PwmConfig {
+    /// Số pwmchip trong /sys/class/pwm (MT7688: 0)
+    pub chip: u8,
+    pub channels: Vec<PwmChannelConfig>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PwmChannelConfig {
+    pub channel: u8,
+    pub period_ns: u32,
+    /// Duty cycle ban đầu (0-100%)
+    pub duty: u8,
+}
+
+/// Nút reset vật lý (GPIO input)
+#[derive(Clone, Debug)]
+pub struct ButtonConfig {
+    /// None = không theo dõi nút
+    pub pin: Option<u8>,
+    /// Nút kéo xuống GND khi nhấn (MT7688 reset button)
+    pub active_low: bool,
+    /// Giữ ≥ N giây: bật WiFi AP fallback
+    pub ap_hold_secs: u8,
+    /// Giữ ≥ N giây: factory reset
+    pub reset_hold_secs: u8,
+}
+
+/// Hardware watchdog: chỉ pet khi mọi task quan trọng còn báo tiến độ
+#[derive(Clone, Debug)]
+pub struct WatchdogConfig {
+    pub enabled: bool,
+    pub device: String,
+    /// Timeout phần cứng (giây) — không pet quá thời gian này thì board reset
+    pub timeout_secs: u32,
+    /// Task không báo tiến độ quá N giây → coi là kẹt, ngừng pet
+    pub stale_secs: u32,
+    /// Yêu cầu procd nhả /dev/watchdog qua ubus (procd mặc định giữ device)
+    pub takeover_procd: bool,
+}
+
+/// Bộ đệm store-and-forward khi mất kết nối broker/server
+#[derive(Clone, Debug)]
+pub struct BufferConfig {
+    /// Thư mục chứa phần tràn ra disk (/tmp là RAM, mất khi reboot)
+    pub dir: String,
+    /// Số message giữ trong RAM trước khi tràn ra disk
+    pub ram_msgs: usize,
+    /// Dung lượng tối đa trên disk (KB), đầy thì bỏ segment cũ nhất
+    pub max_disk_kb: u32,
+    /// Message cũ hơn N giây bị bỏ khi replay (0 = không giới hạn)
+    pub max_age_secs: u32,
 }
 
 #[derive(Clone, Debug)]
@@ -104,6 +333,7 @@ pub struct WebConfig {
 
 #[derive(Clone, Debug)]
 pub struct GeneralConfig {
+    /// Chu kỳ gửi telemetry (giây, 0 = tắt)
     pub interval_secs: u64,
     pub device_name: String,
     pub wrap_json: bool,
@@ -117,10 +347,15 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             mqtt: MqttConfig::default(),
+            mqtt_brokers: Vec::new(),
             http: HttpConfig::default(),
             tcp: TcpConfig::default(),
             uart: UartConfig::default(),
             gpio: GpioConfig::default(),
+            pwm: PwmConfig::default(),
+            button: ButtonConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            buffer: BufferConfig::default(),
             web: WebConfig::default(),
             general: GeneralConfig::default(),
         }
@@ -130,6 +365,7 @@ impl Default for Config {
 impl Default for MqttConfig {
     fn default() -> Self {
         Self {
+            name: "mqtt".into(),
             enabled: false,
             broker: "broker.emqx.io".into(),
             port: 8883,
@@ -139,13 +375,70 @@ impl Default for MqttConfig {
             username: String::new(),
             password: String::new(),
             qos: 1,
+            status_topic: String::new(),
+            telemetry_topic: String::new(),
+            ha_discovery: false,
+            ha_prefix: "homeassistant".into(),
+            ha_fields: Vec::new(),
+            shadow_topic: String::new(),
+            sparkplug: false,
+            sparkplug_group: "ugate".into(),
+            sparkplug_node: String::new(),
+            sparkplug_device: "uart".into(),
+            sparkplug_fields: Vec::new(),
+            preset: MqttPreset::Generic,
+            tb_device_name: String::new(),
+            azure_connection_string: String::new(),
+            azure_sas_ttl_secs: 3600,
+            client_id: String::new(),
+            clean_session: true,
+            keep_alive_secs: 30,
+            inflight: 10,
+            tls_roots: TlsRoots::System,
+            tls_fingerprint: String::new(),
+            version: MqttVersion::V311,
+            message_expiry_secs: 0,
+            user_properties: Vec::new(),
+            topic_rules: Vec::new(),
         }
     }
 }
 
+impl MqttConfig {
+    /// Nhận frame UART gốc (tự định dạng payload) thay vì payload đã wrap JSON
+    pub fn raw_frames(&self) -> bool {
+        self.sparkplug || self.preset == MqttPreset::ThingsBoard
+    }
+}
+
 impl Default for HttpConfig {
     fn default() -> Self {
-        Self { enabled: false, url: String::new(), method: HttpMethod::Post }
+        Self {
+            enabled: false,
+            url: String::new(),
+            method: HttpMethod::Post,
+            ordered: true,
+            telemetry: false,
+            headers: Vec::new(),
+            auth: HttpAuth::None,
+            username: String::new(),
+            password: String::new(),
+            token: String::new(),
+            api_key_header: "X-API-Key".into(),
+            body_template: String::new(),
+            batch_max_items: 1,
+            batch_max_bytes: 65536,
+            batch_max_ms: 1000,
+            batch_format: BatchFormat::Json,
+            gzip: false,
+            max_concurrent: 4,
+            sign_secret: String::new(),
+            verify_response: false,
+            poll_url: String::new(),
+            poll_interval_secs: 10,
+            poll_timeout_secs: 60,
+            poll_ack_url: String::new(),
+        }
     }
 }
 
@@ -182,7 +475,56 @@ impl Default for GpioConfig {
     fn default() -> Self {
         Self {
             pins: vec![],
+            safe_states: vec![],
+            inputs: vec![],
             led_pin: 44,
+            uart_led_pin: None,
+            led_patterns: LedPatternConfig::default(),
+        }
+    }
+}
+
+impl Default for LedPatternConfig {
+    fn default() -> Self {
+        Self {
+            ok: "1000,1000".into(),
+            degraded: "150,150,150,1000".into(),
+            uart_down: "100,100".into(),
+            upgrade: "50,50,50,50,50,50,500,300".into(),
+        }
+    }
+}
+
+impl Default for ButtonConfig {
+    fn default() -> Self {
+        Self {
+            pin: None,
+            active_low: true,
+            ap_hold_secs: 5,
+            reset_hold_secs: 10,
+        }
+    }
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            device: "/dev/watchdog".into(),
+            timeout_secs: 30,
+            stale_secs: 60,
+            takeover_procd: true,
+        }
+    }
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self {
+            dir: "/tmp/ugate_buffer".into(),
+            ram_msgs: 100,
+            max_disk_kb: 1024,
+            max_age_secs: 86400,
         }
     }
 }
@@ -216,12 +558,106 @@ fn uci_get_or(key: &str, default: &str) -> String {
         .unwrap_or_else(|_| default.to_string())
 }
 
+/// "temp:°C hum:% status" → [(temp, °C), (hum, %), (status, "")] — phần sau ':' tuỳ chọn
+fn parse_fields(s: &str) -> Vec<(String, String)> {
+    s.split_whitespace()
+        .map(|f| match f.split_once(':') {
+            Some((name, extra)) => (name.to_string(), extra.to_string()),
+            None => (f.to_string(), String::new()),
+        })
+        .collect()
+}
+
+/// Đọc 1 section MQTT (`mqtt` chính hoặc `mqtt_broker`). Giá trị mặc định lấy từ `base`
+fn load_mqtt(get: &dyn Fn(&str, &str) -> String, base: MqttConfig) -> MqttConfig {
+    let mut m = base;
+    m.enabled = get("enabled", "0") == "1";
+    m.broker = get("broker", &m.broker);
+    m.port = get("port", "8883").parse().unwrap_or(8883);
+    m.tls = get("tls", "1") == "1";
+    m.topic = get("topic", &m.topic);
+    m.sub_topic = get("sub_topic", &m.sub_topic);
+    m.username = get("username", "");
+    m.password = get("password", "");
+    m.qos = get("qos", "1").parse().unwrap_or(1);
+    m.status_topic = get("status_topic", "");
+    m.telemetry_topic = get("telemetry_topic", "");
+    m.ha_discovery = get("ha_discovery", "0") == "1";
+    m.ha_prefix = get("ha_prefix", &m.ha_prefix);
+    m.ha_fields = parse_fields(&get("ha_fields", ""));
+    m.shadow_topic = get("shadow_topic", "");
+    m.sparkplug = get("sparkplug", "0") == "1";
+    m.sparkplug_group = get("sparkplug_group", &m.sparkplug_group);
+    m.sparkplug_node = get("sparkplug_node", "");
+    m.sparkplug_device = get("sparkplug_device", &m.sparkplug_device);
+    m.sparkplug_fields = parse_fields(&get("sparkplug_fields", ""));
+    m.preset = match get("preset", "").as_str() {
+        "thingsboard" => MqttPreset::ThingsBoard,
+        "azure" => MqttPreset::Azure,
+        _ => MqttPreset::Generic,
+    };
+    m.tb_device_name = get("tb_device_name", "");
+    m.azure_connection_string = get("azure_connection_string", "");
+    m.azure_sas_ttl_secs = get("azure_sas_ttl_secs", "3600").parse().unwrap_or(3600).max(60);
+    m.client_id = get("client_id", "");
+    m.clean_session = get("clean_session", "1") == "1";
+    m.keep_alive_secs = get("keep_alive_secs", "30").parse().unwrap_or(30).max(5);
+    m.inflight = get("inflight", "10").parse().unwrap_or(10).clamp(1, 100);
+    m.tls_roots = match get("tls_roots", "system").as_str() {
+        "custom" => TlsRoots::Custom,
+        "both" => TlsRoots::Both,
+        _ => TlsRoots::System,
+    };
+    m.tls_fingerprint = get("tls_fingerprint", "");
+    m.version = match get("protocol_version", "3.1.1").as_str() {
+        "5" | "5.0" => MqttVersion::V5,
+        _ => MqttVersion::V311,
+    };
+    m.message_expiry_secs = get("message_expiry_secs", "0").parse().unwrap_or(0);
+    m.user_properties = parse_pairs(&get("user_properties", ""));
+    m
+}
+
+/// "key=value,key2=value2" → [(key, value), ...] — giá trị được chứa '=' (vd token base64)
+pub(crate) fn parse_pairs(s: &str) -> Vec<(String, String)> {
+    s.split(',')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .filter(|(k, _)| !k.is_empty())
+        .collect()
+}
+
+/// Ngược lại parse_pairs: [(key, value), ...] → "key=value,key2=value2"
+pub(crate) fn format_pairs(pairs: &[(String, String)]) -> String {
+    pairs.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join(",")
+}
+
+/// "0x03" hoặc "3"
+fn parse_byte(s: &str) -> Option<u8> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
 fn uci_section_get(section: &str, key: &str, default: &str) -> String {
     Uci::get(&format!("{}.@{}[0].{}", UCI_PKG, section, key))
         .unwrap_or_else(|_| default.to_string())
 }
 
 impl Config {
+    /// Kết nối MQTT thứ `idx`: 0 = section `mqtt` chính, 1.. = `mqtt_broker`
+    pub fn mqtt_slot(&self, idx: usize) -> Option<&MqttConfig> {
+        match idx {
+            0 => Some(&self.mqtt),
+            i => self.mqtt_brokers.get(i - 1),
+        }
+    }
+
+    pub fn mqtt_by_name(&self, name: &str) -> Option<&MqttConfig> {
+        std::iter::once(&self.mqtt).chain(&self.mqtt_brokers).find(|m| m.name == name)
+    }
+
     /// Tạo file UCI mặc định nếu chưa có
     pub fn ensure_uci_file() {
         let path = "/etc/config/ugate";
@@ -313,6 +749,23 @@ config upgrade
         uci_set("mqtt", "username", &self.mqtt.username);
         uci_set("mqtt", "password", &self.mqtt.password);
         uci_set("mqtt", "qos", &self.mqtt.qos.to_string());
+        uci_set("mqtt", "tls_roots", match self.mqtt.tls_roots {
+            TlsRoots::System => "system",
+            TlsRoots::Custom => "custom",
+            TlsRoots::Both => "both",
+        });
+        uci_set("mqtt", "tls_fingerprint", &self.mqtt.tls_fingerprint);
+        uci_set("mqtt", "protocol_version", match self.mqtt.version {
+            MqttVersion::V311 => "3.1.1",
+            MqttVersion::V5 => "5",
+        });
+        uci_set("mqtt", "status_topic", &self.mqtt.status_topic);
+        uci_set("mqtt", "client_id", &self.mqtt.client_id);
+        uci_set("mqtt", "clean_session", if self.mqtt.clean_session { "1" } else { "0" });
+        uci_set("mqtt", "keep_alive_secs", &self.mqtt.keep_alive_secs.to_string());
+        uci_set("mqtt", "inflight", &self.mqtt.inflight.to_string());
+        uci_set("mqtt", "message_expiry_secs", &self.mqtt.message_expiry_secs.to_string());
+        uci_set("mqtt", "user_properties", &format_pairs(&self.mqtt.user_properties));
 
         // HTTP
         uci_set("http", "enabled", if self.http.enabled { "1" } else { "0" });
@@ -320,7 +773,38 @@ config upgrade
         uci_set("http", "method", match self.http.method {
             HttpMethod::Get => "get",
             HttpMethod::Post => "post",
+            HttpMethod::Put => "put",
+            HttpMethod::Patch => "patch",
         });
+        uci_set("http", "ordered", if self.http.ordered { "1" } else { "0" });
+        uci_set("http", "telemetry", if self.http.telemetry { "1" } else { "0" });
+        uci_set("http", "headers", &format_pairs(&self.http.headers));
+        uci_set("http", "auth", match self.http.auth {
+            HttpAuth::None => "none",
+            HttpAuth::Basic => "basic",
+            HttpAuth::Bearer => "bearer",
+            HttpAuth::ApiKey => "api_key",
+        });
+        uci_set("http", "username", &self.http.username);
+        uci_set("http", "password", &self.http.password);
+        uci_set("http", "token", &self.http.token);
+        uci_set("http", "api_key_header", &self.http.api_key_header);
+        uci_set("http", "body_template", &self.http.body_template);
+        uci_set("http", "batch_max_items", &self.http.batch_max_items.to_string());
+        uci_set("http", "batch_max_bytes", &self.http.batch_max_bytes.to_string());
+        uci_set("http", "batch_max_ms", &self.http.batch_max_ms.to_string());
+        uci_set("http", "batch_format", match self.http.batch_format {
+            BatchFormat::Json => "json",
+            BatchFormat::Ndjson => "ndjson",
+        });
+        uci_set("http", "gzip", if self.http.gzip { "1" } else { "0" });
+        uci_set("http", "max_concurrent", &self.http.max_concurrent.to_string());
+        uci_set("http", "sign_secret", &self.http.sign_secret);
+        uci_set("http", "verify_response", if self.http.verify_response { "1" } else { "0" });
+        uci_set("http", "poll_url", &self.http.poll_url);
+        uci_set("http", "poll_interval_secs", &self.http.poll_interval_secs.to_string());
+        uci_set("http", "poll_timeout_secs", &self.http.poll_timeout_secs.to_string());
+        uci_set("http", "poll_ack_url", &self.http.poll_ack_url);
 
         // TCP
         uci_set("tcp", "enabled", if self.tcp.enabled { "1" } else { "0" });
@@ -364,26 +848,71 @@ config upgrade
     /// Load config from UCI `/etc/config/ugate`
     pub fn load() -> Self {
         Self::ensure_uci_file();
-        let mut cfg = Config::default();
-
-        // MQTT
-        cfg.mqtt.enabled = uci_section_get("mqtt", "enabled", "0") == "1";
-        cfg.mqtt.broker = uci_section_get("mqtt", "broker", &cfg.mqtt.broker);
-        cfg.mqtt.port = uci_section_get("mqtt", "port", "8883").parse().unwrap_or(8883);
-        cfg.mqtt.tls = uci_section_get("mqtt", "tls", "1") == "1";
-        cfg.mqtt.topic = uci_section_get("mqtt", "topic", &cfg.mqtt.topic);
-        cfg.mqtt.sub_topic = uci_section_get("mqtt", "sub_topic", &cfg.mqtt.sub_topic);
-        cfg.mqtt.username = uci_section_get("mqtt", "username", "");
-        cfg.mqtt.password = uci_section_get("mqtt", "password", "");
-        cfg.mqtt.qos = uci_section_get("mqtt", "qos", "1").parse().unwrap_or(1);
+        // MQTT: section chính + các broker phụ @mqtt_broker[i], dừng khi hết section
+        let mut cfg = Config {
+            mqtt: load_mqtt(&|key, default| uci_section_get("mqtt", key, default), MqttConfig::default()),
+            ..Config::default()
+        };
+        for i in 0..crate::channels::mqtt::MAX_BROKERS - 1 {
+            let get = |key: &str, default: &str| {
+                Uci::get(&format!("{}.@mqtt_broker[{}].{}", UCI_PKG, i, key))
+                    .unwrap_or_else(|_| default.to_string())
+            };
+            if Uci::get(&format!("{}.@mqtt_broker[{}].broker", UCI_PKG, i)).is_err() {
+                break;
+            }
+            let fallback = format!("broker{}", i + 1);
+            let mut broker = load_mqtt(&get, MqttConfig { name: get("name", &fallback), ..MqttConfig::default() });
+            // Tên dùng làm thư mục buffer/chứng chỉ: chỉ chữ, số, '-', '_' và không trùng
+            let valid = broker.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid || broker.name.is_empty() || cfg.mqtt_by_name(&broker.name).is_some() {
+                log::warn!("[Config] mqtt_broker[{}]: tên '{}' không hợp lệ/trùng, dùng '{}'", i, broker.name, fallback);
+                broker.name = fallback;
+            }
+            cfg.mqtt_brokers.push(broker);
+        }
 
         // HTTP
         cfg.http.enabled = uci_section_get("http", "enabled", "0") == "1";
         cfg.http.url = uci_section_get("http", "url", "");
         cfg.http.method = match uci_section_get("http", "method", "post").as_str() {
             "get" => HttpMethod::Get,
+            "put" => HttpMethod::Put,
+            "patch" => HttpMethod::Patch,
             _ => HttpMethod::Post,
         };
+        cfg.http.ordered = uci_section_get("http", "ordered", "1") == "1";
+        cfg.http.telemetry = uci_section_get("http", "telemetry", "0") == "1";
+        cfg.http.headers = parse_pairs(&uci_section_get("http", "headers", ""));
+        cfg.http.auth = match uci_section_get("http", "auth", "none").as_str() {
+            "basic" => HttpAuth::Basic,
+            "bearer" => HttpAuth::Bearer,
+            "api_key" => HttpAuth::ApiKey,
+            _ => HttpAuth::None,
+        };
+        cfg.http.username = uci_section_get("http", "username", "");
+        cfg.http.password = uci_section_get("http", "password", "");
+        cfg.http.token = uci_section_get("http", "token", "");
+        cfg.http.api_key_header = uci_section_get("http", "api_key_header", &cfg.http.api_key_header);
+        cfg.http.body_template = uci_section_get("http", "body_template", "");
+        cfg.http.batch_max_items = uci_section_get("http", "batch_max_items", "1").parse().unwrap_or(1).clamp(1, 1000);
+        cfg.http.batch_max_bytes = uci_section_get("http", "batch_max_bytes", "65536").parse().unwrap_or(65536).max(256);
+        cfg.http.batch_max_ms = uci_section_get("http", "batch_max_ms", "1000").parse().unwrap_or(1000);
+        cfg.http.batch_format = match uci_section_get("http", "batch_format", "json").as_str() {
+            "ndjson" => BatchFormat::Ndjson,
+            _ => BatchFormat::Json,
+        };
+        cfg.http.gzip = uci_section_get("http", "gzip", "0") == "1";
+        cfg.http.max_concurrent = uci_section_get("http", "max_concurrent", "4").parse().unwrap_or(4).clamp(1, 16);
+        cfg.http.sign_secret = uci_section_get("http", "sign_secret", "");
+        cfg.http.verify_response = uci_section_get("http", "verify_response", "0") == "1";
+        if cfg.http.verify_response && cfg.http.sign_secret.is_empty() {
+            log::warn!("[Config] http.verify_response bật nhưng sign_secret rỗng: mọi lệnh trong response bị bỏ");
+        }
+        cfg.http.poll_url = uci_section_get("http", "poll_url", "");
+        cfg.http.poll_interval_secs = uci_section_get("http", "poll_interval_secs", "10").parse().unwrap_or(10);
+        cfg.http.poll_timeout_secs = uci_section_get("http", "poll_timeout_secs", "60").parse().unwrap_or(60).clamp(5, 600);
+        cfg.http.poll_ack_url = uci_section_get("http", "poll_ack_url", "");
 
         // TCP
         cfg.tcp.enabled = uci_section_get("tcp", "enabled", "0") == "1";
@@ -418,12 +947,115 @@ config upgrade
 
         // GPIO
         cfg.gpio.led_pin = uci_section_get("gpio", "led_pin", "44").parse().unwrap_or(44);
+        cfg.gpio.uart_led_pin = uci_section_get("gpio", "uart_led_pin", "").parse().ok();
+        let led = &mut cfg.gpio.led_patterns;
+        led.ok = uci_section_get("gpio", "led_ok", &led.ok);
+        led.degraded = uci_section_get("gpio", "led_degraded", &led.degraded);
+        led.uart_down = uci_section_get("gpio", "led_uart_down", &led.uart_down);
+        led.upgrade = uci_section_get("gpio", "led_upgrade", &led.upgrade);
         // Parse pins list from UCI
         if let Ok(pins_str) = Uci::get(&format!("{}.@gpio[0].pins", UCI_PKG)) {
             cfg.gpio.pins = pins_str.split_whitespace()
                 .filter_map(|s| s.parse().ok())
                 .collect();
         }
+        if let Ok(inputs_str) = Uci::get(&format!("{}.@gpio[0].inputs", UCI_PKG)) {
+            cfg.gpio.inputs = inputs_str.split_whitespace()
+                .filter_map(|s| s.parse().ok())
+                .take(4)
+                .collect();
+        }
+        if let Ok(states_str) = Uci::get(&format!("{}.@gpio[0].safe_states", UCI_PKG)) {
+            cfg.gpio.safe_states = states_str.split_whitespace()
+                .map(|s| match s {
+                    "on" | "1" => Some(true),
+                    "keep" => None,
+                    _ => Some(false),
+                })
+                .collect();
+        }
+
+        // Topic rule: mỗi rule 1 section anonymous @topic_rule[i], dừng khi hết section
+        for i in 0.. {
+            let topic = match Uci::get(&format!("{}.@topic_rule[{}].topic", UCI_PKG, i)) {
+                Ok(t) => t,
+                Err(_) => break,
+            };
+            let get = |key: &str| Uci::get(&format!("{}.@topic_rule[{}].{}", UCI_PKG, i, key)).ok();
+            let prefix = get("prefix").filter(|p| !p.is_empty());
+            let rule = TopicRule {
+                byte: get("byte_offset")
+                    .and_then(|o| o.parse().ok())
+                    .zip(get("byte_value").and_then(|v| parse_byte(&v))),
+                prefix: prefix.as_deref().and_then(crate::channels::topic::parse_prefix),
+                field: get("field").zip(get("value")),
+                topic,
+            };
+            if prefix.is_some() && rule.prefix.is_none() {
+                log::warn!("[Config] topic_rule[{}]: prefix hex '{}' không hợp lệ, bỏ qua rule", i, prefix.unwrap_or_default());
+                continue;
+            }
+            if rule.byte.is_none() && rule.prefix.is_none() && rule.field.is_none() {
+                log::warn!("[Config] topic_rule[{}] không có điều kiện, bỏ qua", i);
+                continue;
+            }
+            // `broker` giới hạn rule cho 1 kết nối, không đặt = áp dụng cho mọi broker
+            let scope = get("broker").filter(|b| !b.is_empty());
+            for m in std::iter::once(&mut cfg.mqtt).chain(cfg.mqtt_brokers.iter_mut()) {
+                if scope.as_ref().is_none_or(|b| *b == m.name) {
+                    m.topic_rules.push(rule.clone());
+                }
+            }
+        }
+
+        // PWM: mỗi kênh 1 section anonymous @pwm[i], dừng khi hết section. Chip chung, đọc ở @pwm[0]
+        cfg.pwm.chip = Uci::get(&format!("{}.@pwm[0].chip", UCI_PKG))
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        for i in 0..crate::pwm::PWM_MAX_CHANNELS {
+            let channel: u8 = match Uci::get(&format!("{}.@pwm[{}].channel", UCI_PKG, i)) {
+                Ok(v) => match v.parse() {
+                    Ok(c) => c,
+                    Err(_) => continue,
+                },
+                Err(_) => break,
+            };
+            let get = |key: &str, default: &str| {
+                Uci::get(&format!("{}.@pwm[{}].{}", UCI_PKG, i, key))
+                    .unwrap_or_else(|_| default.to_string())
+            };
+            cfg.pwm.channels.push(PwmChannelConfig {
+                channel,
+                period_ns: get("period_ns", "1000000").parse().unwrap_or(1_000_000),
+                duty: get("duty", "0").parse::<u8>().unwrap_or(0).min(100),
+            });
+        }
+
+        // Button
+        cfg.button.pin = uci_section_get("button", "pin", "").parse().ok();
+        cfg.button.active_low = uci_section_get("button", "active_low", "1") == "1";
+        cfg.button.ap_hold_secs = uci_section_get("button", "ap_hold_secs", "5").parse().unwrap_or(5);
+        cfg.button.reset_hold_secs = uci_section_get("button", "reset_hold_secs", "10").parse().unwrap_or(10);
+        if cfg.button.reset_hold_secs <= cfg.button.ap_hold_secs {
+            let fixed = cfg.button.ap_hold_secs.saturating_add(5);
+            log::warn!("[Config] button.reset_hold_secs ({}) phải lớn hơn ap_hold_secs ({}), dùng {}",
+                cfg.button.reset_hold_secs, cfg.button.ap_hold_secs, fixed);
+            cfg.button.reset_hold_secs = fixed;
+        }
+
+        // Watchdog
+        cfg.watchdog.enabled = uci_section_get("watchdog", "enabled", "0") == "1";
+        cfg.watchdog.device = uci_section_get("watchdog", "device", &cfg.watchdog.device);
+        cfg.watchdog.timeout_secs = uci_section_get("watchdog", "timeout_secs", "30").parse().unwrap_or(30);
+        cfg.watchdog.stale_secs = uci_section_get("watchdog", "stale_secs", "60").parse().unwrap_or(60);
+        cfg.watchdog.takeover_procd = uci_section_get("watchdog", "takeover_procd", "1") == "1";
+
+        // Offline buffer
+        cfg.buffer.dir = uci_section_get("buffer", "dir", &cfg.buffer.dir);
+        cfg.buffer.ram_msgs = uci_section_get("buffer", "ram_msgs", "100").parse().unwrap_or(100);
+        cfg.buffer.max_disk_kb = uci_section_get("buffer", "max_disk_kb", "1024").parse().unwrap_or(1024);
+        cfg.buffer.max_age_secs = uci_section_get("buffer", "max_age_secs", "86400").parse().unwrap_or(86400);
 
         // Web
         cfg.web.port = uci_section_get("web", "port", "8888").parse().unwrap_or(8888);
@@ -450,7 +1082,8 @@ config upgrade
 pub struct AppState {
     config: RwLock<Config>,
     config_tx: watch::Sender<()>,
-    mqtt_notify: RwLock<Option<mpsc::Sender<()>>>,
+    /// Mỗi MQTT thread (1 broker) 1 kênh báo kết nối lại
+    mqtt_notify: RwLock<Vec<mpsc::Sender<()>>>,
 }
 
 impl AppState {
@@ -459,12 +1092,12 @@ impl AppState {
         Self {
             config: RwLock::new(config),
             config_tx,
-            mqtt_notify: RwLock::new(None),
+            mqtt_notify: RwLock::new(Vec::new()),
         }
     }
 
-    pub fn set_mqtt_notifier(&self, tx: mpsc::Sender<()>) {
-        *self.mqtt_notify.write().unwrap() = Some(tx);
+    pub fn add_mqtt_notifier(&self, tx: mpsc::Sender<()>) {
+        self.mqtt_notify.write().unwrap().push(tx);
     }
 
     pub fn get(&self) -> Config {
@@ -475,12 +1108,17 @@ impl AppState {
         self.config_tx.subscribe()
     }
 
+    /// MQTT kết nối lại mà không đổi config (vd: vừa thay chứng chỉ TLS)
+    pub fn reconnect_mqtt(&self) {
+        for tx in self.mqtt_notify.read().unwrap().iter() {
+            let _ = tx.send(());
+        }
+    }
+
     pub fn update(&self, new_config: Config) {
         *self.config.write().unwrap() = new_config;
         let _ = self.config_tx.send(());
-        if let Some(tx) = self.mqtt_notify.read().unwrap().as_ref() {
-            let _ = tx.send(());
-        }
+        self.reconnect_mqtt();
         log::info!("[Config] Updated, publishers will reconnect");
     }
 }
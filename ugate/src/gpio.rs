@@ -1,13 +1,13 @@
 //! Điều khiển GPIO qua chardev ioctl (API kernel hiện đại)
 //! Rust thuần, không cần libgpiod, dễ cross-compile cho MIPS
-//! Hỗ trợ: set ON/OFF, toggle, heartbeat LED
+//! Hỗ trợ: set ON/OFF, toggle, heartbeat LED theo trạng thái hệ thống, LED hoạt động UART
 
 use crate::commands::{Command, GpioState};
 use crate::web_api::status::SharedStats;
 use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 // --- ioctl constants (từ linux/gpio.h) ---
 
@@ -20,7 +20,9 @@ type IoctlNum = libc::c_ulong;
 const GPIO_GET_LINEHANDLE_IOCTL: IoctlNum = 0xC16CB403u32 as IoctlNum;
 const GPIOHANDLE_SET_LINE_VALUES_IOCTL: IoctlNum = 0xC040B409u32 as IoctlNum;
 const GPIOHANDLE_GET_LINE_VALUES_IOCTL: IoctlNum = 0xC040B408u32 as IoctlNum;
+const GPIOHANDLE_REQUEST_INPUT: u32 = 0x01;
 const GPIOHANDLE_REQUEST_OUTPUT: u32 = 0x02;
+const GPIOHANDLE_REQUEST_ACTIVE_LOW: u32 = 0x04;
 
 #[repr(C)]
 struct GpioHandleRequest {
@@ -37,20 +39,34 @@ struct GpioHandleData {
     values: [u8; GPIOHANDLES_MAX],
 }
 
-/// Wrapper cho 1 GPIO line (output)
-struct GpioLine {
+/// Wrapper cho 1 GPIO line (output hoặc input)
+pub(crate) struct GpioLine {
     handle: std::fs::File,
 }
 
 impl GpioLine {
     /// Yêu cầu 1 line output từ GPIO chip
     fn request_output(chip: &str, line: u32, initial: bool) -> std::io::Result<Self> {
+        Self::request(chip, line, GPIOHANDLE_REQUEST_OUTPUT, initial)
+    }
+
+    /// Yêu cầu 1 line input (nút nhấn). active_low: kernel tự đảo mức → true = đang nhấn
+    pub(crate) fn request_input(chip: &str, line: u32, active_low: bool) -> std::io::Result<Self> {
+        let flags = if active_low {
+            GPIOHANDLE_REQUEST_INPUT | GPIOHANDLE_REQUEST_ACTIVE_LOW
+        } else {
+            GPIOHANDLE_REQUEST_INPUT
+        };
+        Self::request(chip, line, flags, false)
+    }
+
+    fn request(chip: &str, line: u32, flags: u32, initial: bool) -> std::io::Result<Self> {
         let chip_path = format!("/dev/{}", chip);
         let chip_file = std::fs::File::open(&chip_path)?;
 
         let mut req = GpioHandleRequest {
             lineoffsets: [0; GPIOHANDLES_MAX],
-            flags: GPIOHANDLE_REQUEST_OUTPUT,
+            flags,
             default_values: [0; GPIOHANDLES_MAX],
             consumer_label: [0; 32],
             lines: 1,
@@ -85,7 +101,7 @@ impl GpioLine {
         if ret < 0 { Err(std::io::Error::last_os_error()) } else { Ok(()) }
     }
 
-    fn get_value(&self) -> std::io::Result<bool> {
+    pub(crate) fn get_value(&self) -> std::io::Result<bool> {
         let mut data = GpioHandleData { values: [0; GPIOHANDLES_MAX] };
         let ret = unsafe {
             libc::ioctl(self.handle.as_raw_fd(), GPIOHANDLE_GET_LINE_VALUES_IOCTL, &mut data)
@@ -101,10 +117,144 @@ impl GpioLine {
     }
 }
 
+/// Mẫu nháy LED: chuỗi thời gian (ms) xen kẽ ON/OFF, bắt đầu bằng ON
+#[derive(Clone, Debug, PartialEq)]
+pub enum LedPattern {
+    Solid(bool),
+    Blink(Vec<u32>),
+}
+
+impl LedPattern {
+    /// Parse "on" | "off" | "150,150,150,1000"
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim() {
+            "on" => Some(Self::Solid(true)),
+            "off" => Some(Self::Solid(false)),
+            list => {
+                let steps: Vec<u32> = list
+                    .split(',')
+                    .map(|p| p.trim().parse().ok())
+                    .collect::<Option<_>>()?;
+                if steps.is_empty() || steps.iter().sum::<u32>() == 0 {
+                    return None;
+                }
+                Some(Self::Blink(steps))
+            }
+        }
+    }
+
+    /// Parse với fallback về mẫu mặc định khi cấu hình sai
+    fn parse_or(s: &str, default: &str) -> Self {
+        Self::parse(s).unwrap_or_else(|| {
+            log::warn!("[GPIO] LED pattern '{}' không hợp lệ, dùng '{}'", s, default);
+            Self::parse(default).unwrap_or(Self::Solid(false))
+        })
+    }
+
+    /// Trạng thái LED tại thời điểm `ms` kể từ lúc bắt đầu mẫu (lặp vô hạn)
+    pub fn level_at(&self, ms: u64) -> bool {
+        match self {
+            Self::Solid(on) => *on,
+            Self::Blink(steps) => {
+                let total: u64 = steps.iter().map(|&s| s as u64).sum();
+                let mut t = ms % total;
+                for (i, &step) in steps.iter().enumerate() {
+                    if t < step as u64 {
+                        return i % 2 == 0;
+                    }
+                    t -= step as u64;
+                }
+                false
+            }
+        }
+    }
+}
+
+/// Trạng thái sức khoẻ hệ thống hiển thị qua heartbeat LED (ưu tiên từ trên xuống)
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Health {
+    /// Nút reset đang được giữ — LED báo hành động sẽ thực hiện khi thả
+    ButtonHeld(crate::button::HoldStage),
+    Upgrading,
+    UartDown,
+    Degraded,
+    Ok,
+}
+
+impl Health {
+    fn current(stats: &SharedStats) -> Self {
+        if let Some(stage) = crate::button::HoldStage::from_u8(stats.button_stage.load(Ordering::Relaxed)) {
+            Self::ButtonHeld(stage)
+        } else if crate::web_api::maintenance::is_upgrading() {
+            Self::Upgrading
+        } else if stats.uart_state.load(Ordering::Relaxed) == 1 {
+            Self::UartDown
+        } else if stats.mqtt.iter().any(|m| m.state.load(Ordering::Relaxed) == 1)
+            // Chỉ client mất kết nối mới là lỗi; server đang chờ client (3) vẫn bình thường
+            || stats.tcp_state.load(Ordering::Relaxed) == 1
+        {
+            Self::Degraded
+        } else {
+            Self::Ok
+        }
+    }
+}
+
+/// Bảng mẫu LED đã parse từ config
+struct LedPatterns {
+    ok: LedPattern,
+    degraded: LedPattern,
+    uart_down: LedPattern,
+    upgrade: LedPattern,
+    /// Phản hồi khi giữ nút: cố định, không cấu hình
+    button: [LedPattern; 3],
+}
+
+impl LedPatterns {
+    fn from_config(cfg: &crate::config::LedPatternConfig) -> Self {
+        let def = crate::config::LedPatternConfig::default();
+        Self {
+            ok: LedPattern::parse_or(&cfg.ok, &def.ok),
+            degraded: LedPattern::parse_or(&cfg.degraded, &def.degraded),
+            uart_down: LedPattern::parse_or(&cfg.uart_down, &def.uart_down),
+            upgrade: LedPattern::parse_or(&cfg.upgrade, &def.upgrade),
+            button: [
+                LedPattern::Solid(true),             // < ap_hold: restart service
+                LedPattern::Blink(vec![100, 100]),   // ≥ ap_hold: bật WiFi AP
+                LedPattern::Blink(vec![40, 40]),     // ≥ reset_hold: factory reset
+            ],
+        }
+    }
+
+    fn for_health(&self, health: Health) -> &LedPattern {
+        match health {
+            Health::Ok => &self.ok,
+            Health::Degraded => &self.degraded,
+            Health::UartDown => &self.uart_down,
+            Health::Upgrading => &self.upgrade,
+            Health::ButtonHeld(stage) => &self.button[stage as usize],
+        }
+    }
+}
+
+/// Mở 1 LED output, log lỗi và trả None nếu không có
+fn open_led(chip: &str, pin: u8, name: &str) -> Option<GpioLine> {
+    match GpioLine::request_output(chip, pin as u32, false) {
+        Ok(line) => {
+            log::info!("[GPIO] {} pin {} sẵn sàng", name, pin);
+            Some(line)
+        }
+        Err(e) => {
+            log::warn!("[GPIO] {} pin {} lỗi: {} (bỏ qua)", name, pin, e);
+            None
+        }
+    }
+}
+
 /// Task GPIO: nhận lệnh từ channel, điều khiển output + heartbeat LED
 pub async fn run(
     config: crate::config::GpioConfig,
-    mut cmd_rx: tokio::sync::mpsc::Receiver<Command>,
+    cmd_rx: &mut tokio::sync::mpsc::Receiver<Command>,
     stats: Arc<SharedStats>,
 ) {
     // Thử mở GPIO chip, nếu không có thì chỉ log warning
@@ -124,22 +274,35 @@ pub async fn run(
         }
     }
 
-    // Heartbeat LED
-    let heartbeat = match GpioLine::request_output(chip, config.led_pin as u32, false) {
-        Ok(line) => {
-            log::info!("[GPIO] Heartbeat LED pin {} sẵn sàng", config.led_pin);
-            Some(line)
-        }
-        Err(e) => {
-            log::warn!("[GPIO] Heartbeat LED pin {} lỗi: {} (bỏ qua)", config.led_pin, e);
-            None
-        }
-    };
+    // Input: đọc mức mỗi tick LED (50ms), lưu vào stats
+    let inputs: Vec<Option<GpioLine>> = config.inputs.iter()
+        .map(|&pin| match GpioLine::request_input(chip, pin as u32, false) {
+            Ok(line) => {
+                log::info!("[GPIO] Pin {} sẵn sàng (input)", pin);
+                Some(line)
+            }
+            Err(e) => {
+                log::warn!("[GPIO] Không thể mở input {}: {} (bỏ qua)", pin, e);
+                None
+            }
+        })
+        .collect();
 
-    let mut heartbeat_interval = tokio::time::interval(Duration::from_millis(500));
+    // Heartbeat LED (mẫu nháy theo trạng thái) + LED hoạt động UART (tuỳ chọn)
+    let heartbeat = open_led(chip, config.led_pin, "Heartbeat LED");
+    let activity = config.uart_led_pin.and_then(|pin| open_led(chip, pin, "UART LED"));
+    let patterns = LedPatterns::from_config(&config.led_patterns);
+
+    let mut health = Health::current(&stats);
+    let mut pattern_start = Instant::now();
+    let mut hb_level = false;
+    let mut act_level = false;
+    let mut last_frames = 0u32;
+    let mut led_tick = tokio::time::interval(Duration::from_millis(50));
 
     loop {
         tokio::select! {
+            _ = crate::shutdown::wait() => break,
             Some(cmd) = cmd_rx.recv() => {
                 if let Command::Gpio { pin, state } = cmd {
                     let idx = (pin.saturating_sub(1)) as usize;
@@ -161,11 +324,79 @@ pub async fn run(
                     }
                 }
             }
-            _ = heartbeat_interval.tick() => {
+            _ = led_tick.tick() => {
+                for (idx, line) in inputs.iter().enumerate() {
+                    if let Some(level) = line.as_ref().and_then(|l| l.get_value().ok()) {
+                        stats.gpio_inputs[idx].store(level as u8, Ordering::Relaxed);
+                    }
+                }
                 if let Some(ref hb) = heartbeat {
-                    let _ = hb.toggle();
+                    let now_health = Health::current(&stats);
+                    if now_health != health {
+                        log::info!("[GPIO] Heartbeat LED: {:?} → {:?}", health, now_health);
+                        health = now_health;
+                        pattern_start = Instant::now();
+                    }
+                    let ms = pattern_start.elapsed().as_millis() as u64;
+                    let level = patterns.for_health(health).level_at(ms);
+                    if level != hb_level && hb.set_value(level).is_ok() {
+                        hb_level = level;
+                    }
                 }
+                // LED UART: sáng 1 tick khi bộ đếm frame RX/TX thay đổi
+                if let Some(ref act) = activity {
+                    let frames = stats.uart_rx_frames.load(Ordering::Relaxed)
+                        .wrapping_add(stats.uart_tx_frames.load(Ordering::Relaxed));
+                    let level = frames != last_frames;
+                    last_frames = frames;
+                    if level != act_level && act.set_value(level).is_ok() {
+                        act_level = level;
+                    }
+                }
+            }
+        }
+    }
+
+    // Tắt êm: đưa output về trạng thái an toàn, tắt LED, giữ line tới khi process thoát
+    // (nhả line thì kernel không đảm bảo giữ mức đã đặt)
+    for (idx, line) in outputs.iter().enumerate() {
+        let safe = config.safe_states.get(idx).copied().flatten();
+        if let (Some(line), Some(level)) = (line, safe) {
+            match line.set_value(level) {
+                Ok(()) => log::info!("[GPIO] Pin {} → {} (an toàn)", config.pins[idx], if level { "ON" } else { "OFF" }),
+                Err(e) => log::error!("[GPIO] Pin {} không về trạng thái an toàn: {}", config.pins[idx], e),
             }
         }
     }
+    for led in heartbeat.iter().chain(activity.iter()) {
+        let _ = led.set_value(false);
+    }
+    crate::shutdown::done(crate::shutdown::Part::Gpio);
+    std::future::pending::<()>().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_led_pattern_parse() {
+        assert_eq!(LedPattern::parse("on"), Some(LedPattern::Solid(true)));
+        assert_eq!(LedPattern::parse("100, 200"), Some(LedPattern::Blink(vec![100, 200])));
+        assert_eq!(LedPattern::parse(""), None);
+        assert_eq!(LedPattern::parse("0,0"), None);
+        assert_eq!(LedPattern::parse("abc"), None);
+    }
+
+    #[test]
+    fn test_led_pattern_double_blink() {
+        let p = LedPattern::parse("150,150,150,1000").unwrap();
+        assert!(p.level_at(0));
+        assert!(!p.level_at(200));
+        assert!(p.level_at(350));
+        assert!(!p.level_at(500));
+        assert!(!p.level_at(1400));
+        // Lặp lại sau 1450ms
+        assert!(p.level_at(1450));
+    }
 }
@@ -3,19 +3,26 @@
 //! Đọc dữ liệu UART từ MCU, fan-out qua MQTT/HTTP/TCP, điều khiển GPIO
 //! Web UI quản lý cấu hình qua trình duyệt
 
+mod button;
 mod channels;
 mod commands;
 mod config;
 mod gpio;
+mod pwm;
+mod shutdown;
+mod supervisor;
+mod telemetry;
 mod time_sync;
 mod uart;
 mod uci;
+mod watchdog;
 mod web_api;
 
 use config::{AppState, Config};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::broadcast;
+use watchdog::Task;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
@@ -43,8 +50,11 @@ async fn main() {
     // Broadcast UART: phân phối frame thô tới tất cả subscriber
     let (uart_broadcast_tx, _) = tokio::sync::broadcast::channel::<Vec<u8>>(64);
 
-    // Kênh MQTT: std mpsc (MQTT chạy trên OS thread riêng)
-    let (mqtt_tx, mqtt_rx) = std::sync::mpsc::channel::<Vec<u8>>();
+    // Kênh MQTT: std mpsc (MQTT chạy trên OS thread riêng), mỗi broker 1 kênh + 1 thread
+    // Số broker cố định lúc khởi động (thêm/bớt section mqtt_broker cần restart)
+    let broker_count = (1 + config.mqtt_brokers.len()).min(channels::mqtt::MAX_BROKERS);
+    let (mqtt_txs, mqtt_rxs): (Vec<_>, Vec<_>) =
+        (0..broker_count).map(|_| std::sync::mpsc::channel::<channels::topic::Routed>()).unzip();
 
     // Kênh HTTP POST: async
     let (http_tx, http_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(64);
@@ -55,42 +65,97 @@ async fn main() {
     // Kênh nội bộ: dispatcher → GPIO
     let (gpio_tx, gpio_rx) = tokio::sync::mpsc::channel::<commands::Command>(32);
 
-    // Kênh lệnh WS (std mpsc cho WebSocket thread)
-    let (ws_cmd_tx, _ws_cmd_rx) = std::sync::mpsc::channel::<commands::Command>();
+    // Kênh nội bộ: dispatcher → PWM
+    let (pwm_tx, pwm_rx) = tokio::sync::mpsc::channel::<commands::Command>(32);
 
-    // Thông báo thay đổi config cho MQTT thread
-    let (config_notify_tx, config_notify_rx) = std::sync::mpsc::channel::<()>();
-    state.set_mqtt_notifier(config_notify_tx);
+    // Kênh lệnh WS/REST (std mpsc cho HTTP server + WebSocket thread)
+    let (ws_cmd_tx, ws_cmd_rx) = std::sync::mpsc::channel::<commands::Command>();
+
+    // Thông báo thay đổi config cho từng MQTT thread
+    let config_notify_rxs: Vec<_> = (0..broker_count)
+        .map(|_| {
+            let (tx, rx) = std::sync::mpsc::channel::<()>();
+            state.add_mqtt_notifier(tx);
+            rx
+        })
+        .collect();
 
     // Kênh MQTT subscribe → cmd (std mpsc vì MQTT chạy trên OS thread)
     let (mqtt_cmd_tx, mqtt_cmd_rx) = std::sync::mpsc::channel::<commands::Command>();
 
-    // --- Khởi chạy MQTT publisher + subscriber (OS thread riêng) ---
-    let mqtt_state = state.clone();
-    let mqtt_stats = stats.clone();
-    std::thread::spawn(move || {
-        channels::mqtt::run_sync(mqtt_state, mqtt_rx, config_notify_rx, mqtt_cmd_tx, mqtt_stats);
-    });
+    // Receiver dùng chung giữa các lần restart của subsystem (supervisor giữ, task mượn)
+    let http_rx = supervisor::shared(http_rx);
+    let gpio_rx = supervisor::shared(gpio_rx);
+    let pwm_rx = supervisor::shared(pwm_rx);
+    let cmd_rx = supervisor::shared(cmd_rx);
+    let mqtt_cmd_rx = supervisor::shared(mqtt_cmd_rx);
+    let ws_cmd_rx = supervisor::shared(ws_cmd_rx);
+
+    // --- Khởi chạy MQTT publisher + subscriber (mỗi broker 1 OS thread) ---
+    channels::mqtt::set_broker_count(broker_count);
+    stats.tasks.set_mqtt_slots(broker_count);
+    for (slot, (mqtt_rx, notify_rx)) in mqtt_rxs.into_iter().zip(config_notify_rxs).enumerate() {
+        let (mqtt_rx, notify_rx) = (supervisor::shared(mqtt_rx), supervisor::shared(notify_rx));
+        let name: &'static str = match config.mqtt_slot(slot) {
+            Some(m) if slot > 0 => Box::leak(format!("mqtt:{}", m.name).into_boxed_str()),
+            _ => "mqtt",
+        };
+        let (mqtt_state, mqtt_stats, mqtt_cmd_tx) = (state.clone(), stats.clone(), mqtt_cmd_tx.clone());
+        supervisor::spawn_thread(&stats, name, Some(Task::Mqtt(slot as u8)), move || {
+            let data_rx = mqtt_rx.blocking_lock();
+            let notify_rx = notify_rx.blocking_lock();
+            channels::mqtt::run_sync(mqtt_state.clone(), slot, &data_rx, &notify_rx, mqtt_cmd_tx.clone(), mqtt_stats.clone());
+        });
+    }
 
-    // --- Khởi chạy HTTP publisher (async) ---
-    tokio::spawn(channels::http_pub::run(state.clone(), http_rx, cmd_tx.clone(), stats.clone()));
+    // --- Khởi chạy HTTP publisher + poll lệnh downlink (async) ---
+    let http_state = state.clone();
+    let http_cmd_tx = cmd_tx.clone();
+    let http_stats = stats.clone();
+    supervisor::spawn(&stats, "http_pub", None, move || {
+        let (state, rx, cmd_tx, stats) = (http_state.clone(), http_rx.clone(), http_cmd_tx.clone(), http_stats.clone());
+        async move { channels::http_pub::run(state, &mut *rx.lock().await, cmd_tx, stats).await }
+    });
+    let poll_state = state.clone();
+    let poll_cmd_tx = cmd_tx.clone();
+    supervisor::spawn(&stats, "http_poll", None, move || {
+        channels::http_poll::run(poll_state.clone(), poll_cmd_tx.clone())
+    });
 
     // --- Khởi chạy TCP Server + Client ---
-    tokio::spawn(channels::tcp::run_server(
-        state.clone(),
-        uart_broadcast_tx.subscribe(),
-        cmd_tx.clone(),
-        stats.clone(),
-    ));
-    tokio::spawn(channels::tcp::run_client(
-        state.clone(),
-        uart_broadcast_tx.subscribe(),
-        cmd_tx.clone(),
-        stats.clone(),
-    ));
+    let tcp_state = state.clone();
+    let tcp_uart = uart_broadcast_tx.clone();
+    let tcp_cmd_tx = cmd_tx.clone();
+    let tcp_stats = stats.clone();
+    supervisor::spawn(&stats, "tcp_server", None, move || {
+        channels::tcp::run_server(tcp_state.clone(), tcp_uart.subscribe(), tcp_cmd_tx.clone(), tcp_stats.clone())
+    });
+    let tcp_state = state.clone();
+    let tcp_uart = uart_broadcast_tx.clone();
+    let tcp_cmd_tx = cmd_tx.clone();
+    let tcp_stats = stats.clone();
+    supervisor::spawn(&stats, "tcp_client", None, move || {
+        channels::tcp::run_client(tcp_state.clone(), tcp_uart.subscribe(), tcp_cmd_tx.clone(), tcp_stats.clone())
+    });
 
     // --- Khởi chạy GPIO controller ---
-    tokio::spawn(gpio::run(config.gpio.clone(), gpio_rx, stats.clone()));
+    let gpio_config = config.gpio.clone();
+    let gpio_stats = stats.clone();
+    supervisor::spawn(&stats, "gpio", None, move || {
+        let (cfg, rx, stats) = (gpio_config.clone(), gpio_rx.clone(), gpio_stats.clone());
+        async move { gpio::run(cfg, &mut *rx.lock().await, stats).await }
+    });
+
+    // --- Khởi chạy PWM controller ---
+    let pwm_config = config.pwm.clone();
+    let pwm_stats = stats.clone();
+    supervisor::spawn(&stats, "pwm", None, move || {
+        let (cfg, rx, stats) = (pwm_config.clone(), pwm_rx.clone(), pwm_stats.clone());
+        async move { pwm::run(cfg, &mut *rx.lock().await, stats).await }
+    });
+
+    // --- Nút reset vật lý (không cấu hình pin thì không chạy, không cần giám sát) ---
+    tokio::spawn(button::run(config.button.clone(), state.clone(), stats.clone()));
 
     // --- WebSocket manager ---
     let ws_manager = Arc::new(web_api::ws::WsManager::new(
@@ -102,36 +167,17 @@ async fn main() {
     let dispatch_state = state.clone();
     let dispatch_stats = stats.clone();
     let dispatch_ws_broadcast = ws_manager.broadcast_tx.clone();
-    tokio::spawn(async move {
-        // Mở UART writer cho chiều gửi xuống MCU
-        let cfg = dispatch_state.get();
-        let uart_port = cfg.uart.port.clone();
-        let uart_baud = cfg.uart.baudrate;
-        let mut uart_writer = match uart::writer::UartWriter::new(&uart_port, uart_baud) {
-            Ok(w) => {
-                log::info!("[Dispatch] UART TX sẵn sàng: {}", uart_port);
-                Some(w)
-            }
-            Err(e) => {
-                log::warn!("[Dispatch] Không mở UART TX {}: {} (chỉ GPIO)", uart_port, e);
-                None
-            }
-        };
-
-        let mut cmd_rx = cmd_rx;
-        loop {
-            // Nhận command từ async channel (TCP/WS/HTTP) hoặc MQTT std channel
-            let cmd = tokio::select! {
-                Some(cmd) = cmd_rx.recv() => cmd,
-                // Poll MQTT subscribe commands (std mpsc → async bridge)
-                _ = tokio::time::sleep(std::time::Duration::from_millis(50)) => {
-                    while let Ok(cmd) = mqtt_cmd_rx.try_recv() {
-                        dispatch_command(&cmd, &gpio_tx, &mut uart_writer, &dispatch_stats, &dispatch_ws_broadcast).await;
-                    }
-                    continue;
-                }
-            };
-            dispatch_command(&cmd, &gpio_tx, &mut uart_writer, &dispatch_stats, &dispatch_ws_broadcast).await;
+    supervisor::spawn(&stats, "dispatcher", Some(Task::Dispatcher), move || {
+        let state = dispatch_state.clone();
+        let stats = dispatch_stats.clone();
+        let ws_broadcast = dispatch_ws_broadcast.clone();
+        let (cmd_rx, mqtt_cmd_rx, ws_cmd_rx) = (cmd_rx.clone(), mqtt_cmd_rx.clone(), ws_cmd_rx.clone());
+        let (gpio_tx, pwm_tx) = (gpio_tx.clone(), pwm_tx.clone());
+        async move {
+            let mut cmd_rx = cmd_rx.lock().await;
+            let mut mqtt_cmd_rx = mqtt_cmd_rx.lock().await;
+            let mut ws_cmd_rx = ws_cmd_rx.lock().await;
+            run_dispatcher(&state, &mut cmd_rx, &mut mqtt_cmd_rx, &mut ws_cmd_rx, &gpio_tx, &pwm_tx, &stats, &ws_broadcast).await;
         }
     });
 
@@ -142,7 +188,7 @@ async fn main() {
     let ws_broadcast = ws_manager.broadcast_tx.clone();
     let status_stats = stats.clone();
     let status_state = state.clone();
-    std::thread::spawn(move || {
+    supervisor::spawn_thread(&stats, "status", None, move || {
         loop {
             std::thread::sleep(Duration::from_secs(1));
             let cfg = status_state.get();
@@ -153,87 +199,225 @@ async fn main() {
 
     // Fan-out UART data tới WS clients
     let ws_broadcast_uart = ws_manager.broadcast_tx.clone();
-    let mut uart_ws_rx = uart_broadcast_tx.subscribe();
-    tokio::spawn(async move {
-        loop {
-            match uart_ws_rx.recv().await {
-                Ok(data) => {
-                    // Gửi UART data dạng hex tới WS
-                    let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
-                    let json = format!(r#"{{"type":"uart","dir":"rx","hex":"{}","len":{}}}"#, hex, data.len());
-                    let _ = ws_broadcast_uart.send(json);
+    let ws_uart = uart_broadcast_tx.clone();
+    supervisor::spawn(&stats, "ws_fanout", None, move || {
+        let ws_broadcast_uart = ws_broadcast_uart.clone();
+        let mut uart_ws_rx = ws_uart.subscribe();
+        async move {
+            loop {
+                match uart_ws_rx.recv().await {
+                    Ok(data) => {
+                        // Gửi UART data dạng hex tới WS
+                        let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+                        let json = format!(r#"{{"type":"uart","dir":"rx","hex":"{}","len":{}}}"#, hex, data.len());
+                        let _ = ws_broadcast_uart.send(json);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
                 }
-                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
-                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
             }
         }
     });
 
+    // --- Telemetry định kỳ (general.interval_secs) → HTTP (MQTT telemetry_topic do MQTT thread gửi) ---
+    let telemetry_state = state.clone();
+    let telemetry_stats = stats.clone();
+    let telemetry_cmd_tx = cmd_tx.clone();
+    supervisor::spawn(&stats, "telemetry", None, move || {
+        let (state, stats) = (telemetry_state.clone(), telemetry_stats.clone());
+        telemetry::run(state, stats, telemetry_cmd_tx.clone())
+    });
+
     // --- Fan-out: broadcast UART → MQTT + HTTP ---
-    let mut uart_rx = uart_broadcast_tx.subscribe();
+    let fanout_uart = uart_broadcast_tx.clone();
     let fanout_state = state.clone();
-    tokio::spawn(async move {
-        loop {
-            match uart_rx.recv().await {
-                Ok(data) => {
-                    let cfg = fanout_state.get();
-                    let payload = if cfg.general.wrap_json {
-                        // Wrap raw data thành JSON với metadata
-                        let ts = std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap_or_default()
-                            .as_secs();
-                        // data_as_text: gửi string (UTF-8), ngược lại hex encode
-                        let data_str = if cfg.general.data_as_text {
-                            match std::str::from_utf8(&data) {
-                                Ok(s) => crate::web_api::json_escape(s),
-                                // Fallback hex nếu không phải UTF-8
-                                Err(_) => data.iter().map(|b| format!("{:02x}", b)).collect(),
-                            }
-                        } else {
-                            data.iter().map(|b| format!("{:02x}", b)).collect()
-                        };
-                        let json = format!(
-                            r#"{{"device_name":"{}","timestamp":{},"data":"{}"}}"#,
-                            crate::web_api::json_escape(&cfg.general.device_name), ts, data_str
-                        );
-                        json.into_bytes()
-                    } else {
-                        data
-                    };
-                    let _ = mqtt_tx.send(payload.clone());
-                    let _ = http_tx.try_send(payload);
-                }
-                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-                    log::warn!("[FanOut] Bỏ qua {} message (quá tải)", n);
-                }
-                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
-            }
-        }
+    let fanout_stats = stats.clone();
+    supervisor::spawn(&stats, "fanout", Some(Task::FanOut), move || {
+        let uart_rx = fanout_uart.subscribe();
+        let (state, stats) = (fanout_state.clone(), fanout_stats.clone());
+        let (mqtt_txs, http_tx) = (mqtt_txs.clone(), http_tx.clone());
+        run_fanout(uart_rx, state, stats, mqtt_txs, http_tx)
     });
 
     // --- Khởi chạy UART reader ---
-    tokio::spawn(uart::reader::run(state.clone(), uart_broadcast_tx, stats.clone()));
+    let reader_state = state.clone();
+    let reader_stats = stats.clone();
+    supervisor::spawn(&stats, "uart_reader", Some(Task::UartReader), move || {
+        uart::reader::run(reader_state.clone(), uart_broadcast_tx.clone(), reader_stats.clone())
+    });
 
-    // --- Khởi chạy HTTP server (blocking, spawn_blocking) ---
+    // --- Khởi chạy HTTP server (blocking, OS thread riêng) ---
     let server_state = state.clone();
     let server_ws = ws_manager.clone();
     let server_session = session_mgr.clone();
-    tokio::task::spawn_blocking(move || {
-        web_api::server::run(server_state, server_ws, server_session);
+    let server_stats = stats.clone();
+    supervisor::spawn_thread(&stats, "http_server", Some(Task::HttpServer), move || {
+        web_api::server::run(server_state.clone(), server_ws.clone(), server_session.clone(), server_stats.clone());
     });
 
     log::info!("ugate v{} đang chạy (tất cả kênh sẵn sàng)", env!("CARGO_PKG_VERSION"));
 
-    // Graceful shutdown
-    tokio::signal::ctrl_c().await.ok();
+    // --- Hardware watchdog (OS thread riêng, pet khi mọi task quan trọng còn chạy) ---
+    let hw_watchdog = watchdog::Watchdog::start(config.watchdog.clone(), stats.clone());
+
+    // Graceful shutdown: procd stop gửi SIGTERM, chạy tay thì Ctrl-C (SIGINT)
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("Không đăng ký được SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
     log::info!("ugate đang tắt...");
+    shutdown::begin();
+    let pending = shutdown::wait_all(Duration::from_secs(4)).await;
+    if pending.is_empty() {
+        log::info!("Đã xả dữ liệu + đóng kết nối xong");
+    } else {
+        log::warn!("Hết thời gian chờ tắt, chưa xong: {:?}", pending);
+    }
+    if let Some(wd) = hw_watchdog {
+        wd.disarm();
+    }
+}
+
+/// Fan-out: broadcast UART → MQTT + HTTP (bọc JSON nếu cấu hình wrap_json)
+async fn run_fanout(
+    mut uart_rx: broadcast::Receiver<Vec<u8>>,
+    state: Arc<AppState>,
+    stats: Arc<web_api::status::SharedStats>,
+    mqtt_txs: Vec<std::sync::mpsc::Sender<channels::topic::Routed>>,
+    http_tx: tokio::sync::mpsc::Sender<Vec<u8>>,
+) {
+    let mut beat = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        stats.tasks.beat(Task::FanOut);
+        let result = tokio::select! {
+            r = uart_rx.recv() => r,
+            _ = beat.tick() => continue,
+            _ = shutdown::wait() => {
+                // UART reader đã dừng → xả nốt frame còn trong broadcast rồi báo xong
+                let mut drained = 0;
+                while let Ok(data) = uart_rx.try_recv() {
+                    forward(&state, data, &mqtt_txs, &http_tx);
+                    drained += 1;
+                }
+                log::info!("[FanOut] Đã xả {} frame, dừng", drained);
+                shutdown::done(shutdown::Part::FanOut);
+                return;
+            }
+        };
+        match result {
+            Ok(data) => forward(&state, data, &mqtt_txs, &http_tx),
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                log::warn!("[FanOut] Bỏ qua {} message (quá tải)", n);
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Đóng gói 1 frame UART và gửi tới các broker MQTT + HTTP
+/// Kênh MQTT không giới hạn: mỗi broker tự buffer nên broker chậm không chặn fan-out
+fn forward(
+    state: &AppState,
+    data: Vec<u8>,
+    mqtt_txs: &[std::sync::mpsc::Sender<channels::topic::Routed>],
+    http_tx: &tokio::sync::mpsc::Sender<Vec<u8>>,
+) {
+    let cfg = state.get();
+    // Topic chọn theo frame gốc (trước khi wrap JSON), mỗi broker topic/rule riêng
+    // Sparkplug/ThingsBoard nhận frame gốc (tự định dạng payload), không wrap JSON
+    let topics: Vec<(usize, String, bool)> = (0..mqtt_txs.len())
+        .filter_map(|slot| cfg.mqtt_slot(slot).filter(|m| m.enabled).map(|m| (slot, m)))
+        .map(|(slot, m)| (slot, channels::topic::route(&cfg, m, &data), m.raw_frames()))
+        .collect();
+    let raw = topics.iter().any(|t| t.2).then(|| data.clone());
+    let payload = if cfg.general.wrap_json {
+        // Wrap raw data thành JSON với metadata
+        let ts = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        // data_as_text: gửi string (UTF-8), ngược lại hex encode
+        let data_str = if cfg.general.data_as_text {
+            match std::str::from_utf8(&data) {
+                Ok(s) => crate::web_api::json_escape(s),
+                // Fallback hex nếu không phải UTF-8
+                Err(_) => data.iter().map(|b| format!("{:02x}", b)).collect(),
+            }
+        } else {
+            data.iter().map(|b| format!("{:02x}", b)).collect()
+        };
+        let json = format!(
+            r#"{{"device_name":"{}","timestamp":{},"data":"{}"}}"#,
+            crate::web_api::json_escape(&cfg.general.device_name), ts, data_str
+        );
+        json.into_bytes()
+    } else {
+        data
+    };
+    for (slot, topic, wants_raw) in topics {
+        let data = match (&raw, wants_raw) {
+            (Some(raw), true) => raw.clone(),
+            _ => payload.clone(),
+        };
+        let _ = mqtt_txs[slot].send((topic, data));
+    }
+    let _ = http_tx.try_send(payload);
+}
+
+/// Command dispatcher: nhận lệnh từ async channel (TCP/HTTP) + std channel (MQTT/WS/REST)
+#[allow(clippy::too_many_arguments)]
+async fn run_dispatcher(
+    state: &AppState,
+    cmd_rx: &mut tokio::sync::mpsc::Receiver<commands::Command>,
+    // &mut: std Receiver không Sync, tham chiếu chung sẽ làm future không Send
+    mqtt_cmd_rx: &mut std::sync::mpsc::Receiver<commands::Command>,
+    ws_cmd_rx: &mut std::sync::mpsc::Receiver<commands::Command>,
+    gpio_tx: &tokio::sync::mpsc::Sender<commands::Command>,
+    pwm_tx: &tokio::sync::mpsc::Sender<commands::Command>,
+    stats: &web_api::status::SharedStats,
+    ws_broadcast: &broadcast::Sender<String>,
+) {
+    // Mở UART writer cho chiều gửi xuống MCU
+    let cfg = state.get();
+    let uart_port = cfg.uart.port.clone();
+    let uart_baud = cfg.uart.baudrate;
+    let mut uart_writer = match uart::writer::UartWriter::new(&uart_port, uart_baud) {
+        Ok(w) => {
+            log::info!("[Dispatch] UART TX sẵn sàng: {}", uart_port);
+            Some(w)
+        }
+        Err(e) => {
+            log::warn!("[Dispatch] Không mở UART TX {}: {} (chỉ GPIO)", uart_port, e);
+            None
+        }
+    };
+
+    loop {
+        stats.tasks.beat(Task::Dispatcher);
+        // Nhận command từ async channel (TCP/WS/HTTP) hoặc MQTT std channel
+        let cmd = tokio::select! {
+            Some(cmd) = cmd_rx.recv() => cmd,
+            // Đang tắt: không nhận lệnh mới (GPIO sắp về trạng thái an toàn)
+            _ = shutdown::wait() => return,
+            // Poll MQTT subscribe + REST commands (std mpsc → async bridge)
+            _ = tokio::time::sleep(std::time::Duration::from_millis(50)) => {
+                while let Ok(cmd) = mqtt_cmd_rx.try_recv().or_else(|_| ws_cmd_rx.try_recv()) {
+                    dispatch_command(&cmd, gpio_tx, pwm_tx, &mut uart_writer, stats, ws_broadcast).await;
+                }
+                continue;
+            }
+        };
+        dispatch_command(&cmd, gpio_tx, pwm_tx, &mut uart_writer, stats, ws_broadcast).await;
+    }
 }
 
-/// Phân phối command tới đích phù hợp: GPIO hoặc UART TX
+/// Phân phối command tới đích phù hợp: GPIO, PWM hoặc UART TX
 async fn dispatch_command(
     cmd: &commands::Command,
     gpio_tx: &tokio::sync::mpsc::Sender<commands::Command>,
+    pwm_tx: &tokio::sync::mpsc::Sender<commands::Command>,
     uart_writer: &mut Option<uart::writer::UartWriter>,
     stats: &web_api::status::SharedStats,
     ws_broadcast: &broadcast::Sender<String>,
@@ -242,6 +426,9 @@ async fn dispatch_command(
         commands::Command::Gpio { .. } => {
             let _ = gpio_tx.send(cmd.clone()).await;
         }
+        commands::Command::Pwm { .. } => {
+            let _ = pwm_tx.send(cmd.clone()).await;
+        }
         commands::Command::UartTx { data } => {
             let bytes = data.as_bytes();
             let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
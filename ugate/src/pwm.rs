@@ -0,0 +1,129 @@
+//! Điều khiển PWM phần cứng qua sysfs (/sys/class/pwm)
+//! MT7688 có 4 kênh PWM trên pwmchip0 — dùng cho dimmer, buzzer
+//! Mỗi kênh: export → period → duty_cycle → enable
+
+use crate::commands::Command;
+use crate::config::{PwmChannelConfig, PwmConfig};
+use crate::web_api::status::SharedStats;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Số kênh PWM tối đa của MT7688
+pub const PWM_MAX_CHANNELS: usize = 4;
+
+/// Wrapper cho 1 kênh PWM đã export
+struct PwmChannel {
+    dir: PathBuf,
+    period_ns: u32,
+    duty: u8,
+}
+
+impl PwmChannel {
+    /// Export kênh (nếu chưa) và áp cấu hình ban đầu
+    fn open(chip: u8, cfg: &PwmChannelConfig) -> std::io::Result<Self> {
+        let chip_dir = PathBuf::from(format!("/sys/class/pwm/pwmchip{}", chip));
+        let dir = chip_dir.join(format!("pwm{}", cfg.channel));
+        if !dir.exists() {
+            std::fs::write(chip_dir.join("export"), cfg.channel.to_string())?;
+            // Kernel tạo thư mục pwmN bất đồng bộ, chờ tối đa 500ms
+            for _ in 0..50 {
+                if dir.join("period").exists() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+        let mut ch = Self { dir, period_ns: 0, duty: 0 };
+        ch.apply(cfg.period_ns, cfg.duty)?;
+        write_attr(&ch.dir, "enable", "1")?;
+        Ok(ch)
+    }
+
+    /// Ghi period + duty. Kernel từ chối duty_cycle > period nên thứ tự ghi quan trọng
+    fn apply(&mut self, period_ns: u32, duty: u8) -> std::io::Result<()> {
+        let duty = duty.min(100);
+        let duty_ns = duty_to_ns(period_ns, duty);
+        if period_ns != self.period_ns {
+            // Hạ duty về 0 trước để period mới luôn hợp lệ
+            write_attr(&self.dir, "duty_cycle", "0")?;
+            write_attr(&self.dir, "period", &period_ns.to_string())?;
+            self.period_ns = period_ns;
+        }
+        write_attr(&self.dir, "duty_cycle", &duty_ns.to_string())?;
+        self.duty = duty;
+        Ok(())
+    }
+}
+
+fn write_attr(dir: &std::path::Path, name: &str, value: &str) -> std::io::Result<()> {
+    std::fs::write(dir.join(name), value)
+}
+
+/// Đổi duty % sang nanoseconds theo period
+fn duty_to_ns(period_ns: u32, duty: u8) -> u32 {
+    (period_ns as u64 * duty.min(100) as u64 / 100) as u32
+}
+
+/// Task PWM: mở các kênh đã cấu hình, nhận lệnh đổi duty/period từ dispatcher
+pub async fn run(
+    config: PwmConfig,
+    cmd_rx: &mut tokio::sync::mpsc::Receiver<Command>,
+    stats: Arc<SharedStats>,
+) {
+    let mut channels: Vec<Option<PwmChannel>> = (0..PWM_MAX_CHANNELS).map(|_| None).collect();
+
+    for cfg in &config.channels {
+        let idx = cfg.channel as usize;
+        if idx >= PWM_MAX_CHANNELS {
+            log::warn!("[PWM] Kênh {} vượt giới hạn (tối đa {}), bỏ qua", cfg.channel, PWM_MAX_CHANNELS);
+            continue;
+        }
+        match PwmChannel::open(config.chip, cfg) {
+            Ok(ch) => {
+                log::info!("[PWM] Kênh {} sẵn sàng: period={}ns duty={}%", cfg.channel, ch.period_ns, ch.duty);
+                stats.pwm_period_ns[idx].store(ch.period_ns, Ordering::Relaxed);
+                stats.pwm_duty[idx].store(ch.duty, Ordering::Relaxed);
+                channels[idx] = Some(ch);
+            }
+            Err(e) => log::warn!("[PWM] Không thể mở kênh {}: {} (bỏ qua)", cfg.channel, e),
+        }
+    }
+
+    while let Some(cmd) = cmd_rx.recv().await {
+        if let Command::Pwm { ch, duty, period_ns } = cmd {
+            let idx = ch as usize;
+            let channel = match channels.get_mut(idx) {
+                Some(Some(c)) => c,
+                _ => {
+                    log::warn!("[PWM] Kênh {} chưa cấu hình, bỏ qua", ch);
+                    continue;
+                }
+            };
+            let period = period_ns.unwrap_or(channel.period_ns);
+            match channel.apply(period, duty) {
+                Ok(()) => {
+                    stats.pwm_period_ns[idx].store(channel.period_ns, Ordering::Relaxed);
+                    stats.pwm_duty[idx].store(channel.duty, Ordering::Relaxed);
+                    log::debug!("[PWM] Kênh {} = {}% @ {}ns", ch, channel.duty, channel.period_ns);
+                }
+                Err(e) => log::error!("[PWM] Kênh {} lỗi: {}", ch, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duty_to_ns() {
+        assert_eq!(duty_to_ns(1_000_000, 40), 400_000);
+        assert_eq!(duty_to_ns(1_000_000, 0), 0);
+        assert_eq!(duty_to_ns(1_000_000, 100), 1_000_000);
+        // Duty > 100% bị giới hạn về period
+        assert_eq!(duty_to_ns(20_000, 150), 20_000);
+    }
+}
@@ -0,0 +1,72 @@
+//! Tắt êm khi nhận SIGTERM (procd stop) hoặc SIGINT (Ctrl-C)
+//! Trình tự: UART reader + TCP ngừng nhận → fan-out xả hàng đợi → MQTT/HTTP xả nốt,
+//! MQTT gửi DISCONNECT → GPIO về trạng thái an toàn. main chờ các phần báo xong (có deadline)
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// Quá thời gian này kể từ khi bắt đầu tắt thì bỏ qua dữ liệu còn lại trong hàng đợi
+/// (procd gửi SIGKILL sau term_timeout mặc định 5s)
+const DRAIN_DEADLINE: Duration = Duration::from_secs(3);
+
+/// Các phần phải báo xong trước khi process thoát
+#[derive(Clone, Copy, Debug)]
+pub enum Part {
+    FanOut = 1,
+    Mqtt = 2,
+    Http = 4,
+    Gpio = 8,
+}
+
+const ALL_PARTS: [Part; 4] = [Part::FanOut, Part::Mqtt, Part::Http, Part::Gpio];
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static DONE: AtomicU8 = AtomicU8::new(0);
+
+fn signal() -> &'static watch::Sender<bool> {
+    static TX: OnceLock<watch::Sender<bool>> = OnceLock::new();
+    TX.get_or_init(|| watch::channel(false).0)
+}
+
+/// Bắt đầu tắt: đánh thức mọi task đang chờ `wait()`
+pub fn begin() {
+    STARTED.get_or_init(Instant::now);
+    signal().send_replace(true);
+}
+
+pub fn is_shutting_down() -> bool {
+    STARTED.get().is_some()
+}
+
+/// Đã hết thời gian xả hàng đợi
+pub fn deadline_passed() -> bool {
+    STARTED.get().is_some_and(|t| t.elapsed() >= DRAIN_DEADLINE)
+}
+
+/// Chờ tới khi bắt đầu tắt (dùng làm nhánh trong `tokio::select!`)
+pub async fn wait() {
+    let mut rx = signal().subscribe();
+    let _ = rx.wait_for(|v| *v).await;
+}
+
+pub fn done(part: Part) {
+    DONE.fetch_or(part as u8, Ordering::Relaxed);
+}
+
+pub fn is_done(part: Part) -> bool {
+    DONE.load(Ordering::Relaxed) & part as u8 != 0
+}
+
+/// main chờ mọi phần báo xong. Trả danh sách phần chưa xong khi hết `timeout`
+pub async fn wait_all(timeout: Duration) -> Vec<Part> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let pending: Vec<Part> = ALL_PARTS.iter().copied().filter(|&p| !is_done(p)).collect();
+        if pending.is_empty() || Instant::now() >= deadline {
+            return pending;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
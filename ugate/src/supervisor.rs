@@ -0,0 +1,216 @@
+//! Giám sát các subsystem chạy nền (tokio task + OS thread)
+//! Task thoát (return/panic) hoặc ngừng báo tiến độ → log lý do, khởi động lại với backoff
+//! Trạng thái + số lần restart của từng subsystem xem tại GET /api/health
+//! Khi đang tắt (shutdown) task thoát sẽ không được khởi động lại
+//! Lưu ý: build release dùng panic=abort nên panic vẫn làm chết process (procd respawn),
+//! supervisor chủ yếu bắt task thoát bình thường và task async bị treo
+
+use crate::channels::reconnect::Reconnector;
+use crate::watchdog::{uptime_secs, Task};
+use crate::web_api::status::SharedStats;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Task async không báo tiến độ quá N giây → abort + khởi động lại
+/// (nhỏ hơn stale_secs mặc định của watchdog để supervisor xử lý trước khi board reset)
+const STALE_SECS: u32 = 30;
+
+/// Chạy ổn định quá thời gian này thì reset backoff về min
+const STABLE_RUN: Duration = Duration::from_secs(60);
+
+/// Subsystem chạy nhận tài nguyên dùng chung giữa các lần restart (receiver, ...)
+pub type Shared<T> = Arc<tokio::sync::Mutex<T>>;
+
+pub fn shared<T>(v: T) -> Shared<T> {
+    Arc::new(tokio::sync::Mutex::new(v))
+}
+
+struct Subsystem {
+    name: &'static str,
+    beat: Option<Task>,
+    running: bool,
+    restarts: u32,
+    last_exit: String,
+    last_exit_at: u32,
+}
+
+/// Bảng trạng thái subsystem (nằm trong SharedStats để web server đọc được)
+pub struct Health {
+    subsystems: Mutex<Vec<Subsystem>>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self { subsystems: Mutex::new(Vec::new()) }
+    }
+
+    fn register(&self, name: &'static str, beat: Option<Task>) -> usize {
+        let mut subs = self.subsystems.lock().unwrap();
+        subs.push(Subsystem {
+            name,
+            beat,
+            running: false,
+            restarts: 0,
+            last_exit: String::new(),
+            last_exit_at: 0,
+        });
+        subs.len() - 1
+    }
+
+    fn set_running(&self, idx: usize) {
+        self.subsystems.lock().unwrap()[idx].running = true;
+    }
+
+    fn record_exit(&self, idx: usize, reason: &str) {
+        let mut subs = self.subsystems.lock().unwrap();
+        let s = &mut subs[idx];
+        s.running = false;
+        s.restarts += 1;
+        s.last_exit = reason.to_string();
+        s.last_exit_at = uptime_secs();
+    }
+
+    /// JSON cho /api/health. Trả (json, ok) — ok=false nếu có subsystem dừng hoặc kẹt
+    pub fn to_json(&self, stats: &SharedStats) -> (String, bool) {
+        let subs = self.subsystems.lock().unwrap();
+        let mut ok = true;
+        let items: Vec<String> = subs.iter().map(|s| {
+            let age = s.beat.map(|t| stats.tasks.age_secs(t));
+            let state = if !s.running {
+                "restarting"
+            } else if age.is_some_and(|a| a > STALE_SECS) {
+                "stalled"
+            } else {
+                "running"
+            };
+            if state != "running" {
+                ok = false;
+            }
+            format!(
+                r#"{{"name":"{}","state":"{}","restarts":{},"heartbeat_age":{},"last_exit":"{}","last_exit_at":{}}}"#,
+                s.name, state, s.restarts,
+                age.map(|a| a.to_string()).unwrap_or_else(|| "null".into()),
+                crate::web_api::json_escape(&s.last_exit), s.last_exit_at,
+            )
+        }).collect();
+        let json = format!(
+            r#"{{"ok":{},"uptime":{},"subsystems":[{}]}}"#,
+            ok, uptime_secs(), items.join(",")
+        );
+        (json, ok)
+    }
+}
+
+/// Chạy tokio task dưới giám sát. `factory` tạo future mới cho mỗi lần (re)start
+pub fn spawn<F, Fut>(stats: &Arc<SharedStats>, name: &'static str, beat: Option<Task>, factory: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let idx = stats.health.register(name, beat);
+    let stats = stats.clone();
+    tokio::spawn(async move {
+        let mut backoff = Reconnector::new(Duration::from_secs(1), Duration::from_secs(60));
+        let mut check = tokio::time::interval(Duration::from_secs(5));
+        loop {
+            if let Some(t) = beat {
+                stats.tasks.beat(t);
+            }
+            stats.health.set_running(idx);
+            let started = Instant::now();
+            let mut handle = tokio::spawn(factory());
+
+            let reason = loop {
+                tokio::select! {
+                    r = &mut handle => break match r {
+                        Ok(()) => "task kết thúc".to_string(),
+                        Err(e) if e.is_panic() => "panic".to_string(),
+                        Err(e) => e.to_string(),
+                    },
+                    _ = check.tick(), if !crate::shutdown::is_shutting_down() => {
+                        let age = beat.map(|t| stats.tasks.age_secs(t)).unwrap_or(0);
+                        if age > STALE_SECS {
+                            handle.abort();
+                            break format!("không báo tiến độ {}s", age);
+                        }
+                    }
+                }
+            };
+            if crate::shutdown::is_shutting_down() {
+                log::info!("[Supervisor] '{}' đã dừng (đang tắt)", name);
+                return;
+            }
+
+            if started.elapsed() >= STABLE_RUN {
+                backoff.reset();
+            }
+            let delay = backoff.next_delay();
+            log::error!("[Supervisor] '{}' dừng: {} — khởi động lại sau {}s", name, reason, delay.as_secs());
+            stats.health.record_exit(idx, &reason);
+            match beat {
+                Some(t) => stats.tasks.sleep(t, delay).await,
+                None => tokio::time::sleep(delay).await,
+            }
+        }
+    });
+}
+
+/// Chạy hàm blocking trên OS thread dưới giám sát (thread không abort được —
+/// heartbeat dừng chỉ được báo "stalled" trong /api/health, watchdog phần cứng xử lý tiếp)
+pub fn spawn_thread<F>(stats: &Arc<SharedStats>, name: &'static str, beat: Option<Task>, factory: F)
+where
+    F: Fn() + Send + 'static,
+{
+    let idx = stats.health.register(name, beat);
+    let stats = stats.clone();
+    std::thread::spawn(move || {
+        let mut backoff = Reconnector::new(Duration::from_secs(1), Duration::from_secs(60));
+        loop {
+            if let Some(t) = beat {
+                stats.tasks.beat(t);
+            }
+            stats.health.set_running(idx);
+            let started = Instant::now();
+            factory();
+            if crate::shutdown::is_shutting_down() {
+                log::info!("[Supervisor] Thread '{}' đã dừng (đang tắt)", name);
+                return;
+            }
+
+            if started.elapsed() >= STABLE_RUN {
+                backoff.reset();
+            }
+            let delay = backoff.next_delay();
+            log::error!("[Supervisor] Thread '{}' kết thúc — khởi động lại sau {}s", name, delay.as_secs());
+            stats.health.record_exit(idx, "thread kết thúc");
+            match beat {
+                Some(t) => stats.tasks.sleep_blocking(t, delay),
+                None => std::thread::sleep(delay),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_json() {
+        let stats = SharedStats::new();
+        let a = stats.health.register("fanout", Some(Task::FanOut));
+        let b = stats.health.register("status", None);
+        stats.health.set_running(a);
+        stats.health.set_running(b);
+        let (json, ok) = stats.health.to_json(&stats);
+        assert!(ok);
+        assert!(json.contains(r#"{"name":"status","state":"running","restarts":0,"heartbeat_age":null"#));
+
+        stats.health.record_exit(b, "task kết thúc");
+        let (json, ok) = stats.health.to_json(&stats);
+        assert!(!ok);
+        assert!(json.contains(r#""name":"status","state":"restarting","restarts":1"#));
+        assert!(json.contains(r#""last_exit":"task kết thúc""#));
+    }
+}
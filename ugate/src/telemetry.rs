@@ -0,0 +1,50 @@
+//! Telemetry định kỳ tới HTTP endpoint (http.telemetry): mỗi general.interval_secs gửi trạng
+//! thái gateway (CPU, RAM, uptime, trạng thái + bộ đếm các kênh, GPIO, RSSI, IP) — độc lập với
+//! dữ liệu UART, không qua offline buffer (gửi lỗi thì bỏ, không replay trạng thái cũ)
+//! MQTT telemetry_topic do từng MQTT thread tự publish (xem mqtt.rs)
+
+use crate::channels::http_pub;
+use crate::commands::Command;
+use crate::config::AppState;
+use crate::web_api::status::SharedStats;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+pub async fn run(state: Arc<AppState>, stats: Arc<SharedStats>, cmd_tx: mpsc::Sender<Command>) {
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(10))
+        .build();
+    loop {
+        // Đọc lại chu kỳ mỗi vòng: đổi interval_secs có hiệu lực sau lần gửi kế tiếp
+        let secs = state.get().general.interval_secs;
+        let wait = if secs == 0 { Duration::from_secs(1) } else { Duration::from_secs(secs) };
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = crate::shutdown::wait() => return,
+        }
+        let cfg = state.get();
+        if secs == 0 || !cfg.http.enabled || !cfg.http.telemetry || cfg.http.url.is_empty() {
+            continue;
+        }
+        // Status đọc /proc + chạy `date`, ureq chặn → không chạy trên runtime current_thread
+        let (agent, stats) = (agent.clone(), stats.clone());
+        let sent = tokio::task::spawn_blocking(move || {
+            let payload = stats.to_telemetry_json(&cfg).into_bytes();
+            http_pub::send_telemetry(&agent, &cfg, payload)
+        });
+        let result = tokio::select! {
+            r = sent => r,
+            _ = crate::shutdown::wait() => return,
+        };
+        match result {
+            Ok(Ok(commands)) => {
+                for cmd in commands {
+                    let _ = cmd_tx.send(cmd).await;
+                }
+            }
+            Ok(Err(reason)) => log::warn!("[Telemetry] Gửi HTTP thất bại: {}", reason),
+            Err(_) => {}
+        }
+    }
+}
@@ -3,6 +3,7 @@
 //! Phân phối frame hoàn chỉnh tới tất cả kênh qua broadcast channel
 
 use crate::config::{AppState, FrameMode};
+use crate::watchdog::Task;
 use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::sync::Arc;
 use std::time::Duration;
@@ -17,17 +18,25 @@ pub async fn run(
 ) {
     let mut retry_secs = 5u64;
     loop {
+        if crate::shutdown::is_shutting_down() {
+            log::info!("[UART] Đang tắt, ngừng đọc");
+            return;
+        }
         let config = state.get();
         if !config.uart.enabled {
-            tokio::time::sleep(Duration::from_secs(10)).await;
+            stats.uart_state.store(0, std::sync::atomic::Ordering::Relaxed);
+            stats.tasks.sleep(Task::UartReader, Duration::from_secs(10)).await;
             retry_secs = 5;
             continue;
         }
+        stats.uart_state.store(1, std::sync::atomic::Ordering::Relaxed);
         match run_read_loop(&state, &broadcast_tx, &stats).await {
             Ok(()) => retry_secs = 5,
             Err(e) => {
+                // Cổng đã đóng: không để trạng thái "connected" trong lúc chờ mở lại
+                stats.uart_state.store(1, std::sync::atomic::Ordering::Relaxed);
                 log::error!("[UART] Error: {}. Retrying in {}s...", e, retry_secs);
-                tokio::time::sleep(Duration::from_secs(retry_secs)).await;
+                stats.tasks.sleep(Task::UartReader, Duration::from_secs(retry_secs)).await;
                 retry_secs = (retry_secs * 2).min(60);
             }
         }
@@ -64,6 +73,8 @@ async fn run_read_loop(
 
     log::info!("[UART] Opened {} @ {} baud, mode={:?}",
         config.uart.port, config.uart.baudrate, config.uart.frame_mode);
+    stats.uart_state.store(2, std::sync::atomic::Ordering::Relaxed);
+    let mut beat = tokio::time::interval(Duration::from_secs(1));
 
     loop {
         tokio::select! {
@@ -72,6 +83,14 @@ async fn run_read_loop(
                 return Ok(());
             }
 
+            // Tắt êm: ngừng nhận dữ liệu mới từ MCU
+            _ = crate::shutdown::wait() => return Ok(()),
+
+            // Báo tiến độ cho watchdog cả khi MCU im lặng
+            _ = beat.tick() => {
+                stats.tasks.beat(Task::UartReader);
+            }
+
             result = async_fd.readable() => {
                 let mut guard = result?;
 
@@ -0,0 +1,281 @@
+//! Hardware watchdog (/dev/watchdog) gắn với sức khoẻ các task quan trọng
+//! Mỗi task gọi `TaskBeats::beat()` trong vòng lặp; watchdog thread chỉ pet khi
+//! MỌI task còn báo tiến độ → runtime treo hoặc 1 MQTT thread (bất kỳ broker nào) kẹt sẽ khiến
+//! board tự reset
+//! Chạy trên OS thread riêng để vẫn phát hiện được khi tokio runtime deadlock
+
+use crate::config::WatchdogConfig;
+use crate::web_api::status::SharedStats;
+use std::io::Write;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// WDIOC_SETTIMEOUT = _IOWR('W', 6, int) — cùng giá trị trên MIPS và x86_64
+#[cfg(target_os = "linux")]
+type IoctlNum = libc::Ioctl;
+#[cfg(not(target_os = "linux"))]
+type IoctlNum = libc::c_ulong;
+const WDIOC_SETTIMEOUT: IoctlNum = 0xC0045706u32 as IoctlNum;
+
+/// Các task quan trọng phải báo tiến độ. Mỗi kết nối MQTT (slot) 1 mục riêng: broker phụ
+/// kẹt không bị che bởi broker chính vẫn chạy
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Task {
+    UartReader,
+    Dispatcher,
+    HttpServer,
+    FanOut,
+    Mqtt(u8),
+}
+
+/// Task cố định (không kể MQTT)
+const FIXED_TASKS: [Task; 4] = [Task::UartReader, Task::Dispatcher, Task::HttpServer, Task::FanOut];
+const SLOTS: usize = FIXED_TASKS.len() + crate::channels::mqtt::MAX_BROKERS;
+
+impl Task {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Task::UartReader => "uart_reader",
+            Task::Dispatcher => "dispatcher",
+            Task::HttpServer => "http_server",
+            Task::FanOut => "fanout",
+            Task::Mqtt(0) => "mqtt",
+            Task::Mqtt(1) => "mqtt_broker[0]",
+            Task::Mqtt(2) => "mqtt_broker[1]",
+            Task::Mqtt(_) => "mqtt_broker[2]",
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Task::UartReader => 0,
+            Task::Dispatcher => 1,
+            Task::HttpServer => 2,
+            Task::FanOut => 3,
+            Task::Mqtt(slot) => FIXED_TASKS.len() + (*slot as usize).min(crate::channels::mqtt::MAX_BROKERS - 1),
+        }
+    }
+}
+
+/// Giây kể từ khi process khởi động (monotonic, u32 vì MIPS không có AtomicU64)
+pub fn uptime_secs() -> u32 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs() as u32
+}
+
+/// Thời điểm báo tiến độ cuối của từng task quan trọng
+pub struct TaskBeats {
+    last: [AtomicU32; SLOTS],
+    /// Số kết nối MQTT đang chạy thread (slot 0..n được theo dõi)
+    mqtt_slots: AtomicUsize,
+}
+
+impl TaskBeats {
+    pub fn new() -> Self {
+        let now = uptime_secs();
+        Self {
+            last: std::array::from_fn(|_| AtomicU32::new(now)),
+            mqtt_slots: AtomicUsize::new(1),
+        }
+    }
+
+    /// main gọi khi spawn các MQTT thread: slot chưa có thread thì không tính là kẹt
+    pub fn set_mqtt_slots(&self, n: usize) {
+        let now = uptime_secs();
+        for slot in 0..n.min(crate::channels::mqtt::MAX_BROKERS) {
+            self.last[Task::Mqtt(slot as u8).index()].store(now, Ordering::Relaxed);
+        }
+        self.mqtt_slots.store(n, Ordering::Relaxed);
+    }
+
+    /// Task báo "vẫn đang chạy"
+    pub fn beat(&self, task: Task) {
+        self.last[task.index()].store(uptime_secs(), Ordering::Relaxed);
+    }
+
+    /// Số giây kể từ lần báo tiến độ cuối
+    pub fn age_secs(&self, task: Task) -> u32 {
+        uptime_secs().saturating_sub(self.last[task.index()].load(Ordering::Relaxed))
+    }
+
+    /// Task đầu tiên không báo tiến độ quá `stale_secs` (nếu có)
+    pub fn first_stale(&self, stale_secs: u32) -> Option<Task> {
+        self.stale_at(uptime_secs(), stale_secs)
+    }
+
+    /// Task kẹt lâu nhất trong số đã quá `stale_secs`
+    fn stale_at(&self, now: u32, stale_secs: u32) -> Option<Task> {
+        let mqtt = (0..self.mqtt_slots.load(Ordering::Relaxed).min(crate::channels::mqtt::MAX_BROKERS))
+            .map(|slot| Task::Mqtt(slot as u8));
+        FIXED_TASKS.iter().copied()
+            .chain(mqtt)
+            .map(|t| (t, now.saturating_sub(self.last[t.index()].load(Ordering::Relaxed))))
+            .filter(|&(_, age)| age > stale_secs)
+            .max_by_key(|&(_, age)| age)
+            .map(|(t, _)| t)
+    }
+
+    /// Sleep async nhưng vẫn báo tiến độ mỗi giây (dùng cho retry/backoff dài)
+    pub async fn sleep(&self, task: Task, dur: Duration) {
+        let deadline = tokio::time::Instant::now() + dur;
+        loop {
+            self.beat(task);
+            let now = tokio::time::Instant::now();
+            if now >= deadline {
+                break;
+            }
+            tokio::time::sleep((deadline - now).min(Duration::from_secs(1))).await;
+        }
+    }
+
+    /// Như `sleep` nhưng cho OS thread (MQTT)
+    pub fn sleep_blocking(&self, task: Task, dur: Duration) {
+        let deadline = Instant::now() + dur;
+        loop {
+            self.beat(task);
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            std::thread::sleep((deadline - now).min(Duration::from_secs(1)));
+        }
+    }
+}
+
+/// Trạng thái watchdog cho status JSON
+pub const WD_DISABLED: u8 = 0;
+pub const WD_ARMED: u8 = 1;
+pub const WD_STARVING: u8 = 2; // có task kẹt, đã ngừng pet → chờ hardware reset
+pub const WD_DISARMED: u8 = 3;
+
+pub fn state_str(s: u8) -> &'static str {
+    match s {
+        WD_ARMED => "armed",
+        WD_STARVING => "starving",
+        WD_DISARMED => "disarmed",
+        WD_DISABLED => "disabled",
+        _ => "unknown",
+    }
+}
+
+/// Giữ file /dev/watchdog; `disarm()` gửi magic close khi tắt êm
+pub struct Watchdog {
+    file: Mutex<Option<std::fs::File>>,
+    config: WatchdogConfig,
+    stats: Arc<SharedStats>,
+}
+
+impl Watchdog {
+    /// Mở device + spawn thread pet. Trả None nếu tắt hoặc không mở được
+    pub fn start(config: WatchdogConfig, stats: Arc<SharedStats>) -> Option<Arc<Self>> {
+        if !config.enabled {
+            return None;
+        }
+        if config.takeover_procd {
+            // procd giữ /dev/watchdog độc quyền — yêu cầu nhả (magic close) trước khi mở
+            procd_watchdog(r#"{"magicclose":true,"stop":true}"#);
+        }
+        let file = match std::fs::OpenOptions::new().write(true).open(&config.device) {
+            Ok(f) => f,
+            Err(e) => {
+                log::error!("[Watchdog] Không mở được {}: {}", config.device, e);
+                if config.takeover_procd {
+                    procd_watchdog(r#"{"stop":false}"#);
+                }
+                return None;
+            }
+        };
+
+        let mut timeout = config.timeout_secs as libc::c_int;
+        let ret = unsafe {
+            use std::os::unix::io::AsRawFd;
+            libc::ioctl(file.as_raw_fd(), WDIOC_SETTIMEOUT, &mut timeout)
+        };
+        if ret < 0 {
+            log::warn!("[Watchdog] Set timeout lỗi: {}", std::io::Error::last_os_error());
+        }
+        log::info!("[Watchdog] Armed {} timeout={}s, task stale sau {}s",
+            config.device, timeout, config.stale_secs);
+        stats.watchdog_state.store(WD_ARMED, Ordering::Relaxed);
+
+        let wd = Arc::new(Self { file: Mutex::new(Some(file)), config, stats });
+        let wd_thread = wd.clone();
+        std::thread::spawn(move || wd_thread.pet_loop());
+        Some(wd)
+    }
+
+    fn pet_loop(&self) {
+        let interval = Duration::from_secs((self.config.timeout_secs / 3).max(1) as u64);
+        loop {
+            std::thread::sleep(interval);
+            let mut guard = self.file.lock().unwrap();
+            let file = match guard.as_mut() {
+                Some(f) => f,
+                None => return, // đã disarm
+            };
+            match self.stats.tasks.first_stale(self.config.stale_secs) {
+                None => {
+                    if self.stats.watchdog_state.swap(WD_ARMED, Ordering::Relaxed) == WD_STARVING {
+                        log::info!("[Watchdog] Các task đã hồi phục, tiếp tục pet");
+                        self.stats.watchdog_stale.lock().unwrap().clear();
+                    }
+                    let _ = file.write_all(b"\0");
+                }
+                Some(task) => {
+                    if self.stats.watchdog_state.swap(WD_STARVING, Ordering::Relaxed) != WD_STARVING {
+                        log::error!("[Watchdog] Task '{}' không báo tiến độ {}s — ngừng pet, board sẽ reset sau {}s",
+                            task.name(), self.stats.tasks.age_secs(task), self.config.timeout_secs);
+                        *self.stats.watchdog_stale.lock().unwrap() = task.name().to_string();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Tắt watchdog êm (magic close 'V') và trả quyền cho procd
+    pub fn disarm(&self) {
+        if let Some(mut file) = self.file.lock().unwrap().take() {
+            let _ = file.write_all(b"V");
+            drop(file);
+            self.stats.watchdog_state.store(WD_DISARMED, Ordering::Relaxed);
+            log::info!("[Watchdog] Disarmed (magic close)");
+            if self.config.takeover_procd {
+                procd_watchdog(r#"{"stop":false}"#);
+            }
+        }
+    }
+}
+
+/// Gọi `ubus call system watchdog <json>` để procd nhả/nhận lại /dev/watchdog
+fn procd_watchdog(args: &str) {
+    let _ = std::process::Command::new("ubus")
+        .args(["call", "system", "watchdog", args])
+        .output();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale_detection() {
+        let beats = TaskBeats::new();
+        beats.set_mqtt_slots(2);
+        for slot in beats.last.iter() {
+            slot.store(100, Ordering::Relaxed);
+        }
+        assert_eq!(beats.stale_at(130, 60), None);
+        // Fan-out kẹt từ giây 50 → quá 60s tại giây 130
+        beats.last[Task::FanOut.index()].store(50, Ordering::Relaxed);
+        assert_eq!(beats.stale_at(130, 60), Some(Task::FanOut));
+        assert_eq!(beats.stale_at(110, 60), None);
+        // Broker phụ kẹt lâu hơn, broker chính vẫn báo đều → vẫn phát hiện
+        beats.last[Task::Mqtt(1).index()].store(20, Ordering::Relaxed);
+        beats.last[Task::Mqtt(0).index()].store(125, Ordering::Relaxed);
+        assert_eq!(beats.stale_at(130, 60), Some(Task::Mqtt(1)));
+        // Slot không có thread (chỉ 2 kết nối) không bao giờ kẹt
+        beats.last[Task::Mqtt(3).index()].store(0, Ordering::Relaxed);
+        assert_eq!(beats.stale_at(130, 60), Some(Task::Mqtt(1)));
+    }
+}
@@ -0,0 +1,61 @@
+//! API quản lý chứng chỉ TLS cho MQTT
+//! GET /api/mqtt/certs[/<broker>] — trạng thái ca/cert/key
+//! POST /api/mqtt/certs/[<broker>/]{ca|cert|key} — upload PEM (raw body), kiểm tra rồi lưu
+//! DELETE /api/mqtt/certs/[<broker>/]{ca|cert|key} — xoá file
+//! Không có <broker> = section `mqtt` chính. Thay đổi có hiệu lực ngay: MQTT kết nối lại
+
+use crate::channels::tls::{self, CertKind};
+use crate::config::AppState;
+use crate::web_api::{json_err, json_escape, json_resp, Resp};
+
+/// "<broker>/<kind>" hoặc "<kind>" → (broker, kind). Broker phải có trong config
+fn target<'a>(path: &'a str, state: &AppState) -> Result<(&'a str, CertKind), Resp> {
+    let (broker, kind) = path.split_once('/').unwrap_or(("mqtt", path));
+    if state.get().mqtt_by_name(broker).is_none() {
+        return Err(json_err(404, "unknown broker"));
+    }
+    match CertKind::parse(kind) {
+        Some(k) => Ok((broker, k)),
+        None => Err(json_err(404, "unknown cert type (ca, cert, key)")),
+    }
+}
+
+pub fn handle_get(broker: &str, state: &AppState) -> Resp {
+    if state.get().mqtt_by_name(broker).is_none() {
+        return json_err(404, "unknown broker");
+    }
+    json_resp(&tls::info_json(broker))
+}
+
+pub fn handle_upload(request: &mut tiny_http::Request, path: &str, state: &AppState) -> Resp {
+    let (broker, kind) = match target(path, state) {
+        Ok(t) => t,
+        Err(resp) => return resp,
+    };
+    use std::io::Read;
+    let mut pem = Vec::new();
+    let _ = request.as_reader().take(tls::MAX_PEM_BYTES as u64 + 1).read_to_end(&mut pem);
+    if pem.len() > tls::MAX_PEM_BYTES {
+        return json_err(413, "PEM too large");
+    }
+    match tls::store(kind, broker, &pem) {
+        Ok(info) => {
+            state.reconnect_mqtt();
+            json_resp(&format!(r#"{{"ok":true,"info":"{}"}}"#, json_escape(&info)))
+        }
+        Err(e) => json_err(400, &e),
+    }
+}
+
+pub fn handle_delete(path: &str, state: &AppState) -> Resp {
+    let (broker, kind) = match target(path, state) {
+        Ok(t) => t,
+        Err(resp) => return resp,
+    };
+    if !tls::remove(kind, broker) {
+        return json_err(404, "not found");
+    }
+    log::info!("[TLS] Xoá {}", kind.path(broker).display());
+    state.reconnect_mqtt();
+    json_resp(r#"{"ok":true}"#)
+}
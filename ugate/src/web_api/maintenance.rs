@@ -10,6 +10,11 @@ use std::sync::Arc;
 /// Guard chống concurrent upgrade (chỉ cho phép 1 upgrade tại 1 thời điểm)
 static UPGRADING: AtomicBool = AtomicBool::new(false);
 
+/// Đang cài firmware (heartbeat LED dùng để hiện pattern riêng)
+pub fn is_upgrading() -> bool {
+    UPGRADING.load(Ordering::Relaxed)
+}
+
 /// GET /api/upgrade/url — lấy upgrade URL từ UCI
 pub fn handle_get_upgrade_url() -> Resp {
     let url = crate::uci::Uci::get("ugate.@upgrade[0].url").unwrap_or_default();
@@ -100,15 +105,25 @@ pub fn handle_restore(request: &mut tiny_http::Request, state: &Arc<AppState>) -
 
 /// POST /api/factory-reset — reset ugate config về mặc định
 pub fn handle_factory_reset(state: &Arc<AppState>) -> Resp {
-    // Ghi default config
+    factory_reset(state);
+    json_resp(r#"{"ok":true,"message":"config reset to defaults"}"#)
+}
+
+/// Ghi config mặc định vào UCI và apply (dùng chung cho API + nút reset)
+pub fn factory_reset(state: &Arc<AppState>) {
     let default = Config::default();
     default.save_to_uci();
-
-    // Apply vào state
     state.update(default);
     log::info!("[Maint] Factory reset: config restored to defaults");
+}
 
-    json_resp(r#"{"ok":true,"message":"config reset to defaults"}"#)
+/// Restart service ugate qua procd (chạy nền, trả về ngay)
+pub fn restart_service() {
+    log::info!("[Maint] Service restart requested");
+    std::thread::spawn(|| {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        Command::new("/etc/init.d/ugate").arg("restart").status().ok();
+    });
 }
 
 /// POST /api/restart — reboot device (trả response trước, reboot sau 1s)
@@ -3,6 +3,7 @@
 //! tungstenite xử lý WebSocket cho dữ liệu real-time và lệnh điều khiển
 
 pub mod auth;
+pub mod certs;
 pub mod maintenance;
 pub mod netcfg;
 pub mod server;
@@ -46,6 +47,100 @@ pub(crate) fn jval(json: &str, key: &str) -> Option<String> {
     })
 }
 
+/// Như `jval` nhưng giải escape chuỗi JSON (\" \\ \n \uXXXX...) — cho trường có thể chứa
+/// ngoặc kép/xuống dòng (template, password)
+pub(crate) fn jstr(json: &str, key: &str) -> Option<String> {
+    let pat = format!("\"{}\":", key);
+    let pos = json.find(&pat)?;
+    let rest = json[pos + pat.len()..].trim_start();
+    let body = match rest.strip_prefix('"') {
+        Some(body) => body,
+        None => return jval(json, key),
+    };
+    let mut out = String::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(out),
+            '\\' => match chars.next()? {
+                'n' => out.push('\n'),
+                'r' => out.push('\r'),
+                't' => out.push('\t'),
+                'b' => out.push('\u{8}'),
+                'f' => out.push('\u{c}'),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32);
+                    out.push(c.unwrap_or('\u{fffd}'));
+                }
+                c => out.push(c),
+            },
+            c => out.push(c),
+        }
+    }
+    None
+}
+
+/// Object lồng `"key":{...}` (gồm cả ngoặc), lần xuất hiện đầu tiên
+pub(crate) fn json_object<'a>(json: &'a str, key: &str) -> Option<&'a str> {
+    let pat = format!("\"{}\":", key);
+    let pos = json.find(&pat)?;
+    let rest = &json[pos + pat.len()..];
+    let start = pos + pat.len() + (rest.len() - rest.trim_start().len());
+    if !json[start..].starts_with('{') {
+        return None;
+    }
+    let mut depth = 0;
+    let mut in_str = false;
+    let mut escaped = false;
+    for (i, c) in json[start..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_str => escaped = true,
+            '"' => in_str = !in_str,
+            '{' if !in_str => depth += 1,
+            '}' if !in_str => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&json[start..start + i + 1]);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Các object `{...}` ở cấp đầu của JSON array `[{...},{...}]`
+pub(crate) fn json_array_objects(json: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut in_str = false;
+    let mut escaped = false;
+    for (i, c) in json.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_str => escaped = true,
+            '"' => in_str = !in_str,
+            '[' | '{' if !in_str => {
+                depth += 1;
+                if c == '{' && depth == 2 {
+                    start = i;
+                }
+            }
+            ']' | '}' if !in_str => {
+                if c == '}' && depth == 2 {
+                    items.push(&json[start..=i]);
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    items
+}
+
 /// Validate identifier an toàn cho UCI key paths (chỉ alphanumeric + underscore)
 pub(crate) fn is_safe_identifier(s: &str) -> bool {
     !s.is_empty() && s.len() <= 64 && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
@@ -68,6 +163,16 @@ pub(crate) fn json_escape(s: &str) -> String {
     out
 }
 
+/// Percent-encode theo RFC 3986 (giữ nguyên ký tự unreserved) cho query string, SAS token
+pub(crate) fn url_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
 /// Validate IPv4 format
 pub(crate) fn is_valid_ipv4(s: &str) -> bool {
     let parts: Vec<&str> = s.split('.').collect();
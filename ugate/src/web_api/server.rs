@@ -5,6 +5,7 @@
 use crate::commands::Command;
 use crate::config::AppState;
 use crate::web_api::auth::SessionManager;
+use crate::web_api::status::SharedStats;
 use crate::web_api::ws::{self, WsManager};
 use std::sync::Arc;
 
@@ -21,6 +22,7 @@ pub fn run(
     state: Arc<AppState>,
     ws_manager: Arc<WsManager>,
     session_mgr: Arc<SessionManager>,
+    stats: Arc<SharedStats>,
 ) {
     let config = state.get();
     let addr = format!("0.0.0.0:{}", config.web.port);
@@ -35,7 +37,17 @@ pub fn run(
 
     log::info!("[HTTP] ugate đang chạy tại http://{}", addr);
 
-    for mut request in server.incoming_requests() {
+    loop {
+        // recv_timeout thay cho incoming_requests() để báo tiến độ cho watchdog khi idle
+        stats.tasks.beat(crate::watchdog::Task::HttpServer);
+        let mut request = match server.recv_timeout(std::time::Duration::from_secs(1)) {
+            Ok(Some(r)) => r,
+            Ok(None) => continue,
+            Err(e) => {
+                log::error!("[HTTP] Server dừng: {}", e);
+                return;
+            }
+        };
         let url = request.url().to_string();
         let method = request.method().clone();
 
@@ -58,8 +70,8 @@ pub fn run(
             continue;
         }
 
-        // Kiểm tra auth cho API routes (trừ login và static files)
-        let needs_auth = url.starts_with("/api/") && url != "/api/login";
+        // Kiểm tra auth cho API routes (trừ login, health check và static files)
+        let needs_auth = url.starts_with("/api/") && url != "/api/login" && url != "/api/health";
         if needs_auth {
             let cookie = request
                 .headers()
@@ -116,12 +128,40 @@ pub fn run(
             (tiny_http::Method::Get, "/api/status") => {
                 handle_get_status(&state)
             }
+            (tiny_http::Method::Get, "/api/health") => {
+                handle_get_health(&stats)
+            }
+
+            // Chứng chỉ TLS cho MQTT (PEM raw body)
+            (tiny_http::Method::Get, "/api/mqtt/certs") => {
+                crate::web_api::certs::handle_get("mqtt", &state)
+            }
+            (tiny_http::Method::Get, path) if path.starts_with("/api/mqtt/certs/") => {
+                crate::web_api::certs::handle_get(path.trim_start_matches("/api/mqtt/certs/"), &state)
+            }
+            (tiny_http::Method::Post, path) if path.starts_with("/api/mqtt/certs/") => {
+                let target = path.trim_start_matches("/api/mqtt/certs/").to_string();
+                crate::web_api::certs::handle_upload(&mut request, &target, &state)
+            }
+            (tiny_http::Method::Delete, path) if path.starts_with("/api/mqtt/certs/") => {
+                let target = path.trim_start_matches("/api/mqtt/certs/");
+                crate::web_api::certs::handle_delete(target, &state)
+            }
 
             // GPIO API
             (tiny_http::Method::Post, path) if path.starts_with("/api/gpio/") => {
                 handle_gpio(&mut request, path, &ws_manager)
             }
 
+            // PWM API
+            (tiny_http::Method::Get, "/api/pwm") => {
+                crate::web_api::json_resp(&stats.pwm_json(&state.get()))
+            }
+            (tiny_http::Method::Post, path) if path.starts_with("/api/pwm/") => {
+                let body = read_body(&mut request);
+                handle_pwm(&body, path, &ws_manager)
+            }
+
             // Password change
             (tiny_http::Method::Post, "/api/password") => {
                 handle_change_password(&mut request, &state)
@@ -326,8 +366,29 @@ fn handle_login(
     }
 }
 
+/// Các section cấu hình của GET/POST /api/config
+const CONFIG_SECTIONS: [&str; 6] = ["general", "mqtt", "http", "tcp", "uart", "web"];
+
 fn handle_get_config(state: &AppState) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
     let c = state.get();
+    let sections: Vec<String> = CONFIG_SECTIONS.iter()
+        .filter_map(|s| section_json(&c, s).map(|json| format!(r#""{}":{}"#, s, json)))
+        .collect();
+    let json = format!("{{{}}}", sections.join(","));
+    tiny_http::Response::from_string(json).with_header(content_type_json())
+}
+
+/// Giá trị thay cho mật khẩu/token/secret trong GET /api/config; POST gửi lại nguyên giá trị
+/// này thì giữ giá trị đang lưu
+const SECRET_MASK: &str = "***";
+
+fn mask(secret: &str) -> &'static str {
+    if secret.is_empty() { "" } else { SECRET_MASK }
+}
+
+/// JSON 1 section cấu hình (cùng dạng body POST /api/config), dùng chung cho shadow.
+/// Mật khẩu, token, sign_secret bị che (SECRET_MASK)
+pub(crate) fn section_json(c: &crate::config::Config, section: &str) -> Option<String> {
     let tcp_mode = match c.tcp.mode {
         crate::config::TcpMode::Server => "server",
         crate::config::TcpMode::Client => "client",
@@ -346,20 +407,58 @@ fn handle_get_config(state: &AppState) -> tiny_http::Response<std::io::Cursor<Ve
     let http_method = match c.http.method {
         crate::config::HttpMethod::Post => "post",
         crate::config::HttpMethod::Get => "get",
+        crate::config::HttpMethod::Put => "put",
+        crate::config::HttpMethod::Patch => "patch",
+    };
+    let http_auth = match c.http.auth {
+        crate::config::HttpAuth::None => "none",
+        crate::config::HttpAuth::Basic => "basic",
+        crate::config::HttpAuth::Bearer => "bearer",
+        crate::config::HttpAuth::ApiKey => "api_key",
+    };
+    let tls_roots = match c.mqtt.tls_roots {
+        crate::config::TlsRoots::System => "system",
+        crate::config::TlsRoots::Custom => "custom",
+        crate::config::TlsRoots::Both => "both",
     };
     use crate::web_api::json_escape as esc;
-    let json = format!(
-        r#"{{"general":{{"device_name":"{}","interval_secs":{},"wrap_json":{},"data_as_text":{}}},"mqtt":{{"enabled":{},"broker":"{}","port":{},"tls":{},"topic":"{}","sub_topic":"{}","username":"{}","password":"{}","qos":{}}},"http":{{"enabled":{},"url":"{}","method":"{}"}},"tcp":{{"enabled":{},"mode":"{}","server_port":{},"client_host":"{}","client_port":{}}},"uart":{{"enabled":{},"baudrate":{},"data_bits":{},"parity":"{}","stop_bits":{},"frame_mode":"{}","frame_length":{},"frame_timeout_ms":{},"gap_ms":{}}},"web":{{"port":{}}}}}"#,
-        esc(&c.general.device_name), c.general.interval_secs, c.general.wrap_json, c.general.data_as_text,
-        c.mqtt.enabled, esc(&c.mqtt.broker), c.mqtt.port, c.mqtt.tls,
-        esc(&c.mqtt.topic), esc(&c.mqtt.sub_topic), esc(&c.mqtt.username), esc(&c.mqtt.password), c.mqtt.qos,
-        c.http.enabled, esc(&c.http.url), http_method,
-        c.tcp.enabled, tcp_mode, c.tcp.server_port, esc(&c.tcp.client_host), c.tcp.client_port,
-        c.uart.enabled, c.uart.baudrate, c.uart.data_bits, parity, c.uart.stop_bits, frame_mode,
-        c.uart.frame_length, c.uart.frame_timeout_ms, c.uart.gap_ms,
-        c.web.port,
-    );
-    tiny_http::Response::from_string(json).with_header(content_type_json())
+    let json = match section {
+        "general" => format!(
+            r#"{{"device_name":"{}","interval_secs":{},"wrap_json":{},"data_as_text":{}}}"#,
+            esc(&c.general.device_name), c.general.interval_secs, c.general.wrap_json, c.general.data_as_text,
+        ),
+        "mqtt" => format!(
+            r#"{{"enabled":{},"broker":"{}","port":{},"tls":{},"topic":"{}","sub_topic":"{}","username":"{}","password":"{}","qos":{},"tls_roots":"{}","tls_fingerprint":"{}","protocol_version":"{}","status_topic":"{}","client_id":"{}","clean_session":{},"keep_alive_secs":{},"inflight":{},"message_expiry_secs":{},"user_properties":"{}"}}"#,
+            c.mqtt.enabled, esc(&c.mqtt.broker), c.mqtt.port, c.mqtt.tls,
+            esc(&c.mqtt.topic), esc(&c.mqtt.sub_topic), esc(&c.mqtt.username), mask(&c.mqtt.password), c.mqtt.qos,
+            tls_roots, esc(&c.mqtt.tls_fingerprint),
+            if c.mqtt.version == crate::config::MqttVersion::V5 { "5" } else { "3.1.1" },
+            esc(&c.mqtt.status_topic), esc(&c.mqtt.client_id), c.mqtt.clean_session, c.mqtt.keep_alive_secs,
+            c.mqtt.inflight, c.mqtt.message_expiry_secs, esc(&crate::config::format_pairs(&c.mqtt.user_properties)),
+        ),
+        "http" => format!(
+            r#"{{"enabled":{},"url":"{}","method":"{}","ordered":{},"telemetry":{},"headers":"{}","auth":"{}","username":"{}","password":"{}","token":"{}","api_key_header":"{}","body_template":"{}","batch_max_items":{},"batch_max_bytes":{},"batch_max_ms":{},"batch_format":"{}","gzip":{},"max_concurrent":{},"sign_secret":"{}","verify_response":{},"poll_url":"{}","poll_interval_secs":{},"poll_timeout_secs":{},"poll_ack_url":"{}"}}"#,
+            c.http.enabled, esc(&c.http.url), http_method, c.http.ordered, c.http.telemetry,
+            esc(&crate::config::format_pairs(&c.http.headers)), http_auth, esc(&c.http.username), mask(&c.http.password),
+            mask(&c.http.token), esc(&c.http.api_key_header), esc(&c.http.body_template),
+            c.http.batch_max_items, c.http.batch_max_bytes, c.http.batch_max_ms,
+            if c.http.batch_format == crate::config::BatchFormat::Ndjson { "ndjson" } else { "json" },
+            c.http.gzip, c.http.max_concurrent, mask(&c.http.sign_secret), c.http.verify_response,
+            esc(&c.http.poll_url), c.http.poll_interval_secs, c.http.poll_timeout_secs, esc(&c.http.poll_ack_url),
+        ),
+        "tcp" => format!(
+            r#"{{"enabled":{},"mode":"{}","server_port":{},"client_host":"{}","client_port":{}}}"#,
+            c.tcp.enabled, tcp_mode, c.tcp.server_port, esc(&c.tcp.client_host), c.tcp.client_port,
+        ),
+        "uart" => format!(
+            r#"{{"enabled":{},"baudrate":{},"data_bits":{},"parity":"{}","stop_bits":{},"frame_mode":"{}","frame_length":{},"frame_timeout_ms":{},"gap_ms":{}}}"#,
+            c.uart.enabled, c.uart.baudrate, c.uart.data_bits, parity, c.uart.stop_bits, frame_mode,
+            c.uart.frame_length, c.uart.frame_timeout_ms, c.uart.gap_ms,
+        ),
+        "web" => format!(r#"{{"port":{}}}"#, c.web.port),
+        _ => return None,
+    };
+    Some(json)
 }
 
 fn handle_set_config(
@@ -367,40 +466,38 @@ fn handle_set_config(
     state: &AppState,
 ) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
     let body = read_body(request);
-    log::info!("[HTTP] Config update: {}", &body[..body.len().min(300)]);
+    // Không log body: chứa mật khẩu/token
+    let sections: Vec<&str> = CONFIG_SECTIONS.iter()
+        .copied()
+        .filter(|s| crate::web_api::json_object(&body, s).is_some())
+        .collect();
+    log::info!("[HTTP] Config update: {:?}", sections);
 
     let mut cfg = state.get();
+    apply_config_json(&mut cfg, &body, &CONFIG_SECTIONS);
+
+    // Lưu UCI và cập nhật state (thông báo tới MQTT/UART reconnect)
+    cfg.save_to_uci();
+    state.update(cfg);
+
+    tiny_http::Response::from_string(r#"{"ok":true}"#).with_header(content_type_json())
+}
 
-    // Tách section từ nested JSON: {"mqtt":{...},"http":{...},...}
-    // Tìm nội dung bên trong mỗi section object
+/// Áp JSON dạng {"mqtt":{...},"uart":{...}} vào cfg, chỉ xét section trong `sections`
+/// (trường vắng mặt giữ nguyên). Dùng chung cho POST /api/config và shadow
+pub(crate) fn apply_config_json(cfg: &mut crate::config::Config, body: &str, sections: &[&str]) {
     let section_body = |section: &str| -> Option<String> {
-        let pat = format!("\"{}\":{{", section);
-        body.find(&pat).and_then(|pos| {
-            let start = pos + pat.len() - 1; // bao gồm '{'
-            let mut depth = 0;
-            for (i, c) in body[start..].char_indices() {
-                match c {
-                    '{' => depth += 1,
-                    '}' => { depth -= 1; if depth == 0 { return Some(body[start..start + i + 1].to_string()); } }
-                    _ => {}
-                }
-            }
-            None
-        })
+        if !sections.contains(&section) {
+            return None;
+        }
+        crate::web_api::json_object(body, section).map(str::to_string)
     };
 
-    // Trích giá trị từ JSON fragment
-    fn jval(json: &str, key: &str) -> Option<String> {
-        let pat = format!("\"{}\":", key);
-        json.find(&pat).and_then(|pos| {
-            let rest = json[pos + pat.len()..].trim_start();
-            if rest.starts_with('"') {
-                rest[1..].find('"').map(|end| rest[1..1 + end].to_string())
-            } else {
-                let end = rest.find(|c: char| c == ',' || c == '}').unwrap_or(rest.len());
-                Some(rest[..end].trim().to_string())
-            }
-        })
+    // Trích giá trị từ JSON fragment (chuỗi được giải escape: template, password có ngoặc kép)
+    use crate::web_api::jstr as jval;
+    // Mật khẩu/token: nhận lại SECRET_MASK từ GET → giữ giá trị đang lưu
+    fn secret(json: &str, key: &str) -> Option<String> {
+        jval(json, key).filter(|v| v != SECRET_MASK)
     }
     fn jbool(json: &str, key: &str) -> Option<bool> {
         jval(json, key).map(|v| v == "true" || v == "1")
@@ -423,8 +520,32 @@ fn handle_set_config(
         if let Some(v) = jval(&s, "topic") { cfg.mqtt.topic = v; }
         if let Some(v) = jval(&s, "sub_topic") { cfg.mqtt.sub_topic = v; }
         if let Some(v) = jval(&s, "username") { cfg.mqtt.username = v; }
-        if let Some(v) = jval(&s, "password") { cfg.mqtt.password = v; }
+        if let Some(v) = secret(&s, "password") { cfg.mqtt.password = v; }
         if let Some(v) = jval(&s, "qos").and_then(|v| v.parse().ok()) { cfg.mqtt.qos = v; }
+        if let Some(v) = jval(&s, "tls_roots") {
+            cfg.mqtt.tls_roots = match v.as_str() {
+                "custom" => crate::config::TlsRoots::Custom,
+                "both" => crate::config::TlsRoots::Both,
+                _ => crate::config::TlsRoots::System,
+            };
+        }
+        if let Some(v) = jval(&s, "tls_fingerprint") {
+            if v.is_empty() || crate::channels::tls::parse_fingerprint(&v).is_some() {
+                cfg.mqtt.tls_fingerprint = v;
+            } else {
+                log::warn!("[HTTP] tls_fingerprint không hợp lệ (cần SHA-256 hex), giữ giá trị cũ");
+            }
+        }
+        if let Some(v) = jval(&s, "protocol_version") {
+            cfg.mqtt.version = if v == "5" { crate::config::MqttVersion::V5 } else { crate::config::MqttVersion::V311 };
+        }
+        if let Some(v) = jval(&s, "status_topic") { cfg.mqtt.status_topic = v; }
+        if let Some(v) = jval(&s, "client_id") { cfg.mqtt.client_id = v; }
+        if let Some(v) = jbool(&s, "clean_session") { cfg.mqtt.clean_session = v; }
+        if let Some(v) = jval(&s, "keep_alive_secs").and_then(|v| v.parse::<u16>().ok()) { cfg.mqtt.keep_alive_secs = v.max(5); }
+        if let Some(v) = jval(&s, "inflight").and_then(|v| v.parse::<u16>().ok()) { cfg.mqtt.inflight = v.clamp(1, 100); }
+        if let Some(v) = jval(&s, "message_expiry_secs").and_then(|v| v.parse().ok()) { cfg.mqtt.message_expiry_secs = v; }
+        if let Some(v) = jval(&s, "user_properties") { cfg.mqtt.user_properties = crate::config::parse_pairs(&v); }
     }
 
     // HTTP
@@ -432,8 +553,43 @@ fn handle_set_config(
         if let Some(v) = jbool(&s, "enabled") { cfg.http.enabled = v; }
         if let Some(v) = jval(&s, "url") { cfg.http.url = v; }
         if let Some(v) = jval(&s, "method") {
-            cfg.http.method = if v == "get" { crate::config::HttpMethod::Get } else { crate::config::HttpMethod::Post };
+            cfg.http.method = match v.as_str() {
+                "get" => crate::config::HttpMethod::Get,
+                "put" => crate::config::HttpMethod::Put,
+                "patch" => crate::config::HttpMethod::Patch,
+                _ => crate::config::HttpMethod::Post,
+            };
+        }
+        if let Some(v) = jbool(&s, "ordered") { cfg.http.ordered = v; }
+        if let Some(v) = jbool(&s, "telemetry") { cfg.http.telemetry = v; }
+        if let Some(v) = jval(&s, "headers") { cfg.http.headers = crate::config::parse_pairs(&v); }
+        if let Some(v) = jval(&s, "auth") {
+            cfg.http.auth = match v.as_str() {
+                "basic" => crate::config::HttpAuth::Basic,
+                "bearer" => crate::config::HttpAuth::Bearer,
+                "api_key" => crate::config::HttpAuth::ApiKey,
+                _ => crate::config::HttpAuth::None,
+            };
         }
+        if let Some(v) = jval(&s, "username") { cfg.http.username = v; }
+        if let Some(v) = secret(&s, "password") { cfg.http.password = v; }
+        if let Some(v) = secret(&s, "token") { cfg.http.token = v; }
+        if let Some(v) = jval(&s, "api_key_header") { cfg.http.api_key_header = v; }
+        if let Some(v) = jval(&s, "body_template") { cfg.http.body_template = v; }
+        if let Some(v) = jval(&s, "batch_max_items").and_then(|v| v.parse::<u32>().ok()) { cfg.http.batch_max_items = v.clamp(1, 1000); }
+        if let Some(v) = jval(&s, "batch_max_bytes").and_then(|v| v.parse::<u32>().ok()) { cfg.http.batch_max_bytes = v.max(256); }
+        if let Some(v) = jval(&s, "batch_max_ms").and_then(|v| v.parse().ok()) { cfg.http.batch_max_ms = v; }
+        if let Some(v) = jval(&s, "batch_format") {
+            cfg.http.batch_format = if v == "ndjson" { crate::config::BatchFormat::Ndjson } else { crate::config::BatchFormat::Json };
+        }
+        if let Some(v) = jbool(&s, "gzip") { cfg.http.gzip = v; }
+        if let Some(v) = jval(&s, "max_concurrent").and_then(|v| v.parse::<u8>().ok()) { cfg.http.max_concurrent = v.clamp(1, 16); }
+        if let Some(v) = secret(&s, "sign_secret") { cfg.http.sign_secret = v; }
+        if let Some(v) = jbool(&s, "verify_response") { cfg.http.verify_response = v; }
+        if let Some(v) = jval(&s, "poll_url") { cfg.http.poll_url = v; }
+        if let Some(v) = jval(&s, "poll_interval_secs").and_then(|v| v.parse().ok()) { cfg.http.poll_interval_secs = v; }
+        if let Some(v) = jval(&s, "poll_timeout_secs").and_then(|v| v.parse::<u32>().ok()) { cfg.http.poll_timeout_secs = v.clamp(5, 600); }
+        if let Some(v) = jval(&s, "poll_ack_url") { cfg.http.poll_ack_url = v; }
     }
 
     // TCP
@@ -474,12 +630,6 @@ fn handle_set_config(
         if let Some(v) = jval(&s, "frame_timeout_ms").and_then(|v| v.parse().ok()) { cfg.uart.frame_timeout_ms = v; }
         if let Some(v) = jval(&s, "gap_ms").and_then(|v| v.parse().ok()) { cfg.uart.gap_ms = v; }
     }
-
-    // Lưu UCI và cập nhật state (thông báo tới MQTT/UART reconnect)
-    cfg.save_to_uci();
-    state.update(cfg);
-
-    tiny_http::Response::from_string(r#"{"ok":true}"#).with_header(content_type_json())
 }
 
 fn handle_get_status(_state: &AppState) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
@@ -488,6 +638,14 @@ fn handle_get_status(_state: &AppState) -> tiny_http::Response<std::io::Cursor<V
         .with_header(content_type_json())
 }
 
+/// Trạng thái subsystem từ supervisor — 503 nếu có subsystem dừng/kẹt (cho monitor bên ngoài)
+fn handle_get_health(stats: &SharedStats) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+    let (json, ok) = stats.health.to_json(stats);
+    tiny_http::Response::from_string(json)
+        .with_status_code(if ok { 200 } else { 503 })
+        .with_header(content_type_json())
+}
+
 fn handle_gpio(
     _request: &mut tiny_http::Request,
     path: &str,
@@ -525,6 +683,32 @@ fn handle_gpio(
     tiny_http::Response::from_string(r#"{"ok":true}"#).with_header(content_type_json())
 }
 
+/// POST /api/pwm/{ch} — body {"duty":40,"period_ns":1000000} (period_ns tuỳ chọn)
+fn handle_pwm(
+    body: &str,
+    path: &str,
+    ws_manager: &WsManager,
+) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+    let ch: u8 = match path.trim_start_matches("/api/pwm/").parse() {
+        Ok(c) if (c as usize) < crate::pwm::PWM_MAX_CHANNELS => c,
+        _ => return crate::web_api::json_err(400, "invalid channel"),
+    };
+    let duty: u8 = match crate::web_api::jval(body, "duty").and_then(|v| v.parse().ok()) {
+        Some(d) if d <= 100 => d,
+        _ => return crate::web_api::json_err(400, "duty must be 0-100"),
+    };
+    let period_ns = match crate::web_api::jval(body, "period_ns") {
+        Some(v) => match v.parse::<u32>() {
+            Ok(p) if p > 0 => Some(p),
+            _ => return crate::web_api::json_err(400, "invalid period_ns"),
+        },
+        None => None,
+    };
+
+    let _ = ws_manager.cmd_tx.send(Command::Pwm { ch, duty, period_ns });
+    crate::web_api::json_resp(r#"{"ok":true}"#)
+}
+
 fn handle_change_password(
     request: &mut tiny_http::Request,
     state: &AppState,
@@ -591,3 +775,24 @@ fn content_type_css() -> tiny_http::Header {
 fn content_type_js() -> tiny_http::Header {
     tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"application/javascript; charset=utf-8"[..]).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_secrets_masked() {
+        let mut cfg = crate::config::Config::default();
+        cfg.mqtt.password = "mq-pass".into();
+        cfg.http.token = "tk-123".into();
+        let http = section_json(&cfg, "http").unwrap();
+        assert!(http.contains(r#""token":"***""#) && http.contains(r#""password":"""#));
+        assert!(!section_json(&cfg, "mqtt").unwrap().contains("mq-pass"));
+
+        // Gửi lại mask → giữ giá trị cũ; giá trị mới → ghi đè
+        let body = format!(r#"{{"http":{},"mqtt":{{"password":"new"}}}}"#, http);
+        apply_config_json(&mut cfg, &body, &CONFIG_SECTIONS);
+        assert_eq!(cfg.http.token, "tk-123");
+        assert_eq!(cfg.mqtt.password, "new");
+    }
+}
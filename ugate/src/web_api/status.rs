@@ -11,54 +11,206 @@ struct CpuSnapshot {
     total: u64,
 }
 
+/// Bộ đếm của 1 kết nối MQTT (mỗi broker 1 bộ)
+pub struct MqttStats {
+    /// Client ID hiện tại (set bởi MQTT thread mỗi lần connect)
+    pub client_id: Mutex<String>,
+    pub published: AtomicU32,
+    pub failed: AtomicU32,
+    /// Message đang chờ gửi trong offline buffer (RAM + disk) + đã bỏ do quá hạn/đầy
+    pub buffered: AtomicU32,
+    pub dropped: AtomicU32,
+    pub state: AtomicU8, // 0=disabled, 1=disconnected, 2=connected
+    /// Lỗi kết nối / reason code gần nhất từ broker (MQTT 5 DISCONNECT, PubAck lỗi)
+    pub last_reason: Mutex<String>,
+}
+
+impl MqttStats {
+    fn new() -> Self {
+        Self {
+            client_id: Mutex::new(String::new()),
+            published: AtomicU32::new(0),
+            failed: AtomicU32::new(0),
+            buffered: AtomicU32::new(0),
+            dropped: AtomicU32::new(0),
+            state: AtomicU8::new(0),
+            last_reason: Mutex::new(String::new()),
+        }
+    }
+
+    fn to_json(&self, enabled: bool) -> String {
+        format!(
+            r#""enabled":{},"state":"{}","client_id":"{}","published":{},"failed":{},"buffered":{},"dropped":{},"last_reason":"{}""#,
+            enabled,
+            state_str(self.state.load(Ordering::Relaxed)),
+            crate::web_api::json_escape(&self.client_id.lock().unwrap()),
+            self.published.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed),
+            self.buffered.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+            crate::web_api::json_escape(&self.last_reason.lock().unwrap()),
+        )
+    }
+}
+
 /// Bộ đếm atomic chia sẻ giữa UART, MQTT, TCP, GPIO tasks
 pub struct SharedStats {
     cpu_prev: Mutex<Option<CpuSnapshot>>,
-    /// MQTT client ID hiện tại (set bởi MQTT thread mỗi lần connect)
-    pub mqtt_client_id: Mutex<String>,
     pub uart_rx_bytes: AtomicU32,
     pub uart_rx_frames: AtomicU32,
     pub uart_tx_bytes: AtomicU32,
     pub uart_tx_frames: AtomicU32,
     pub uart_failed: AtomicU32,
-    pub mqtt_published: AtomicU32,
-    pub mqtt_failed: AtomicU32,
-    pub mqtt_state: AtomicU8, // 0=disabled, 1=disconnected, 2=connected
+    pub uart_state: AtomicU8, // 0=disabled, 1=chưa mở được port, 2=đang mở
+    /// [0] = section `mqtt` chính, [1..] = các section `mqtt_broker` theo thứ tự
+    pub mqtt: [MqttStats; crate::channels::mqtt::MAX_BROKERS],
     pub tcp_connections: AtomicU8,
-    pub tcp_state: AtomicU8, // 0=disabled, 1=disconnected, 2=connected
+    pub tcp_state: AtomicU8, // 0=disabled, 1=disconnected, 2=connected, 3=listening (server chờ client)
     pub http_state: AtomicU8, // 0=disabled, 1=active, 2=error
     pub http_sent: AtomicU32,
     pub http_failed: AtomicU32,
+    pub http_buffered: AtomicU32,
+    pub http_dropped: AtomicU32,
     pub gpio_states: [AtomicU8; 4],
+    /// Mức GPIO input (gpio.inputs): 0=thấp, 1=cao
+    pub gpio_inputs: [AtomicU8; 4],
+    /// Duty hiện tại (0-100%) + period của từng kênh PWM
+    pub pwm_duty: [AtomicU8; 4],
+    pub pwm_period_ns: [AtomicU32; 4],
+    /// Nút reset: 0=không nhấn, 1=nhấn ngắn, 2=đủ giữ bật AP, 3=đủ giữ factory reset
+    pub button_stage: AtomicU8,
+    /// Nhịp báo tiến độ của các task quan trọng (watchdog + supervisor)
+    pub tasks: crate::watchdog::TaskBeats,
+    pub watchdog_state: AtomicU8, // 0=disabled, 1=armed, 2=starving, 3=disarmed
+    /// Tên task kẹt khiến watchdog ngừng pet (rỗng nếu bình thường)
+    pub watchdog_stale: Mutex<String>,
+    /// Trạng thái + số lần restart của các subsystem (supervisor)
+    pub health: crate::supervisor::Health,
 }
 
 impl SharedStats {
     pub fn new() -> Self {
         Self {
             cpu_prev: Mutex::new(None),
-            mqtt_client_id: Mutex::new(String::new()),
             uart_rx_bytes: AtomicU32::new(0),
             uart_rx_frames: AtomicU32::new(0),
             uart_tx_bytes: AtomicU32::new(0),
             uart_tx_frames: AtomicU32::new(0),
             uart_failed: AtomicU32::new(0),
-            mqtt_published: AtomicU32::new(0),
-            mqtt_failed: AtomicU32::new(0),
-            mqtt_state: AtomicU8::new(0),
+            uart_state: AtomicU8::new(0),
+            mqtt: std::array::from_fn(|_| MqttStats::new()),
             tcp_connections: AtomicU8::new(0),
             tcp_state: AtomicU8::new(0),
             http_state: AtomicU8::new(0),
             http_sent: AtomicU32::new(0),
             http_failed: AtomicU32::new(0),
+            http_buffered: AtomicU32::new(0),
+            http_dropped: AtomicU32::new(0),
             gpio_states: [
                 AtomicU8::new(0),
                 AtomicU8::new(0),
                 AtomicU8::new(0),
                 AtomicU8::new(0),
             ],
+            gpio_inputs: [
+                AtomicU8::new(0),
+                AtomicU8::new(0),
+                AtomicU8::new(0),
+                AtomicU8::new(0),
+            ],
+            pwm_duty: [
+                AtomicU8::new(0),
+                AtomicU8::new(0),
+                AtomicU8::new(0),
+                AtomicU8::new(0),
+            ],
+            pwm_period_ns: [
+                AtomicU32::new(0),
+                AtomicU32::new(0),
+                AtomicU32::new(0),
+                AtomicU32::new(0),
+            ],
+            button_stage: AtomicU8::new(0),
+            tasks: crate::watchdog::TaskBeats::new(),
+            watchdog_state: AtomicU8::new(0),
+            watchdog_stale: Mutex::new(String::new()),
+            health: crate::supervisor::Health::new(),
         }
     }
 
+    /// JSON array trạng thái các kênh PWM đã cấu hình: [{"ch":0,"duty":40,"period_ns":1000000}]
+    pub fn pwm_json(&self, config: &crate::config::Config) -> String {
+        let items: Vec<String> = config.pwm.channels.iter()
+            .filter(|c| (c.channel as usize) < self.pwm_duty.len())
+            .map(|c| {
+                let idx = c.channel as usize;
+                format!(
+                    r#"{{"ch":{},"duty":{},"period_ns":{}}}"#,
+                    c.channel,
+                    self.pwm_duty[idx].load(Ordering::Relaxed),
+                    self.pwm_period_ns[idx].load(Ordering::Relaxed),
+                )
+            })
+            .collect();
+        format!("[{}]", items.join(","))
+    }
+
+    /// Mức các GPIO input đã cấu hình: [true,false]
+    fn inputs_json(&self, config: &crate::config::Config) -> String {
+        let items: Vec<String> = self.gpio_inputs.iter()
+            .take(config.gpio.inputs.len())
+            .map(|v| (v.load(Ordering::Relaxed) != 0).to_string())
+            .collect();
+        format!("[{}]", items.join(","))
+    }
+
+    /// State cho Home Assistant (1 topic, các entity đọc qua value_template):
+    /// {"cpu":12,"ram_used":31,"uptime":3600,"uart_rx_frames":..,"gpio1":"ON","input1":"OFF",...}
+    pub fn to_ha_state_json(&self, config: &crate::config::Config) -> String {
+        let (cpu, ram_used, uptime) = self.system_metrics();
+        let on_off = |v: &AtomicU8| if v.load(Ordering::Relaxed) != 0 { "ON" } else { "OFF" };
+        let mut json = format!(
+            r#"{{"cpu":{},"ram_used":{},"uptime":{},"uart_rx_frames":{},"uart_tx_frames":{},"uart_rx_bytes":{},"uart_failed":{}"#,
+            cpu,
+            ram_used,
+            uptime,
+            self.uart_rx_frames.load(Ordering::Relaxed),
+            self.uart_tx_frames.load(Ordering::Relaxed),
+            self.uart_rx_bytes.load(Ordering::Relaxed),
+            self.uart_failed.load(Ordering::Relaxed),
+        );
+        for (i, v) in self.gpio_states.iter().take(config.gpio.pins.len()).enumerate() {
+            json.push_str(&format!(r#","gpio{}":"{}""#, i + 1, on_off(v)));
+        }
+        for (i, v) in self.gpio_inputs.iter().take(config.gpio.inputs.len()).enumerate() {
+            json.push_str(&format!(r#","input{}":"{}""#, i + 1, on_off(v)));
+        }
+        json.push('}');
+        json
+    }
+
+    /// (CPU %, RAM đã dùng MB, uptime giây)
+    pub fn system_metrics(&self) -> (u8, u16, u64) {
+        let (ram_used, _) = read_mem_info();
+        (self.read_cpu_percent(), ram_used, read_uptime_secs().unwrap_or(0.0) as u64)
+    }
+
+    /// Dấu vân tay mức GPIO output + input: đổi → publish lại state Home Assistant ngay
+    pub fn gpio_signature(&self) -> u8 {
+        self.gpio_states.iter().chain(self.gpio_inputs.iter())
+            .enumerate()
+            .fold(0, |acc, (i, v)| acc | (((v.load(Ordering::Relaxed) != 0) as u8) << i))
+    }
+
+    /// Các broker phụ: [{"name":"cloud2","enabled":true,"state":"connected",...}]
+    fn mqtt_brokers_json(&self, config: &crate::config::Config) -> String {
+        let items: Vec<String> = config.mqtt_brokers.iter()
+            .zip(self.mqtt.iter().skip(1))
+            .map(|(b, s)| format!(r#"{{"name":"{}",{}}}"#, crate::web_api::json_escape(&b.name), s.to_json(b.enabled)))
+            .collect();
+        items.join(",")
+    }
+
     /// Tính CPU% từ delta /proc/stat giữa 2 lần gọi (giống top)
     fn read_cpu_percent(&self) -> u8 {
         let cur = match read_proc_stat() {
@@ -89,7 +241,7 @@ impl SharedStats {
         let cpu = self.read_cpu_percent();
 
         format!(
-            r#"{{"type":"status","version":"{}","uptime":"{}","datetime":"{}","cpu":{},"ram_used":{},"ram_total":{},"uart":{{"rx_bytes":{},"rx_frames":{},"tx_bytes":{},"tx_frames":{},"failed":{},"config":"{} 8N1"}},"mqtt":{{"enabled":{},"state":"{}","client_id":"{}","published":{},"failed":{}}},"http":{{"enabled":{},"state":"{}","sent":{},"failed":{}}},"tcp":{{"enabled":{},"state":"{}","connections":{}}},"gpio":[{},{},{},{}]}}"#,
+            r#"{{"type":"status","version":"{}","uptime":"{}","datetime":"{}","cpu":{},"ram_used":{},"ram_total":{},"uart":{{"rx_bytes":{},"rx_frames":{},"tx_bytes":{},"tx_frames":{},"failed":{},"state":"{}","config":"{} 8N1"}},"mqtt":{{{}}},"mqtt_brokers":[{}],"http":{{"enabled":{},"state":"{}","sent":{},"failed":{},"buffered":{},"dropped":{}}},"tcp":{{"enabled":{},"state":"{}","connections":{}}},"gpio":[{},{},{},{}],"inputs":{},"pwm":{},"watchdog":{{"enabled":{},"state":"{}","timeout":{},"stale_task":"{}"}}}}"#,
             env!("CARGO_PKG_VERSION"),
             uptime,
             datetime,
@@ -101,16 +253,16 @@ impl SharedStats {
             self.uart_tx_bytes.load(Ordering::Relaxed),
             self.uart_tx_frames.load(Ordering::Relaxed),
             self.uart_failed.load(Ordering::Relaxed),
+            state_str(self.uart_state.load(Ordering::Relaxed)),
             config.uart.baudrate,
-            config.mqtt.enabled,
-            state_str(self.mqtt_state.load(Ordering::Relaxed)),
-            self.mqtt_client_id.lock().unwrap(),
-            self.mqtt_published.load(Ordering::Relaxed),
-            self.mqtt_failed.load(Ordering::Relaxed),
+            self.mqtt[0].to_json(config.mqtt.enabled),
+            self.mqtt_brokers_json(config),
             config.http.enabled,
             state_str(self.http_state.load(Ordering::Relaxed)),
             self.http_sent.load(Ordering::Relaxed),
             self.http_failed.load(Ordering::Relaxed),
+            self.http_buffered.load(Ordering::Relaxed),
+            self.http_dropped.load(Ordering::Relaxed),
             config.tcp.enabled,
             state_str(self.tcp_state.load(Ordering::Relaxed)),
             self.tcp_connections.load(Ordering::Relaxed),
@@ -118,8 +270,48 @@ impl SharedStats {
             self.gpio_states[1].load(Ordering::Relaxed) != 0,
             self.gpio_states[2].load(Ordering::Relaxed) != 0,
             self.gpio_states[3].load(Ordering::Relaxed) != 0,
+            self.inputs_json(config),
+            self.pwm_json(config),
+            config.watchdog.enabled,
+            crate::watchdog::state_str(self.watchdog_state.load(Ordering::Relaxed)),
+            config.watchdog.timeout_secs,
+            self.watchdog_stale.lock().unwrap(),
         )
     }
+
+    /// Telemetry định kỳ: status đầy đủ + RSSI WiFi + IP nguồn
+    /// {"type":"telemetry","device_name":"..","timestamp":..,"rssi":-62,"ip":"..","status":{...}}
+    pub fn to_telemetry_json(&self, config: &crate::config::Config) -> String {
+        let ts = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        format!(
+            r#"{{"type":"telemetry","device_name":"{}","timestamp":{},"rssi":{},"ip":"{}","status":{}}}"#,
+            crate::web_api::json_escape(&config.general.device_name),
+            ts,
+            read_rssi().map(|r| r.to_string()).unwrap_or_else(|| "null".into()),
+            // IP nguồn của route mặc định (UDP connect không gửi gói nào) — địa chỉ LAN khi
+            // đứng sau NAT, không phải IP public của WAN
+            crate::channels::mqtt::local_ip("8.8.8.8", 53),
+            self.to_status_json(config),
+        )
+    }
+}
+
+/// RSSI (dBm) của interface WiFi client đầu tiên trong /proc/net/wireless
+/// Dòng dạng: " phy0-sta0: 0000   45.  -62.  -256        0 ..."
+fn read_rssi() -> Option<i32> {
+    let content = std::fs::read_to_string("/proc/net/wireless").ok()?;
+    parse_wireless(&content)
+}
+
+fn parse_wireless(content: &str) -> Option<i32> {
+    content.lines().skip(2).find_map(|line| {
+        let (_, fields) = line.split_once(':')?;
+        let level = fields.split_whitespace().nth(2)?;
+        level.trim_end_matches('.').parse().ok()
+    })
 }
 
 fn state_str(s: u8) -> &'static str {
@@ -127,6 +319,7 @@ fn state_str(s: u8) -> &'static str {
         0 => "disabled",
         1 => "waiting",
         2 => "connected",
+        3 => "listening",
         _ => "unknown",
     }
 }
@@ -144,12 +337,14 @@ fn read_datetime() -> String {
 
 /// Đọc uptime từ /proc/uptime
 fn read_uptime() -> String {
+    read_uptime_secs().map(format_uptime).unwrap_or_else(|| "0m".into())
+}
+
+fn read_uptime_secs() -> Option<f64> {
     std::fs::read_to_string("/proc/uptime")
         .ok()
         .and_then(|s| s.split_whitespace().next().map(String::from))
         .and_then(|s| s.parse::<f64>().ok())
-        .map(format_uptime)
-        .unwrap_or_else(|| "0m".into())
 }
 
 fn format_uptime(secs: f64) -> String {
@@ -208,3 +403,19 @@ fn read_proc_stat() -> Option<CpuSnapshot> {
     let total: u64 = vals.iter().sum();
     Some(CpuSnapshot { idle, total })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_wireless() {
+        let header = "Inter-| sta-|   Quality        |   Discarded packets               | Missed | WE\n \
+                      face | tstus | link level noise |  nwid  crypt   frag  retry   misc | beacon | 22\n";
+        let sample = format!("{}phy0-sta0: 0000   45.  -62.  -256        0      0      0      0      0        0\n", header);
+        assert_eq!(parse_wireless(&sample), Some(-62));
+        // Không có WiFi client → chỉ có header
+        assert_eq!(parse_wireless(header), None);
+        assert_eq!(parse_wireless(""), None);
+    }
+}
@@ -154,6 +154,17 @@ pub fn handle_disconnect() -> Resp {
     json_resp(r#"{"ok":true,"draft":true}"#)
 }
 
+/// Bật AP fallback (giữ nguyên STA) và apply ngay — dùng khi giữ nút reset
+/// Cho phép kỹ thuật viên kết nối trực tiếp vào Web UI khi mất mạng STA
+pub fn enable_ap_fallback() {
+    Uci::set("wireless.default_radio0.disabled", "0").ok();
+    Uci::commit("wireless").ok();
+    log::info!("[WiFi] Bật AP fallback '{}'", Uci::get("wireless.default_radio0.ssid").unwrap_or_default());
+    std::thread::spawn(|| {
+        Command::new("wifi").arg("reload").status().ok();
+    });
+}
+
 // --- helpers ---
 
 fn set_sta_config(body: &str) {